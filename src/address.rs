@@ -0,0 +1,161 @@
+use std::str::FromStr;
+
+use regex::Regex;
+
+/// One end of an address range, either a line number (one-based) or a pattern a line must match.
+/// An open end (`10..`) is `Last`, which keeps the range open until the input runs out.
+#[derive(Clone)]
+enum Bound {
+    Line(usize),
+    Pattern(Regex),
+    Last,
+}
+
+impl Bound {
+    fn matches(&self, number: usize, line: &str) -> bool {
+        match self {
+            Bound::Line(n) => number == *n,
+            Bound::Pattern(re) => re.is_match(line),
+            Bound::Last => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Range {
+    start: Bound,
+    end: Option<Bound>,
+    active: bool,
+}
+
+/// A set of sed-style line addresses such as `10-20,45`, `100..` or `/BEGIN/../END/`.
+///
+/// Pattern ranges are stateful: a range opens on the first line matching its start and closes
+/// (inclusively) on the next line matching its end, so lines have to be fed in order.
+#[derive(Clone)]
+pub struct Addresses {
+    ranges: Vec<Range>,
+}
+
+impl Addresses {
+    pub fn select(&mut self, number: usize, line: &str) -> bool {
+        // every range has to see every line to keep its open/closed state correct
+        let mut selected = false;
+        for range in &mut self.ranges {
+            selected |= range.select(number, line);
+        }
+        selected
+    }
+}
+
+impl Range {
+    fn select(&mut self, number: usize, line: &str) -> bool {
+        let Some(end) = &self.end else {
+            return self.start.matches(number, line);
+        };
+
+        if !self.active {
+            if !self.start.matches(number, line) {
+                return false;
+            }
+            // like sed, an end line at or before the start line selects only the start line
+            self.active = !matches!(end, Bound::Line(n) if *n <= number);
+            return true;
+        }
+
+        if match end {
+            Bound::Line(n) => number >= *n,
+            Bound::Pattern(re) => re.is_match(line),
+            Bound::Last => false,
+        } {
+            self.active = false;
+        }
+        true
+    }
+}
+
+impl FromStr for Addresses {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = Vec::new();
+        let mut rest = s;
+
+        while !rest.is_empty() {
+            // an open start (`..50`) begins at the first line
+            let (start, after) = match rest.starts_with("..") {
+                true => (Bound::Line(1), rest),
+                false => parse_bound(rest)?,
+            };
+            let (end, after) = if let Some(after) = after.strip_prefix("..") {
+                let (end, after) = parse_end(after)?;
+                (Some(end), after)
+            } else if let (Bound::Line(_), Some(after)) = (&start, after.strip_prefix('-')) {
+                match parse_end(after)? {
+                    (end @ (Bound::Line(_) | Bound::Last), after) => (Some(end), after),
+                    _ => return Err(format!("expected a line number after '-' in '{s}'")),
+                }
+            } else {
+                (None, after)
+            };
+
+            ranges.push(Range {
+                start,
+                end,
+                active: false,
+            });
+
+            rest = match after.strip_prefix(',') {
+                Some(after) => after,
+                None if after.is_empty() => after,
+                None => return Err(format!("unexpected '{after}' in address '{s}'")),
+            };
+        }
+
+        if ranges.is_empty() {
+            return Err("no addresses given".to_string());
+        }
+        Ok(Addresses { ranges })
+    }
+}
+
+// parses the end of a range, which is open when nothing follows the separator
+fn parse_end(s: &str) -> Result<(Bound, &str), String> {
+    match s.is_empty() || s.starts_with(',') {
+        true => Ok((Bound::Last, s)),
+        false => parse_bound(s),
+    }
+}
+
+// parses a line number or a `/pattern/` from the front of `s`, returning it with the unparsed remainder
+fn parse_bound(s: &str) -> Result<(Bound, &str), String> {
+    if let Some(body) = s.strip_prefix('/') {
+        let mut pattern = String::new();
+        let mut chars = body.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '/' => {
+                    let re = Regex::new(&pattern).map_err(|e| e.to_string())?;
+                    return Ok((Bound::Pattern(re), &body[i + 1..]));
+                }
+                '\\' => match chars.next() {
+                    Some((_, '/')) => pattern.push('/'),
+                    Some((_, c)) => {
+                        pattern.push('\\');
+                        pattern.push(c);
+                    }
+                    None => pattern.push('\\'),
+                },
+                c => pattern.push(c),
+            }
+        }
+        return Err(format!("unterminated pattern in address '/{body}'"));
+    }
+
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    match s[..digits].parse::<usize>() {
+        Ok(0) => Err("line numbers start at 1".to_string()),
+        Ok(n) => Ok((Bound::Line(n), &s[digits..])),
+        Err(_) => Err(format!("expected a line number or /pattern/ at '{s}'")),
+    }
+}
@@ -0,0 +1,482 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::cli::{
+    FormattingOperation, ListOperation, NumericOperation, Operation, Output, op_functions,
+};
+use crate::compare::Order;
+use crate::fuzzy;
+use crate::pipeline::Pipeline;
+
+/// Running state for operations whose output depends on more than the current line.
+pub enum Aggregate {
+    CountTotal(usize),
+    Is(bool),
+    /// Every line, for operations that only output once the input is complete
+    Lines(Vec<String>),
+    /// The number the next line gets
+    Number(usize),
+    /// The input being read and the number of the line last read from it
+    Source(String, usize),
+    /// How many lines have been let through so far
+    Head(usize),
+    /// The last lines seen, at most as many as will be output
+    Tail(VecDeque<String>),
+    /// How many lines have been read, and a random sample of them with their positions
+    Reservoir(usize, Vec<(usize, String)>),
+    /// The current run of identical lines and its length
+    Adjacent(Option<(String, usize)>),
+    /// Every distinct line in the order first seen, with how often it occurred
+    Seen {
+        index: HashMap<String, usize>,
+        counts: Vec<(String, usize)>,
+    },
+    /// The lines of the other file not yet paired with a line of the input
+    Zip(Box<dyn Iterator<Item = Result<String, String>> + Send + Sync>),
+    /// Every distinct line output so far
+    Distinct(HashSet<String>),
+    /// The length of every line in bytes, characters and display width
+    Lengths(Vec<[usize; 3]>),
+    /// The lines kept so far that later lines are compared with
+    Kept(Vec<String>),
+    /// The state of each stage of a preset or script
+    Pipeline(Vec<Option<Aggregate>>),
+}
+
+impl Aggregate {
+    /// Returns the state the operation carries between lines, or `None` if it works line by line.
+    pub fn new(operation: &Operation) -> Result<Option<Self>, String> {
+        Ok(match operation {
+            Operation::Numeric(NumericOperation::Count { total: true, .. }) => {
+                Some(Aggregate::CountTotal(0))
+            }
+            Operation::Is { .. } => Some(Aggregate::Is(true)),
+            Operation::Lists(ListOperation::Join { .. })
+            | Operation::Lists(ListOperation::Sort { .. })
+            | Operation::Lists(ListOperation::Shuffle { .. })
+            | Operation::Formatting(FormattingOperation::Table { .. })
+            | Operation::Formatting(FormattingOperation::Transpose { .. })
+            | Operation::Formatting(FormattingOperation::StripCommonPrefix { .. })
+            | Operation::Formatting(FormattingOperation::StripCommonSuffix { .. })
+            | Operation::Formatting(FormattingOperation::Dedent {
+                strip_common: true, ..
+            })
+            | Operation::Lists(ListOperation::Reverse {
+                line_order: true, ..
+            }) => Some(Aggregate::Lines(Vec::new())),
+            Operation::Lists(ListOperation::Number { start, .. }) => {
+                Some(Aggregate::Number(*start))
+            }
+            Operation::Formatting(FormattingOperation::Format { .. })
+            | Operation::Script { .. } => Some(Aggregate::Source(String::new(), 0)),
+            Operation::Lists(ListOperation::Head { .. }) => Some(Aggregate::Head(0)),
+            Operation::Lists(ListOperation::Union { .. }) => {
+                Some(Aggregate::Distinct(HashSet::new()))
+            }
+            Operation::Numeric(NumericOperation::Stats { .. }) => {
+                Some(Aggregate::Lengths(Vec::new()))
+            }
+            Operation::Lists(ListOperation::DedupFuzzy { .. }) => Some(Aggregate::Kept(Vec::new())),
+            Operation::Lists(ListOperation::Zip { file, .. }) => {
+                Some(Aggregate::Zip(Box::new(op_functions::other_lines(file)?)))
+            }
+            Operation::Lists(ListOperation::Tail { .. }) => Some(Aggregate::Tail(VecDeque::new())),
+            Operation::Lists(ListOperation::Sample { n: Some(_), .. }) => {
+                Some(Aggregate::Reservoir(0, Vec::new()))
+            }
+            Operation::Lists(ListOperation::Dedup { adjacent: true, .. }) => {
+                Some(Aggregate::Adjacent(None))
+            }
+            Operation::Lists(ListOperation::Dedup { .. })
+            | Operation::Lists(ListOperation::Freq { .. })
+            | Operation::Lists(ListOperation::Tokens { .. }) => Some(Aggregate::Seen {
+                index: HashMap::new(),
+                counts: Vec::new(),
+            }),
+            Operation::Preset { .. } | Operation::Run { .. } | Operation::Stages(_) => {
+                match operation.pipeline()? {
+                    Some(pipeline) => pipeline.aggregates()?.map(Aggregate::Pipeline),
+                    None => None,
+                }
+            }
+            _ => None,
+        })
+    }
+
+    /// Starts reading from a new input.
+    pub fn begin(&mut self, source: &str) {
+        match self {
+            Aggregate::Source(name, line) => {
+                *name = source.to_string();
+                *line = 0;
+            }
+            Aggregate::Pipeline(states) => {
+                states
+                    .iter_mut()
+                    .flatten()
+                    .for_each(|state| state.begin(source));
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether no line fed from now on can change the output, so the rest of the input needn't be
+    /// read.
+    pub fn done(&self, operation: &Operation) -> bool {
+        match (self, operation) {
+            (Aggregate::Head(taken), Operation::Lists(ListOperation::Head { n })) => taken >= n,
+            (Aggregate::Pipeline(states), _) => operation
+                .pipeline()
+                .ok()
+                .flatten()
+                .is_some_and(|pipeline| pipeline.done(states)),
+            _ => false,
+        }
+    }
+
+    /// Feeds the next line, returning whatever can already be output for it.
+    pub fn push(&mut self, operation: &Operation, line: &str) -> Result<Output, String> {
+        Ok(match (self, operation) {
+            (Aggregate::Pipeline(states), _) => match operation.pipeline()? {
+                Some(pipeline) => pipeline.push(states, line)?,
+                None => Output::Skip,
+            },
+            (
+                Aggregate::CountTotal(total),
+                Operation::Numeric(NumericOperation::Count {
+                    words,
+                    graphemes,
+                    bytes,
+                    matches,
+                    ..
+                }),
+            ) => {
+                *total += op_functions::count(*words, *graphemes, *bytes, matches.as_ref(), line);
+                Output::Skip
+            }
+            (Aggregate::Is(all), Operation::Is { predicate, .. }) => {
+                *all &= op_functions::is(predicate, line);
+                operation.execute(line)?
+            }
+            (Aggregate::Lines(lines), _) => {
+                lines.push(line.to_string());
+                Output::Skip
+            }
+            (
+                Aggregate::Number(next),
+                Operation::Lists(ListOperation::Number {
+                    format, skip_empty, ..
+                }),
+            ) => match *skip_empty && line.is_empty() {
+                true => Output::Single(String::new()),
+                false => {
+                    *next += 1;
+                    Output::Single(op_functions::number(format, *next - 1, line))
+                }
+            },
+            (
+                Aggregate::Source(name, n),
+                Operation::Formatting(FormattingOperation::Format { template, delim }),
+            ) => {
+                *n += 1;
+                Output::Single(op_functions::format(
+                    template,
+                    delim.as_deref(),
+                    (*n, name),
+                    line,
+                ))
+            }
+            (Aggregate::Source(name, n), Operation::Script { script, delim }) => {
+                *n += 1;
+                op_functions::script(script, delim.as_deref(), (*n, name), line)?
+            }
+            (Aggregate::Head(taken), Operation::Lists(ListOperation::Head { n })) => {
+                match *taken < *n {
+                    true => {
+                        *taken += 1;
+                        Output::Single(line.to_string())
+                    }
+                    false => Output::Skip,
+                }
+            }
+            (Aggregate::Tail(last), Operation::Lists(ListOperation::Tail { n })) => {
+                if *n > 0 {
+                    if last.len() == *n {
+                        last.pop_front();
+                    }
+                    last.push_back(line.to_string());
+                }
+                Output::Skip
+            }
+            (
+                Aggregate::Reservoir(seen, sample),
+                Operation::Lists(ListOperation::Sample { n: Some(n), .. }),
+            ) => {
+                // each line read so far stays in the sample with probability n / seen
+                *seen += 1;
+                if sample.len() < *n {
+                    sample.push((*seen, line.to_string()));
+                } else {
+                    let i = fastrand::usize(..*seen);
+                    if i < *n {
+                        sample[i] = (*seen, line.to_string());
+                    }
+                }
+                Output::Skip
+            }
+            (Aggregate::Zip(other), Operation::Lists(ListOperation::Zip { delim, .. })) => {
+                let other = other.next().transpose()?.unwrap_or_default();
+                Output::Single(format!("{line}{delim}{other}"))
+            }
+            (
+                Aggregate::Kept(kept),
+                Operation::Lists(ListOperation::DedupFuzzy {
+                    threshold,
+                    adjacent,
+                }),
+            ) => {
+                if kept
+                    .iter()
+                    .any(|earlier| fuzzy::similar(earlier, line, *threshold))
+                {
+                    return Ok(Output::Skip);
+                }
+                if *adjacent {
+                    kept.clear();
+                }
+                kept.push(line.to_string());
+                Output::Single(line.to_string())
+            }
+            (Aggregate::Lengths(lengths), _) => {
+                lengths.push(op_functions::lengths(line));
+                Output::Skip
+            }
+            (Aggregate::Distinct(seen), _) => match seen.insert(line.to_string()) {
+                true => Output::Single(line.to_string()),
+                false => Output::Skip,
+            },
+            (Aggregate::Adjacent(Some((last, n))), _) if last == line => {
+                *n += 1;
+                Output::Skip
+            }
+            (Aggregate::Adjacent(run), Operation::Lists(ListOperation::Dedup { count, .. })) => {
+                // a new run starts, so the previous one is complete
+                let previous = run.replace((line.to_string(), 1));
+                match (count, previous) {
+                    (true, Some((last, n))) => Output::Single(op_functions::counted(n, &last)),
+                    (true, None) => Output::Skip,
+                    (false, _) => Output::Single(line.to_string()),
+                }
+            }
+            (
+                Aggregate::Seen { index, counts },
+                Operation::Lists(ListOperation::Dedup { count, .. }),
+            ) => match tally(index, counts, line) && !count {
+                true => Output::Single(line.to_string()),
+                false => Output::Skip,
+            },
+            (
+                Aggregate::Seen { index, counts },
+                Operation::Lists(ListOperation::Tokens { pattern, .. }),
+            ) => {
+                for token in op_functions::words(pattern.as_ref(), line) {
+                    tally(index, counts, token);
+                }
+                Output::Skip
+            }
+            (
+                Aggregate::Seen { index, counts },
+                Operation::Lists(ListOperation::Freq { by, .. }),
+            ) => {
+                for token in op_functions::tokens(by.as_ref(), line) {
+                    tally(index, counts, token);
+                }
+                Output::Skip
+            }
+            _ => operation.execute(line)?,
+        })
+    }
+
+    /// Returns the output held back until the end of the input.
+    pub fn finish(&mut self, operation: &Operation) -> Result<Output, String> {
+        Ok(match (self, operation) {
+            (Aggregate::Pipeline(states), _) => match operation.pipeline()? {
+                Some(pipeline) => pipeline.finish(states)?,
+                None => Output::Skip,
+            },
+            (Aggregate::Lines(lines), _) if lines.is_empty() => Output::Skip,
+            (
+                Aggregate::Lines(lines),
+                Operation::Lists(ListOperation::Join { delim, final_delim }),
+            ) => Output::Single(op_functions::join(delim, final_delim.as_deref(), lines)),
+            (
+                Aggregate::Lines(lines),
+                Operation::Lists(ListOperation::Sort {
+                    numeric,
+                    natural,
+                    version,
+                    reverse,
+                    key,
+                    delim,
+                }),
+            ) => {
+                let order = match (numeric, natural, version) {
+                    (true, _, _) => Order::Numeric,
+                    (_, true, _) => Order::Natural,
+                    (_, _, true) => Order::Version,
+                    _ => Order::Lexical,
+                };
+                Output::Multiple(op_functions::sort(
+                    order,
+                    *reverse,
+                    *key,
+                    delim.as_deref(),
+                    std::mem::take(lines),
+                ))
+            }
+            (
+                Aggregate::Lines(lines),
+                Operation::Formatting(FormattingOperation::Dedent { .. }),
+            ) => {
+                let common = op_functions::common_indent(lines).len();
+                Output::Multiple(
+                    lines
+                        .iter()
+                        .map(|line| line.get(common..).unwrap_or_default().to_string())
+                        .collect(),
+                )
+            }
+            (
+                Aggregate::Lines(lines),
+                Operation::Formatting(FormattingOperation::StripCommonPrefix { delim }),
+            ) => Output::Multiple(op_functions::strip_common(lines, false, delim.as_deref())),
+            (
+                Aggregate::Lines(lines),
+                Operation::Formatting(FormattingOperation::StripCommonSuffix { delim }),
+            ) => Output::Multiple(op_functions::strip_common(lines, true, delim.as_deref())),
+            (Aggregate::Lines(lines), Operation::Lists(ListOperation::Shuffle { seed })) => {
+                let mut rng = match seed {
+                    Some(seed) => fastrand::Rng::with_seed(*seed),
+                    None => fastrand::Rng::new(),
+                };
+                rng.shuffle(lines);
+                Output::Multiple(std::mem::take(lines))
+            }
+            (
+                Aggregate::Lines(lines),
+                Operation::Formatting(FormattingOperation::Table { delim }),
+            ) => Output::Multiple(op_functions::table(lines, delim.as_deref())),
+            (
+                Aggregate::Lines(lines),
+                Operation::Formatting(FormattingOperation::Transpose { delim }),
+            ) => Output::Multiple(op_functions::transpose(lines, delim.as_deref())),
+            (Aggregate::Lines(lines), Operation::Lists(ListOperation::Reverse { .. })) => {
+                Output::Multiple(lines.drain(..).rev().collect())
+            }
+            (Aggregate::CountTotal(total), _) => Output::Single(total.to_string()),
+            (
+                Aggregate::Lengths(lengths),
+                Operation::Numeric(NumericOperation::Stats { histogram }),
+            ) => Output::Multiple(op_functions::line_stats(lengths, *histogram)),
+            (Aggregate::Distinct(seen), Operation::Lists(ListOperation::Union { file })) => {
+                let mut rest = Vec::new();
+                for line in op_functions::other_lines(file)? {
+                    let line = line?;
+                    if seen.insert(line.clone()) {
+                        rest.push(line);
+                    }
+                }
+                match rest.is_empty() {
+                    true => Output::Skip,
+                    false => Output::Multiple(rest),
+                }
+            }
+            (Aggregate::Zip(other), Operation::Lists(ListOperation::Zip { delim, .. })) => {
+                let rest = other
+                    .map(|line| Ok(format!("{delim}{}", line?)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                match rest.is_empty() {
+                    true => Output::Skip,
+                    false => Output::Multiple(rest),
+                }
+            }
+            (Aggregate::Reservoir(_, sample), _) if !sample.is_empty() => {
+                sample.sort_by_key(|(position, _)| *position);
+                Output::Multiple(sample.drain(..).map(|(_, line)| line).collect())
+            }
+            (Aggregate::Tail(last), _) if !last.is_empty() => {
+                Output::Multiple(last.drain(..).collect())
+            }
+            (
+                Aggregate::Adjacent(run),
+                Operation::Lists(ListOperation::Dedup { count: true, .. }),
+            ) => run.take().map_or(Output::Skip, |(last, n)| {
+                Output::Single(op_functions::counted(n, &last))
+            }),
+            (Aggregate::Seen { counts, .. }, Operation::Lists(ListOperation::Freq { top, .. }))
+                if !counts.is_empty() =>
+            {
+                // stable, so items as frequent as each other stay in the order first seen
+                counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+                Output::Multiple(
+                    counts
+                        .iter()
+                        .take(top.unwrap_or(usize::MAX))
+                        .map(|(item, n)| op_functions::counted(*n, item))
+                        .collect(),
+                )
+            }
+            (
+                Aggregate::Seen { counts, .. },
+                Operation::Lists(ListOperation::Tokens { top, .. }),
+            ) if !counts.is_empty() => {
+                let total = counts.iter().map(|(_, n)| n).sum();
+                counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+                Output::Multiple(
+                    counts
+                        .iter()
+                        .take(top.unwrap_or(usize::MAX))
+                        .map(|(token, n)| op_functions::token_share(*n, total, token))
+                        .collect(),
+                )
+            }
+            (
+                Aggregate::Seen { counts, .. },
+                Operation::Lists(ListOperation::Dedup { count: true, .. }),
+            ) if !counts.is_empty() => Output::Multiple(
+                counts
+                    .iter()
+                    .map(|(line, n)| op_functions::counted(*n, line))
+                    .collect(),
+            ),
+            _ => Output::Skip,
+        })
+    }
+
+    /// Whether the run should exit successfully, for operations that report through the exit status.
+    pub fn success(&self) -> bool {
+        match self {
+            Aggregate::Is(all) => *all,
+            Aggregate::Pipeline(states) => Pipeline::success(states),
+            _ => true,
+        }
+    }
+}
+
+// counts another occurrence of `item`, returning whether it is the first
+fn tally(
+    index: &mut HashMap<String, usize>,
+    counts: &mut Vec<(String, usize)>,
+    item: &str,
+) -> bool {
+    match index.get(item) {
+        Some(&i) => {
+            counts[i].1 += 1;
+            false
+        }
+        None => {
+            index.insert(item.to_string(), counts.len());
+            counts.push((item.to_string(), 1));
+            true
+        }
+    }
+}
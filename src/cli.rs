@@ -1,13 +1,426 @@
-use clap::{Parser, Subcommand};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use aho_corasick::AhoCorasick;
+
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueEnum};
+use encoding_rs::Encoding;
+use jiff::tz::TimeZone;
+use regex::Regex;
+
+use crate::address::Addresses;
+use crate::color::ColorChoice;
+use crate::numbers::NumExpr;
+use crate::pattern::{Pattern, PatternSet};
+use crate::pipeline::{Definition, OptionValue, Pipeline, Word, Words};
+use crate::process::{NonMatching, QuoteOutput};
+use crate::records::LineEnding;
+use crate::redact::RedactPreset;
+use crate::script::Script;
+use crate::span::{OnInvalid, Span};
+use crate::template::Template;
+use crate::time::TimeFormat;
 
 pub enum Output {
     Multiple(Vec<String>),
     Single(String),
+    Skip,
+}
+
+impl Output {
+    pub fn map(self, f: impl Fn(String) -> String) -> Self {
+        match self {
+            Output::Multiple(x) => Output::Multiple(x.into_iter().map(f).collect()),
+            Output::Single(x) => Output::Single(f(x)),
+            Output::Skip => Output::Skip,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RatioFormat {
+    Ratio,
+    Percent,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Metric {
+    /// Shannon entropy of the line's characters, in bits per character
+    Entropy,
+    /// Proportion of alphabetic characters
+    Alpha,
+    /// Proportion of numeric characters
+    Digit,
+    /// Proportion of uppercase characters
+    Upper,
+    /// Proportion of whitespace characters
+    Whitespace,
+    /// Proportion of ASCII punctuation characters
+    Punct,
+    /// Proportion of non-ASCII characters
+    NonAscii,
+}
+
+/// The regex dialect `RegexQuote` escapes for.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RegexFlavor {
+    /// str's own --regex mode and the Rust regex crate
+    Rust,
+    /// POSIX extended regular expressions, as in `grep -E`
+    Ere,
+    /// POSIX basic regular expressions, as in plain `grep` and `sed`
+    Bre,
+}
+
+/// The component of a URL `Url` outputs.
+#[derive(Clone)]
+pub enum UrlPart {
+    Scheme,
+    Host,
+    Port,
+    Path,
+    Query,
+    Fragment,
+    /// The value of one parameter of the query, decoded
+    QueryKey(String),
+}
+
+impl FromStr for UrlPart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "scheme" => UrlPart::Scheme,
+            "host" => UrlPart::Host,
+            "port" => UrlPart::Port,
+            "path" => UrlPart::Path,
+            "query" => UrlPart::Query,
+            "fragment" => UrlPart::Fragment,
+            _ => match s.strip_prefix("query:") {
+                Some(key) => UrlPart::QueryKey(key.to_string()),
+                None => {
+                    return Err(
+                        "expected scheme, host, port, path, query, fragment or query:KEY"
+                            .to_string(),
+                    );
+                }
+            },
+        })
+    }
+}
+
+impl fmt::Display for UrlPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UrlPart::Scheme => f.write_str("scheme"),
+            UrlPart::Host => f.write_str("host"),
+            UrlPart::Port => f.write_str("port"),
+            UrlPart::Path => f.write_str("path"),
+            UrlPart::Query => f.write_str("query"),
+            UrlPart::Fragment => f.write_str("fragment"),
+            UrlPart::QueryKey(key) => write!(f, "query:{key}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Blake3,
+    Crc32,
 }
 
+#[derive(Subcommand)]
+pub enum Predicate {
+    #[command(about = "Line contains a pattern")]
+    Contains { pattern: Pattern },
+    #[command(about = "Line starts with a pattern")]
+    StartsWith { pattern: Pattern },
+    #[command(about = "Line ends with a pattern")]
+    EndsWith { pattern: Pattern },
+    #[command(about = "Line matches a regular expression")]
+    Matches { pattern: Regex },
+    #[command(about = "Line is empty", alias = "is-empty")]
+    Empty,
+    #[command(about = "Line is a number", alias = "is-number")]
+    Number,
+    #[command(about = "Line is entirely ASCII", alias = "is-ascii")]
+    Ascii,
+}
+
+/// An operation, its larger sections grouped into enums of their own. clap builds an enum's
+/// subcommands in a single function, and all of them together need more stack than the 2 MiB a
+/// thread gets in a debug build.
 #[derive(Subcommand)]
 pub enum Operation {
     /* Pattern-Based */
+    #[command(flatten)]
+    PatternBased(PatternOperation),
+
+    /* Index-Based */
+    #[command(flatten)]
+    IndexBased(IndexOperation),
+
+    /* Mixed */
+    #[command(
+        about = "Cuts from a pattern to an index",
+        long_about = "Cuts contents of a line starting at the first character of a pattern to a given index (exclusive)."
+    )]
+    CutFromPatToIndex {
+        #[arg(help = "Pattern to begin cutting at (pattern-inclusive)")]
+        pattern: Pattern,
+        #[arg(
+            help = "Index to stop cutting at, must be after pattern (zero-based, index-exclusive)"
+        )]
+        index: usize,
+    },
+
+    #[command(
+        about = "Cuts from an index to a pattern",
+        long_about = "Cuts contents of a line starting from a given index (inclusive) and the first character of a pattern."
+    )]
+    CutFromIndexToPat {
+        #[arg(help = "Index to begin cutting at (zero-based, index-inclusive)")]
+        index: usize,
+        #[arg(help = "Pattern to stop cutting at (pattern-exclusive)")]
+        pattern: Pattern,
+    },
+
+    #[command(
+        about = "Trims between a pattern and an index",
+        long_about = "Trims (removes) contents of a line between the firts character of a pattern and a given index in the line."
+    )]
+    TrimFromPatToIndex {
+        #[arg(help = "Patter to begin trimming from (pattern-inclusive)")]
+        pattern: Pattern,
+        #[arg(help = "Index to stop trimming at (index-exclusive)")]
+        index: usize,
+    },
+
+    #[command(
+        about = "Trims between an index and a pattern",
+        long_about = "Trims (removes) contents of a line starting from a given index (inclusive) and the first character of a pattern."
+    )]
+    TrimFromIndexToPat {
+        #[arg(help = "Index to begin trimming from (index-inclusive)")]
+        index: usize,
+        #[arg(help = "Pattern to stop trimming at (pattern-exclusive)")]
+        pattern: Pattern,
+    },
+
+    /* Formatting */
+    #[command(flatten)]
+    Formatting(FormattingOperation),
+
+    /* Encoding */
+    #[command(flatten)]
+    Encoding(EncodingOperation),
+
+    /* Character Mapping */
+    #[command(
+        about = "Rotates letters through the alphabet",
+        long_about = "Shifts each ASCII letter N places through the alphabet (13 by default, the self-inverse ROT13), wrapping around from z to a. Case is preserved and other characters are left as they are. A negative N shifts backwards, so rot -3 undoes rot 3."
+    )]
+    Rot {
+        #[arg(
+            help = "Number of places to shift (negative shifts backwards)",
+            default_value_t = 13,
+            allow_negative_numbers = true
+        )]
+        n: i64,
+    },
+
+    #[command(
+        about = "Turns each line into a URL slug",
+        long_about = "Transliterates each line to ASCII (so café becomes cafe), lowercases it and collapses every run of other characters into a single hyphen, trimming any at the ends. The result is safe for URLs and file names."
+    )]
+    Slug {
+        #[arg(help = "Separator between words", long, default_value = "-")]
+        separator: String,
+    },
+
+    #[command(
+        about = "Transliterates each line to ASCII",
+        long_about = "Replaces accented and non-Latin characters in each line with their closest ASCII equivalents (é becomes e, ß becomes ss, 北 becomes Bei), for systems that only handle ASCII. Characters with no equivalent are replaced with --unknown."
+    )]
+    Deunicode {
+        #[arg(
+            help = "Text to write for characters with no ASCII equivalent",
+            long,
+            value_name = "TEXT",
+            default_value = "[?]"
+        )]
+        unknown: String,
+    },
+
+    /* Paths */
+    #[command(
+        about = "Outputs the last component of a path",
+        long_about = "Outputs the file or directory name a path ends in, ignoring trailing separators, as basename does: a/b/ gives b, and / gives /. Backslashes count as separators too, for Windows paths."
+    )]
+    Basename,
+
+    #[command(
+        about = "Outputs the directory of a path",
+        long_about = "Outputs a path without its last component, ignoring trailing separators, as dirname does: a/b/ gives a, a gives ., and /a gives /. Backslashes count as separators too, for Windows paths, and a drive keeps its separator (C:\\a gives C:\\)."
+    )]
+    Dirname,
+
+    #[command(
+        about = "Outputs the extension of a path",
+        long_about = "Outputs the extension of the last component of a path, without the dot: the part after its last dot, so a.tar.gz gives gz. Names without a dot, or starting with their only one such as .bashrc, have no extension and give an empty line."
+    )]
+    Ext,
+
+    #[command(
+        about = "Outputs the file name of a path without its extension",
+        long_about = "Outputs the last component of a path with its extension removed, so dir/a.tar.gz gives a.tar and .bashrc stays .bashrc."
+    )]
+    Stem,
+
+    #[command(
+        about = "Replaces the extension of a path",
+        long_about = "Replaces the extension of the last component of a path with a new one, or adds it to names without one, keeping the rest of the path. An empty extension removes it."
+    )]
+    WithExt {
+        #[arg(help = "New extension, with or without its dot")]
+        ext: String,
+    },
+
+    /* Time */
+    #[command(
+        about = "Converts timestamps",
+        long_about = "Finds the timestamps in each line, as epoch seconds or milliseconds, RFC 3339 (2024-05-01T12:30:00Z, also with a space or no offset) or the common log format of access logs (01/May/2024:12:30:00 +0000), and rewrites them in another format and time zone. --from limits the search to one of these formats, or with a strftime format reads the whole line as one timestamp. Timestamps without an offset are taken as UTC; those that can't be read are left as they are."
+    )]
+    Time {
+        #[arg(
+            help = "Format to read: epoch, epoch-ms, rfc3339, clf, or a strftime format for the whole line (any of the named formats by default)",
+            long,
+            value_name = "FORMAT"
+        )]
+        from: Option<TimeFormat>,
+        #[arg(
+            help = "Format to write: epoch, epoch-ms, rfc3339, clf or a strftime format",
+            long,
+            value_name = "FORMAT",
+            default_value = "rfc3339"
+        )]
+        to: TimeFormat,
+        #[arg(
+            help = "Time zone to write times in: an IANA name such as Europe/Paris, UTC or local",
+            long,
+            value_name = "ZONE",
+            default_value = "UTC",
+            value_parser = crate::time::parse_zone
+        )]
+        tz: TimeZone,
+    },
+
+    /* Numeric */
+    #[command(flatten)]
+    Numeric(NumericOperation),
+
+    /* Lists */
+    #[command(flatten)]
+    Lists(ListOperation),
+
+    /* Predicates */
+    #[command(
+        about = "Tests lines against a predicate",
+        long_about = "Tests each line against a predicate, printing true or false for each (nothing with --quiet). Exits with status 0 if every line satisfies the predicate and 1 otherwise, for use in shell conditions."
+    )]
+    Is {
+        #[command(subcommand)]
+        predicate: Predicate,
+        #[arg(
+            help = "Print nothing, only set the exit status",
+            short,
+            long,
+            global = true
+        )]
+        quiet: bool,
+    },
+
+    /* Pipelines */
+    #[command(
+        about = "Runs a named preset",
+        long_about = "Runs a preset from $XDG_CONFIG_HOME/str/presets.toml (~/.config/str/presets.toml by default), so a team can share its cleanup recipes. Each preset is an operation or a list of operations applied one after another, as if each line were piped from one str to the next, written as on the command line:\n\n  tidy = [\"trim\", \"replace '  ' -- ' '\"]\n\nor as tables, like the JSON that `str run` takes:\n\n  tidy = [{ op = \"trim\" }, { op = \"replace\", args = [\"  \", \"--\", \" \"] }]\n\nOptions such as --regex are given with `str preset`, and apply to every stage."
+    )]
+    Preset {
+        #[arg(help = "Name of the preset")]
+        name: String,
+        #[arg(
+            help = "Read presets from this file instead",
+            long,
+            value_name = "FILE"
+        )]
+        presets: Option<PathBuf>,
+        #[arg(skip)]
+        pipeline: OnceLock<Pipeline>,
+    },
+
+    #[command(
+        about = "Runs operations described as JSON",
+        long_about = "Runs an operation, or a list of operations applied one after another, described as JSON: an object naming the operation with \"op\", giving its arguments as on the command line with \"args\", and its options by their long names:\n\n  {\"op\": \"replace\", \"args\": [\"cat\", \"--\", \"dog\"], \"number\": 1}\n\nOptions that take no value are set with true. Options such as --regex may also be given with `str run`, and apply to every operation."
+    )]
+    Run {
+        #[arg(
+            help = "The operation or list of operations",
+            long,
+            value_name = "JSON"
+        )]
+        json: String,
+        #[arg(skip)]
+        pipeline: OnceLock<Pipeline>,
+    },
+
+    #[command(
+        about = "Runs a Rhai script on each line",
+        long_about = "Evaluates a Rhai script (https://rhai.rs) for each line, with the line as `line`, its fields as the array `fields` (zero-based), its number in its input as `n` and the input's name as `file`. A string the script evaluates to replaces the line, and an array replaces it with a line per element; false drops the line, and true or nothing keeps it, along with any changes the script made to `line`. print writes to stderr."
+    )]
+    Script {
+        #[arg(
+            help = "Rhai code, e.g. 'fields[1].to_upper()'",
+            value_parser = Script::compile
+        )]
+        script: Script,
+        #[arg(
+            help = "Optional: delimiter separating fields (defaults to whitespace)",
+            short,
+            long
+        )]
+        delim: Option<String>,
+    },
+
+    #[command(
+        about = "Builds a pipeline interactively",
+        long_about = "Opens an editor for a pipeline of operations, showing their output on a sample of the input (its first 1000 records) as they are typed. Ctrl-E exits and prints the pipeline as a shell command."
+    )]
+    Tui,
+
+    /// The operations of a script given with --file
+    #[command(skip)]
+    Stages(Pipeline),
+
+    /* Help */
+    #[command(
+        about = "Prints usage examples",
+        long_about = "Prints runnable examples of each operation, or only those of a given operation."
+    )]
+    Examples {
+        #[arg(help = "Optional: operation to show examples of (e.g. cut-from-pat)")]
+        operation: Option<String>,
+    },
+}
+
+/// The pattern-based operations.
+#[derive(Subcommand)]
+pub enum PatternOperation {
     #[command(
         about = "Splits at whitespace",
         long_about = "Splits each line at whitespace, optionally a finite number of times per line."
@@ -17,6 +430,11 @@ pub enum Operation {
             help = "Optional: number of whitespace segments to split at (negative values start from end)"
         )]
         number: Option<i64>,
+        #[arg(
+            help = "Don't split inside single or double quotes, which are kept in the output",
+            long
+        )]
+        respect_quotes: bool,
     },
 
     #[command(
@@ -25,11 +443,16 @@ pub enum Operation {
     )]
     SplitAtPat {
         #[arg(help = "Pattern at which to split the input")]
-        pattern: String,
+        pattern: Pattern,
         #[arg(
             help = "Optional: number of times to split at given pattern (negative values start from end"
         )]
         number: Option<i64>,
+        #[arg(
+            help = "Don't split inside single or double quotes, which are kept in the output",
+            long
+        )]
+        respect_quotes: bool,
     },
 
     #[command(
@@ -43,6 +466,63 @@ pub enum Operation {
             help = "Optional: number of times to split at a given character (negative values start from end)"
         )]
         number: Option<i64>,
+        #[arg(
+            help = "Don't split inside single or double quotes, which are kept in the output",
+            long
+        )]
+        respect_quotes: bool,
+    },
+
+    #[command(
+        about = "Keeps a single segment of a line",
+        long_about = "Splits each line at a delimiter (whitespace by default) and outputs only the Nth segment, counting from zero, or from the end when negative (-1 is the last segment)."
+    )]
+    Segment {
+        #[arg(
+            help = "Segment to keep (zero-based, negative values count from end)",
+            allow_negative_numbers = true
+        )]
+        n: i64,
+        #[arg(
+            help = "Optional: delimiter to split at (defaults to whitespace)",
+            short,
+            long
+        )]
+        delimiter: Option<String>,
+    },
+
+    #[command(
+        about = "Gets the value of a key",
+        long_about = "Reads each line as key-value pairs, such as `level=info msg=\"hi there\" user=bob`, and outputs the value of a given key with any quotes around it removed, dropping lines without the key. Separators inside single or double quotes don't count."
+    )]
+    Get {
+        #[arg(help = "Key whose value to output")]
+        key: String,
+        #[arg(
+            help = "Separator between a key and its value",
+            long,
+            value_name = "SEP",
+            default_value = "="
+        )]
+        kv_sep: String,
+        #[arg(
+            help = "Separator between one pair and the next",
+            long,
+            value_name = "SEP",
+            default_value = " "
+        )]
+        pair_sep: String,
+    },
+
+    #[command(
+        about = "Gets a component of a URL",
+        long_about = "Parses each line as a URL and outputs one component of it: its scheme, host, port (the scheme's default when none is given), path, query, fragment, or with query:KEY the decoded value of one query parameter. Lines that aren't URLs or lack the component are dropped."
+    )]
+    Url {
+        #[arg(
+            help = "Component to output: scheme, host, port, path, query, fragment or query:KEY"
+        )]
+        part: UrlPart,
     },
 
     #[command(
@@ -53,7 +533,7 @@ pub enum Operation {
         #[arg(
             help = "Pattern at which to begin cutting (pattern-inclusive, cuts until end of line)"
         )]
-        pattern: String,
+        pattern: Pattern,
     },
 
     #[command(
@@ -64,11 +544,16 @@ pub enum Operation {
         #[arg(
             help = "Pattern at which to begin cutting (pattern-inclusive, cuts until end-pattern, or end of line)"
         )]
-        start: String,
+        start: Pattern,
         #[arg(
             help = "Pattern at which to stop cutting (pattern-exclusive, from beginning if start not found)"
         )]
-        end: String,
+        end: Pattern,
+        #[arg(
+            help = "Output every span from a start pattern to the next end pattern after it, each on its own line, dropping lines without one",
+            long
+        )]
+        all: bool,
     },
 
     #[command(
@@ -77,9 +562,10 @@ pub enum Operation {
     )]
     CutFromPatToOffset {
         #[arg(help = "Pattern to begin cutting at (pattern-inclusive)")]
-        pattern: String,
+        pattern: Pattern,
         #[arg(
-            help = "Offset from pattern to cut until (negative values move backward from beginning of first pattern)"
+            help = "Offset from pattern to cut until (negative values move backward from beginning of first pattern)",
+            allow_negative_numbers = true
         )]
         offset: i64,
     },
@@ -89,8 +575,20 @@ pub enum Operation {
         long_about = "Cuts starting from the beginning of a line to either the first character of a pattern, or the end of the line if the pattern isn't found."
     )]
     CutUntilPat {
-        #[arg(help = "Pattern to cut until (pattern-exclusive)")]
-        pattern: String,
+        #[arg(
+            help = "Pattern to cut until (pattern-exclusive)",
+            required_unless_present = "patterns"
+        )]
+        pattern: Option<Pattern>,
+        #[arg(
+            help = "Another pattern to match alongside it, the first of any of them in the line counting (may be given multiple times)",
+            short = 'e',
+            long = "pattern",
+            value_name = "PATTERN"
+        )]
+        patterns: Vec<Pattern>,
+        #[arg(skip)]
+        set: OnceLock<PatternSet>,
     },
 
     #[command(
@@ -98,8 +596,20 @@ pub enum Operation {
         long_about = "Trims (removes) contents of a line starting from a given pattern to the end of the line."
     )]
     TrimFromPat {
-        #[arg(help = "Pattern to begin trimming at (pattern-inclusive)")]
-        pattern: String,
+        #[arg(
+            help = "Pattern to begin trimming at (pattern-inclusive)",
+            required_unless_present = "patterns"
+        )]
+        pattern: Option<Pattern>,
+        #[arg(
+            help = "Another pattern to match alongside it, the first of any of them in the line counting (may be given multiple times)",
+            short = 'e',
+            long = "pattern",
+            value_name = "PATTERN"
+        )]
+        patterns: Vec<Pattern>,
+        #[arg(skip)]
+        set: OnceLock<PatternSet>,
     },
 
     #[command(
@@ -110,9 +620,9 @@ pub enum Operation {
         #[arg(
             help = "Pattern to begin trimming from (pattern-inclusive, trims to end if end-pattern is not found)"
         )]
-        start: String,
+        start: Pattern,
         #[arg(help = "Pattern to stop trimming at (pattern-exclusive)")]
-        end: String,
+        end: Pattern,
     },
 
     #[command(
@@ -121,7 +631,7 @@ pub enum Operation {
     )]
     TrimUntilPat {
         #[arg(help = "Pattern to trim until (pattern-exclusive)")]
-        pattern: String,
+        pattern: Pattern,
     },
 
     #[command(
@@ -130,514 +640,5555 @@ pub enum Operation {
     )]
     TrimToPat {
         #[arg(help = "Pattern to trim to (pattern-inclusive)")]
-        pattern: String,
+        pattern: Pattern,
     },
 
-    /* Index-Based */
     #[command(
-        about = "Splits at a given index",
-        long_about = "Split each line at a given index."
+        about = "Keeps lines containing a pattern",
+        long_about = "Keeps only the lines containing a given pattern, dropping the rest (or the reverse with --invert)."
     )]
-    SplitAtIndex {
-        #[arg(help = "Index to split at")]
-        index: usize,
+    Filter {
+        #[arg(
+            help = "Pattern lines must contain to be kept",
+            required_unless_present = "patterns"
+        )]
+        pattern: Option<Pattern>,
+        #[arg(
+            help = "Another pattern lines may contain instead (may be given multiple times)",
+            short = 'e',
+            long = "pattern",
+            value_name = "PATTERN"
+        )]
+        patterns: Vec<Pattern>,
+        // set by the global --invert
+        #[arg(skip)]
+        invert: bool,
+        #[arg(skip)]
+        set: OnceLock<PatternSet>,
     },
 
     #[command(
-        about = "Cut beginning at a given index",
-        long_about = "Cut each line beginning at an index until the end of the line."
+        about = "Outputs only the matching parts of a line",
+        long_about = "Outputs only the part of each line matching a given pattern (most useful with --regex), dropping lines without a match. With --all, every match on a line is output on its own line."
     )]
-    CutFromIndex {
-        #[arg(help = "Index to begin cutting from (index-inclusive, cuts until end of line)")]
-        index: usize,
+    Match {
+        #[arg(help = "Pattern to output matches of")]
+        pattern: Pattern,
+        #[arg(
+            help = "Output every match on each line instead of only the first",
+            long
+        )]
+        all: bool,
     },
 
     #[command(
-        about = "Cut between two indices",
-        long_about = "Cut each line between one index (inclusive) and another (exclusive)."
+        about = "Outputs what is between two delimiters",
+        long_about = "Outputs the text between each opening delimiter and the closing one after it, each on its own line, dropping lines without any. With --balanced, delimiters nest, so `(a (b) c)` gives `a (b) c` rather than stopping at the first closing delimiter; a delimiter that is left open is ignored."
     )]
-    CutFromIndexToIndex {
-        #[arg(help = "Index to begin cutting from (index-inclusive)")]
-        start: usize,
-        #[arg(help = "Index to stop cutting at (index-exclusive)")]
-        end: usize,
+    Between {
+        #[arg(help = "Opening delimiter (excluded from the output)")]
+        open: Pattern,
+        #[arg(help = "Closing delimiter (excluded from the output)")]
+        close: Pattern,
+        #[arg(
+            help = "Track how deeply delimiters nest, outputting what is between each outermost pair",
+            long
+        )]
+        balanced: bool,
     },
 
     #[command(
-        about = "Cuts from an index to an offset from the index",
-        long_about = "Cuts contents of a line starting from an index to a given offset amount from the index."
+        about = "Keeps lines approximately containing a pattern",
+        long_about = "Keeps only the lines containing a part within a Levenshtein (edit) distance of a given text, that is, at most --max-dist characters inserted, deleted or substituted away from it, so misspelled hostnames, usernames or keys still match. With --whole-line, the whole line is compared instead. With --positions, the byte range of the closest part of each kept line is output instead of the line."
     )]
-    CutFromIndexToOffset {
-        #[arg(help = "Index at which to begin cutting (index-inclusive)")]
-        index: usize,
-        #[arg(help = "Offset from index to cut to (negative values move backward from index)")]
-        offset: i64,
+    Fuzzy {
+        #[arg(help = "Text to look for (always literal)")]
+        pattern: String,
+        #[arg(
+            help = "Most characters to insert, delete or substitute",
+            long,
+            default_value_t = 1
+        )]
+        max_dist: usize,
+        #[arg(help = "Compare the whole line rather than any part of it", long)]
+        whole_line: bool,
+        #[arg(
+            help = "Output the byte range of the closest part of each line, as START..END",
+            long,
+            conflicts_with = "whole_line"
+        )]
+        positions: bool,
     },
 
     #[command(
-        about = "Cuts until a given index",
-        long_about = "Cuts contents of a line starting from the beginning of each line to a given index in that line."
+        about = "Trims whitespace or patterns from both ends",
+        long_about = "Trims (removes) either whitespace (default) or patterns from the beginning and end of each line."
+    )]
+    Trim {
+        #[arg(help = "Optional: pattern to trim from beginning and end of input")]
+        pattern: Option<Pattern>,
+    },
+
+    #[command(
+        about = "Replaces a list of patterns with another pattern",
+        long_about = "Replaces instances of given patterns with another pattern, optionally a finite number of times per line (to remove patterns entirely, use remove command)."
+    )]
+    Replace {
+        #[arg(help = "Patterns to replace inline from input")]
+        patterns: Vec<Pattern>,
+        #[arg(help = "What to replace pattern with", last(true))]
+        with: String,
+        #[arg(
+            help = "Optional: number of pattern-matches to replace (negative values start from end)",
+            short,
+            long
+        )]
+        number: Option<i64>,
+        #[arg(skip)]
+        set: OnceLock<PatternSet>,
+    },
+
+    #[command(
+        about = "Removes a pattern",
+        long_about = "Removes inline a given pattern, optioanlly a finite number of times per line."
+    )]
+    Remove {
+        #[arg(
+            help = "List of patterns to remove inline from input",
+            required_unless_present = "patterns"
+        )]
+        pattern: Vec<Pattern>,
+        #[arg(
+            help = "Another pattern to remove (may be given multiple times)",
+            short = 'e',
+            long = "pattern",
+            value_name = "PATTERN"
+        )]
+        patterns: Vec<Pattern>,
+        #[arg(
+            help = "Optional: number of pattern-matches to remove (negative values start from end)",
+            short,
+            long
+        )]
+        number: Option<i64>,
+        #[arg(skip)]
+        set: OnceLock<PatternSet>,
+    },
+
+    #[command(
+        about = "Replaces many patterns at once",
+        long_about = "Replaces many patterns with their own replacements in a single pass, preferring the leftmost and then longest match, so one replacement can never be matched by another. Patterns are literal text and case matters; --regex and --fold-case are rejected rather than ignored."
+    )]
+    ReplaceMany {
+        #[arg(
+            help = "Pattern-replacement pairs in the form PATTERN=REPLACEMENT (split at the first '=')"
+        )]
+        pairs: Vec<String>,
+        #[arg(
+            help = "File of additional PATTERN=REPLACEMENT pairs, one per line",
+            long,
+            value_name = "FILE"
+        )]
+        map: Option<PathBuf>,
+        #[arg(skip)]
+        table: OnceLock<(AhoCorasick, Vec<String>)>,
+    },
+
+    #[command(
+        about = "Highlights patterns",
+        long_about = "Highlights every instance of the given patterns in color, leaving the rest of the line untouched. Whether color is used follows --color."
+    )]
+    Highlight {
+        #[arg(help = "List of patterns to highlight", required = true)]
+        patterns: Vec<Pattern>,
+    },
+
+    #[command(
+        about = "Masks sensitive text",
+        long_about = "Replaces every match of the given patterns or presets (email addresses, IPv4 addresses, social security numbers, card numbers) with a mask, **** by default, or with its digest under --hash so equal values stay recognizable, so logs can be shared without what they shouldn't reveal."
+    )]
+    #[command(group(ArgGroup::new("sensitive").required(true).multiple(true)))]
+    Redact {
+        #[arg(help = "Patterns to redact", group = "sensitive")]
+        patterns: Vec<Pattern>,
+        #[arg(
+            help = "Redact a kind of sensitive text (repeatable)",
+            long,
+            value_enum,
+            group = "sensitive"
+        )]
+        preset: Vec<RedactPreset>,
+        #[arg(help = "Text to replace each match with", long, default_value = "****")]
+        mask: String,
+        #[arg(
+            help = "Replace each match with its digest instead (sha256 by default)",
+            long,
+            value_name = "ALGORITHM",
+            value_enum,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "sha256",
+            conflicts_with = "mask"
+        )]
+        hash: Option<HashAlgorithm>,
+    },
+}
+
+/// The index-based operations.
+#[derive(Subcommand)]
+pub enum IndexOperation {
+    #[command(
+        about = "Splits at a given index",
+        long_about = "Split each line at a given index."
+    )]
+    SplitAtIndex {
+        #[arg(help = "Index to split at")]
+        index: usize,
+    },
+
+    #[command(
+        about = "Cut beginning at a given index",
+        long_about = "Cut each line beginning at an index until the end of the line."
+    )]
+    CutFromIndex {
+        #[arg(help = "Index to begin cutting from (index-inclusive, cuts until end of line)")]
+        index: usize,
+    },
+
+    #[command(
+        about = "Cut between two indices",
+        long_about = "Cut each line between one index (inclusive) and another (exclusive)."
+    )]
+    CutFromIndexToIndex {
+        #[arg(help = "Index to begin cutting from (index-inclusive)")]
+        start: usize,
+        #[arg(help = "Index to stop cutting at (index-exclusive)")]
+        end: usize,
+    },
+
+    #[command(
+        about = "Cuts from an index to an offset from the index",
+        long_about = "Cuts contents of a line starting from an index to a given offset amount from the index."
+    )]
+    CutFromIndexToOffset {
+        #[arg(help = "Index at which to begin cutting (index-inclusive)")]
+        index: usize,
+        #[arg(
+            help = "Offset from index to cut to (negative values move backward from index)",
+            allow_negative_numbers = true
+        )]
+        offset: i64,
+    },
+
+    #[command(
+        about = "Cuts until a given index",
+        long_about = "Cuts contents of a line starting from the beginning of each line to a given index in that line."
     )]
     CutUntilIndex {
         #[arg(help = "Index to cut until (index-exclusive, if zero)")]
         index: usize,
     },
-
-    #[command(
-        about = "Trims starting from an index",
-        long_about = "Trims (removes) contents of a line starting from a given index (inclusive) the the end of the line."
-    )]
-    TrimFromIndex {
-        #[arg(help = "Index to begin trimming from (zero-based, index-inclusive)")]
-        index: usize,
+
+    #[command(
+        about = "Trims starting from an index",
+        long_about = "Trims (removes) contents of a line starting from a given index (inclusive) the the end of the line."
+    )]
+    TrimFromIndex {
+        #[arg(help = "Index to begin trimming from (zero-based, index-inclusive)")]
+        index: usize,
+    },
+
+    #[command(
+        about = "Trims between two indices",
+        long_about = "Trims (removes) contents of a line between two indices."
+    )]
+    TrimFromIndexToIndex {
+        #[arg(help = "Index to begin trimming from (zero-based, index-inclusive)")]
+        start: usize,
+        #[arg(help = "Index to stop trimming at (zero-based, index-exclusive)")]
+        end: usize,
+    },
+
+    #[command(
+        about = "Trims from an index ton an offset from the index",
+        long_about = "Trims (removes) starting from an index to a given offset amount from the index."
+    )]
+    TrimFromIndexToOffset {
+        #[arg(help = "Index to start trimming from (zero-based, index-inclusive)")]
+        index: usize,
+        #[arg(
+            help = "Offset from index to trim to (negative values move backward from index)",
+            allow_negative_numbers = true
+        )]
+        offset: i64,
+    },
+
+    #[command(
+        about = "Trims until an index",
+        long_about = "Trims (removes) contents of a line until a given index."
+    )]
+    TrimUntilIndex {
+        #[arg(help = "Index to trim until (zero-baesd, index-exclusive)")]
+        index: usize,
+    },
+}
+
+/// The formatting operations.
+#[derive(Subcommand)]
+pub enum FormattingOperation {
+    #[command(
+        about = "Pads a line on the left",
+        long_about = "Pads (right-justifies) each line on the left to a given display width, optionally with a fill character other than a space."
+    )]
+    PadLeft {
+        #[arg(help = "Display width to pad to (lines already at least this wide are left as-is)")]
+        width: usize,
+        #[arg(help = "Optional: character to pad with (defaults to a space)")]
+        fill: Option<char>,
+    },
+
+    #[command(
+        about = "Pads a line on the right",
+        long_about = "Pads (left-justifies) each line on the right to a given display width, optionally with a fill character other than a space."
+    )]
+    PadRight {
+        #[arg(help = "Display width to pad to (lines already at least this wide are left as-is)")]
+        width: usize,
+        #[arg(help = "Optional: character to pad with (defaults to a space)")]
+        fill: Option<char>,
+    },
+
+    #[command(
+        about = "Centers a line",
+        long_about = "Centers each line within a given display width by padding both sides, placing any odd column of padding on the right."
+    )]
+    Center {
+        #[arg(
+            help = "Display width to center within (lines already at least this wide are left as-is)"
+        )]
+        width: usize,
+        #[arg(help = "Optional: character to pad with (defaults to a space)")]
+        fill: Option<char>,
+    },
+
+    #[command(
+        about = "Cuts a line to a display width",
+        long_about = "Cuts each line to the characters that fit within a given number of terminal columns, counting wide (e.g. CJK) characters as two, so mixed text can be clipped for fixed-width displays. A wide character that would straddle the limit is left out. With --ellipsis, lines that are cut end with the given text, which counts toward the width."
+    )]
+    CutToWidth {
+        #[arg(help = "Display width to cut to")]
+        width: usize,
+        #[arg(
+            help = "Text to end cut lines with, such as '…'",
+            long,
+            value_name = "TEXT"
+        )]
+        ellipsis: Option<String>,
+    },
+
+    #[command(
+        about = "Trims a display width from the start of a line",
+        long_about = "Trims (removes) the characters in the first given number of terminal columns of each line, counting wide (e.g. CJK) characters as two. A wide character that straddles the limit is trimmed too."
+    )]
+    TrimToWidth {
+        #[arg(help = "Display width to trim")]
+        width: usize,
+    },
+
+    #[command(
+        about = "Wraps long lines",
+        long_about = "Wraps each line at word boundaries so no output line is wider than a given display width. Words wider than the width are kept whole unless --hard is given."
+    )]
+    Wrap {
+        #[arg(help = "Maximum display width of each output line")]
+        width: usize,
+        #[arg(help = "Break words that don't fit within the width", long)]
+        hard: bool,
+    },
+
+    #[command(
+        about = "Converts tabs to spaces",
+        long_about = "Replaces each tab with the spaces needed to reach the next tab stop, counting columns by display width."
+    )]
+    Expand {
+        #[arg(help = "Distance between tab stops", default_value_t = 8)]
+        width: usize,
+    },
+
+    #[command(
+        about = "Converts spaces to tabs",
+        long_about = "Replaces runs of two or more spaces that end at a tab stop in the leading whitespace of each line (or anywhere with --all) with tabs. Tabs already in the line are expanded first, so their stops line up."
+    )]
+    Unexpand {
+        #[arg(help = "Distance between tab stops", default_value_t = 8)]
+        width: usize,
+        #[arg(
+            help = "Convert spaces anywhere in the line, not only leading ones",
+            long
+        )]
+        all: bool,
+    },
+
+    #[command(
+        about = "Collapses runs of whitespace",
+        long_about = "Collapses each run of spaces and tabs into a single space and trims them from both ends of the line. With --all-whitespace, every kind of Unicode whitespace is collapsed, including line breaks inside records read with --slurp or --paragraph."
+    )]
+    Squeeze {
+        #[arg(
+            help = "Collapse all Unicode whitespace, not only spaces and tabs",
+            long
+        )]
+        all_whitespace: bool,
+    },
+
+    #[command(
+        about = "Indents lines",
+        long_about = "Prefixes each non-empty line with N spaces, or N copies of another string with --with."
+    )]
+    Indent {
+        #[arg(help = "Number of times to repeat the indent")]
+        n: usize,
+        #[arg(
+            help = "Optional: string to indent with (defaults to a space)",
+            long = "with",
+            value_name = "STR",
+            default_value = " "
+        )]
+        with: String,
+    },
+
+    #[command(
+        about = "Removes indentation",
+        long_about = "Removes leading whitespace from each line: all of it, at most N characters, or with --strip-common the longest leading whitespace shared by every non-blank line, keeping relative indentation intact."
+    )]
+    Dedent {
+        #[arg(
+            help = "Optional: remove at most this many whitespace characters",
+            conflicts_with = "strip_common"
+        )]
+        n: Option<usize>,
+        #[arg(
+            help = "Remove only the leading whitespace all lines have in common (waits for the whole input)",
+            long
+        )]
+        strip_common: bool,
+    },
+
+    #[command(
+        about = "Removes the prefix shared by all lines",
+        long_about = "Finds the longest prefix every line starts with and removes it from each line, waiting for the whole input. With --delim, only whole segments ending in the delimiter are removed, e.g. whole directories of paths."
+    )]
+    StripCommonPrefix {
+        #[arg(
+            help = "Optional: only remove the shared prefix up to and including its last DELIM",
+            short,
+            long
+        )]
+        delim: Option<String>,
+    },
+
+    #[command(
+        about = "Removes the suffix shared by all lines",
+        long_about = "Finds the longest suffix every line ends with and removes it from each line, waiting for the whole input. With --delim, only whole segments starting with the delimiter are removed, e.g. a shared file extension."
+    )]
+    StripCommonSuffix {
+        #[arg(
+            help = "Optional: only remove the shared suffix from its first DELIM on",
+            short,
+            long
+        )]
+        delim: Option<String>,
+    },
+
+    #[command(
+        about = "Aligns fields into columns",
+        long_about = "Splits each line into fields and pads them so the fields of all lines line up in columns (like column -t), measuring display width so wide characters align. Waits for the whole input."
+    )]
+    Table {
+        #[arg(
+            help = "Optional: delimiter separating fields (defaults to whitespace)",
+            short,
+            long
+        )]
+        delim: Option<String>,
+    },
+
+    #[command(
+        about = "Swaps rows and columns",
+        long_about = "Treats the input as a matrix of fields and outputs one line per column, joined with the same delimiter (a space when splitting at whitespace). Missing fields of shorter lines are left empty. Waits for the whole input."
+    )]
+    Transpose {
+        #[arg(
+            help = "Optional: delimiter separating fields (defaults to whitespace)",
+            short,
+            long
+        )]
+        delim: Option<String>,
+    },
+
+    #[command(
+        about = "Rearranges fields with a template",
+        long_about = "Splits each line into fields and outputs a template filled in from them, where {0}, {1}, ... are fields (zero-based), {line} is the whole line, {n} its number in its input and {file} the input's name. Placeholders take a width and alignment such as {1:>8}, and fields a line doesn't have are left empty."
+    )]
+    Format {
+        #[arg(
+            help = "Template to output, e.g. '{2}-{1} ({0})'",
+            value_parser = |s: &str| Template::parse(s, &["#", "line", "n", "file"])
+        )]
+        template: Template,
+        #[arg(
+            help = "Optional: delimiter separating fields (defaults to whitespace)",
+            short,
+            long
+        )]
+        delim: Option<String>,
+    },
+}
+
+/// The encoding operations.
+#[derive(Subcommand)]
+pub enum EncodingOperation {
+    #[command(
+        about = "Encodes lines as base64",
+        long_about = "Encodes each line (or the whole input with --slurp) as base64."
+    )]
+    B64Encode {
+        #[arg(
+            help = "Use the URL- and filename-safe alphabet (- and _ instead of + and /)",
+            long
+        )]
+        url_safe: bool,
+        #[arg(help = "Leave out the trailing = padding", long)]
+        no_pad: bool,
+    },
+
+    #[command(
+        about = "Decodes base64 lines",
+        long_about = "Decodes each line (or the whole input with --slurp) from base64, with or without padding. Decoded bytes that aren't valid UTF-8 are output as hex, unless --lossy or --binary is given."
+    )]
+    B64Decode {
+        #[arg(
+            help = "Use the URL- and filename-safe alphabet (- and _ instead of + and /)",
+            long
+        )]
+        url_safe: bool,
+    },
+
+    #[command(
+        about = "Percent-encodes lines for URLs",
+        long_about = "Percent-encodes each line for use in a URL. By default every character other than letters, digits and -._~ is escaped, as a query parameter or path segment needs. With --full, characters that give a URL its structure (such as / ? & = :) are kept, so a whole URL can be encoded."
+    )]
+    UrlEncode {
+        #[arg(help = "Keep the reserved characters of a full URL", long)]
+        full: bool,
+    },
+
+    #[command(
+        about = "Decodes percent-encoded lines",
+        long_about = "Decodes percent-escapes in each line. With --full, escapes of characters that give a URL its structure (such as %2F for /) are kept, so the URL keeps its meaning. With --plus, + decodes to a space as in form data. Malformed escapes are left as they are. Decoded bytes that aren't valid UTF-8 are output as hex, unless --lossy or --binary is given."
+    )]
+    UrlDecode {
+        #[arg(help = "Keep escapes of the reserved characters of a full URL", long)]
+        full: bool,
+        #[arg(help = "Decode + as a space", long)]
+        plus: bool,
+    },
+
+    #[command(
+        about = "Escapes HTML special characters",
+        long_about = "Replaces & < > \" and ' in each line with HTML entities so the line can be placed in HTML text or a quoted attribute. With --ascii, non-ASCII characters are also written as numeric entities."
+    )]
+    HtmlEscape {
+        #[arg(help = "Also escape non-ASCII characters", long)]
+        ascii: bool,
+    },
+
+    #[command(
+        about = "Decodes HTML entities",
+        long_about = "Replaces named (&amp;, &eacute;, ...) and numeric (&#233;, &#xE9;) HTML entities in each line with the characters they stand for. Unknown entities are left as they are."
+    )]
+    HtmlUnescape,
+
+    #[command(
+        about = "Quotes each line as a single shell word",
+        long_about = "Wraps each line in single quotes, writing embedded single quotes as '\\'', so it can be pasted into a POSIX shell script as one word. With --minimal, lines made only of characters that are safe unquoted are left as they are."
+    )]
+    ShellQuote {
+        #[arg(help = "Leave lines that need no quoting unchanged", long)]
+        minimal: bool,
+    },
+
+    #[command(
+        about = "Removes shell quoting from each line",
+        long_about = "Reads each line as a POSIX shell word and writes its value, undoing single quotes, double quotes and backslash escapes. Whitespace outside quotes is kept as it is. A line with an unterminated quote is an error."
+    )]
+    ShellUnquote,
+
+    #[command(
+        about = "Escapes each line as a JSON string",
+        long_about = "Escapes quotes, backslashes and control characters in each line as a JSON string literal requires, writing \\n, \\t and the like where JSON has a short form and \\uXXXX otherwise. With --quote, the result is wrapped in double quotes."
+    )]
+    JsonEscape {
+        #[arg(help = "Wrap the result in double quotes", long)]
+        quote: bool,
+        #[arg(help = "Also escape non-ASCII characters as \\uXXXX", long)]
+        ascii: bool,
+    },
+
+    #[command(
+        about = "Decodes JSON string escapes",
+        long_about = "Decodes the escapes of a JSON string literal in each line, including \\uXXXX and surrogate pairs. Surrounding double quotes are removed if present. A line with an invalid escape is an error."
+    )]
+    JsonUnescape,
+
+    #[command(
+        about = "Writes special characters as backslash escapes",
+        long_about = "Writes backslashes, control characters and (with --ascii) non-ASCII characters in each line as backslash escapes: \\n, \\t and the like where there is a short form, \\xNN for other control characters and \\u{...} for non-ASCII characters. The result can be read back with unescape."
+    )]
+    Escape {
+        #[arg(help = "Also escape non-ASCII characters as \\u{...}", long)]
+        ascii: bool,
+    },
+
+    #[command(
+        about = "Interprets backslash escapes",
+        long_about = "Interprets backslash escapes in each line like printf %b or echo -e: \\n, \\t, \\r, \\\\, \\a, \\b, \\e, \\f, \\v, \\0NNN (octal), \\xNN (hex byte) and \\u{...} (Unicode code point). Unknown escapes are left as they are. Bytes that don't form valid UTF-8 are written as hex, unless --lossy or --binary is given."
+    )]
+    Unescape,
+
+    #[command(
+        about = "Escapes regex metacharacters",
+        long_about = "Escapes every regex metacharacter in each line, so the line matches itself literally when used as a pattern. By default the result is for str's own --regex mode; --flavor ere and --flavor bre escape for grep -E and plain grep or sed instead, which treat some escaped characters specially."
+    )]
+    RegexQuote {
+        #[arg(help = "Regex dialect to escape for", long, value_enum, default_value_t = RegexFlavor::Rust)]
+        flavor: RegexFlavor,
+    },
+
+    #[command(
+        about = "Hashes each line",
+        long_about = "Replaces each line with its hex digest under the given algorithm. With --slurp, the whole input is hashed at once (without its final newline). With --append, the digest is added after the line and a tab instead of replacing it."
+    )]
+    Hash {
+        #[arg(help = "Hash algorithm to use", value_enum)]
+        algorithm: HashAlgorithm,
+        #[arg(help = "Write the line, a tab and its digest", long)]
+        append: bool,
+    },
+}
+
+/// The numeric and whole-input operations.
+#[derive(Subcommand)]
+pub enum NumericOperation {
+    #[command(
+        about = "Computes the ratio between two fields",
+        long_about = "Divides one numeric field of each line by another and appends the result to the line (or outputs only the result with --substitute). Lines where either field is missing or not a number are left as-is, and dividing by zero gives n/a."
+    )]
+    Ratio {
+        #[arg(help = "Field to divide (zero-based)")]
+        field_a: usize,
+        #[arg(help = "Field to divide by (zero-based)")]
+        field_b: usize,
+        #[arg(
+            help = "How to present the result",
+            long,
+            value_enum,
+            default_value_t = RatioFormat::Ratio
+        )]
+        format: RatioFormat,
+        #[arg(help = "Number of decimal places to print", long, default_value_t = 2)]
+        precision: usize,
+        #[arg(
+            help = "Optional: delimiter separating fields (defaults to whitespace)",
+            short,
+            long
+        )]
+        delim: Option<String>,
+        #[arg(help = "Output only the result instead of appending it", long)]
+        substitute: bool,
+    },
+
+    #[command(
+        about = "Extracts the numbers in a line",
+        long_about = "Outputs every number found in each line, each on its own line or joined by --sep on one line, so the numbers of messy text can be fed to tools that expect one number per line. Numbers are found inside words too, as in 250ms, and a sign is only kept with --signed. Lines without numbers are dropped."
+    )]
+    #[command(group(ArgGroup::new("kind")))]
+    Numbers {
+        #[arg(help = "Only extract integers", long, group = "kind")]
+        ints: bool,
+        #[arg(
+            help = "Only extract numbers with a fractional part",
+            long,
+            group = "kind"
+        )]
+        floats: bool,
+        #[arg(
+            help = "Keep a leading - or + that isn't part of a word, as in -5 or -.5 but not 2024-05",
+            long
+        )]
+        signed: bool,
+        #[arg(
+            help = "Join the numbers with SEP on one line instead of outputting each on its own line",
+            long,
+            value_name = "SEP"
+        )]
+        sep: Option<String>,
+    },
+
+    #[command(
+        about = "Does arithmetic on the numbers in a line",
+        long_about = "Rewrites every number in each line (or in the field selected with --field) with the result of an arithmetic expression on it, such as *1024, +1 or round:2, so units can be converted inside text. Steps separated by commas are applied in order, as in /1024,round:1, and can add (+N), subtract (-N), multiply (*N), divide (/N), round to a number of decimal places (round:PLACES), floor, ceil or take the absolute value (abs). A - is only a sign when it doesn't follow a letter or digit, so x-5 holds 5, and numbers against words (v2, 12px), dates (2024-05-01), times (12:30:00) and versions (1.2.3) are left as-is."
+    )]
+    MapNum {
+        #[arg(
+            help = "Expression to apply, such as *1024, +1 or /1024,round:1",
+            allow_hyphen_values = true
+        )]
+        expr: NumExpr,
+    },
+
+    #[command(
+        about = "Reformats the numbers in a line",
+        long_about = "Rewrites every number in each line (or in the field selected with --field) with thousands separators, a fixed number of decimal places, zero padding to a width or in scientific notation. Text around the numbers is left as-is, and so are numbers against words (v2, 12px), dates (2024-05-01), times (12:30:00) and versions (1.2.3); a - is only a sign when it doesn't follow a letter or digit."
+    )]
+    NumFormat {
+        #[arg(
+            help = "Group the digits in thousands with a separator (defaults to a comma)",
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ",",
+            conflicts_with = "scientific"
+        )]
+        thousands: Option<String>,
+        #[arg(help = "Optional: number of decimal places to print", long)]
+        precision: Option<usize>,
+        #[arg(
+            help = "Optional: pad numbers with zeros after their sign to at least this width",
+            long
+        )]
+        width: Option<usize>,
+        #[arg(help = "Write numbers in scientific notation, such as 1.5e3", long)]
+        scientific: bool,
+    },
+
+    #[command(
+        about = "Measures each line",
+        long_about = "Computes a metric for each line, such as its Shannon entropy (in bits per character) or the proportion of its characters in a class. Given --min or --max, outputs only the lines whose metric falls within range instead of the metric itself."
+    )]
+    Metrics {
+        #[arg(help = "Metric to compute", value_enum)]
+        metric: Metric,
+        #[arg(
+            help = "Optional: only keep lines whose metric is at least this value",
+            long
+        )]
+        min: Option<f64>,
+        #[arg(
+            help = "Optional: only keep lines whose metric is at most this value",
+            long
+        )]
+        max: Option<f64>,
+    },
+
+    #[command(
+        about = "Reports statistics on line lengths",
+        long_about = "Reports the number of lines and the smallest, largest, mean and median line length in bytes, characters and display width (counting wide characters as two columns), for sizing fixed-width layouts or spotting unusual lines. With --histogram, also draws how many lines fall in each range of lengths in characters. Waits for the whole input."
+    )]
+    Stats {
+        #[arg(
+            help = "Draw a histogram of lengths in characters, in ranges of WIDTH (10 by default)",
+            long,
+            value_name = "WIDTH",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "10"
+        )]
+        histogram: Option<usize>,
+    },
+
+    #[command(
+        about = "Writes numbers as readable sizes, counts or durations",
+        long_about = "Rewrites every number in each line as a quantity read at a glance: a size in bytes such as 1.4 GiB (default), a count with an SI prefix such as 12.3k, or a duration such as 1h 30m. Numbers written with their unit, as in 1536B or 90061s, are converted too, while numbers against other words (12px), dates (2024-05-01), times (12:30:00) and versions (1.2.3) are left as-is. Dehumanize turns them back."
+    )]
+    #[command(group(ArgGroup::new("quantity")))]
+    Humanize {
+        #[arg(
+            help = "Numbers are bytes, in powers of 1024 such as 1.4 GiB (default)",
+            long,
+            group = "quantity"
+        )]
+        bytes: bool,
+        #[arg(
+            help = "Numbers are counts, in powers of 1000 such as 12.3k",
+            long,
+            group = "quantity"
+        )]
+        si: bool,
+        #[arg(
+            help = "Numbers are seconds, in days, hours, minutes and seconds such as 1h 30m",
+            long,
+            group = "quantity"
+        )]
+        duration: bool,
+    },
+
+    #[command(
+        about = "Turns readable sizes, counts or durations back into numbers",
+        long_about = "Rewrites every readable quantity in each line as the plain number it stands for, so it can be sorted or summed: sizes such as 1.4 GiB, 512K or 3 MB in bytes (default; KiB and a bare K are powers of 1024, kB and MB of 1000), counts such as 12.3k, or durations such as 1h 30m in seconds."
+    )]
+    #[command(group(ArgGroup::new("quantity")))]
+    Dehumanize {
+        #[arg(
+            help = "Read sizes in bytes, such as 1.4 GiB (default)",
+            long,
+            group = "quantity"
+        )]
+        bytes: bool,
+        #[arg(
+            help = "Read counts with SI prefixes, such as 12.3k",
+            long,
+            group = "quantity"
+        )]
+        si: bool,
+        #[arg(
+            help = "Read durations, such as 1h 30m, in seconds",
+            long,
+            group = "quantity"
+        )]
+        duration: bool,
+    },
+
+    #[command(
+        about = "Counts characters, words or matches",
+        long_about = "Counts the characters (default), bytes, words, grapheme clusters or pattern matches in each line, or across the whole input with --total."
+    )]
+    #[command(group(ArgGroup::new("unit")))]
+    Count {
+        #[arg(help = "Count whitespace-separated words", long, group = "unit")]
+        words: bool,
+        #[arg(help = "Count characters (default)", long, group = "unit")]
+        chars: bool,
+        #[arg(
+            help = "Count grapheme clusters (user-perceived characters)",
+            long,
+            group = "unit"
+        )]
+        graphemes: bool,
+        #[arg(help = "Count bytes", long, group = "unit")]
+        bytes: bool,
+        #[arg(
+            help = "Count matches of a pattern",
+            long,
+            value_name = "PATTERN",
+            group = "unit"
+        )]
+        matches: Option<Pattern>,
+        #[arg(
+            help = "Output a single count for the whole input instead of one per line",
+            long
+        )]
+        total: bool,
+    },
+}
+
+/// The operations on lists of lines.
+#[derive(Subcommand)]
+pub enum ListOperation {
+    #[command(
+        about = "Joins lines with a delimiter",
+        long_about = "Joins all input lines into a single line with a delimiter between each, the inverse of the split operations. --final-delim puts a different delimiter before the last line for natural-language lists such as 'a, b, and c'."
+    )]
+    Join {
+        #[arg(help = "Delimiter to put between lines")]
+        delim: String,
+        #[arg(
+            help = "Delimiter to put before the last line instead (e.g. ', and ')",
+            long,
+            value_name = "DELIM"
+        )]
+        final_delim: Option<String>,
+    },
+
+    #[command(
+        about = "Pairs lines with those of a file",
+        long_about = "Joins each input line with the line at the same position in FILE, separated by a tab or --delim (like paste). When one runs out first, the other's remaining lines are paired with empty lines."
+    )]
+    Zip {
+        #[arg(help = "File whose lines to pair the input with")]
+        file: PathBuf,
+        #[arg(
+            help = "Delimiter to put between the paired lines",
+            short,
+            long,
+            default_value = "\t"
+        )]
+        delim: String,
+    },
+
+    #[command(
+        about = "Keeps lines not in a file",
+        long_about = "Keeps only the input lines that aren't a line of FILE, comparing whole lines. Neither input needs to be sorted: FILE's lines are held in memory and the input is streamed."
+    )]
+    OnlyIn {
+        #[arg(help = "File of lines to drop")]
+        file: PathBuf,
+        #[arg(skip)]
+        set: OnceLock<HashSet<String>>,
+    },
+
+    #[command(
+        about = "Keeps lines also in a file",
+        long_about = "Keeps only the input lines that are also a line of FILE, comparing whole lines. Neither input needs to be sorted: FILE's lines are held in memory and the input is streamed."
+    )]
+    AlsoIn {
+        #[arg(help = "File of lines to keep")]
+        file: PathBuf,
+        #[arg(skip)]
+        set: OnceLock<HashSet<String>>,
+    },
+
+    #[command(
+        about = "Combines lines with those of a file",
+        long_about = "Outputs each distinct input line once, in input order, then the lines of FILE that weren't in the input. Neither input needs to be sorted, and the distinct input lines are held in memory."
+    )]
+    Union {
+        #[arg(help = "File of lines to add")]
+        file: PathBuf,
+    },
+
+    #[command(
+        about = "Repeats lines",
+        long_about = "Outputs each line N times, each on its own line or joined by --sep on one line. With --slurp, the whole input is repeated."
+    )]
+    Repeat {
+        #[arg(help = "Number of times to output each line")]
+        n: usize,
+        #[arg(
+            help = "Join the repetitions with SEP on one line instead of outputting each on its own line",
+            long,
+            value_name = "SEP"
+        )]
+        sep: Option<String>,
+    },
+
+    #[command(
+        about = "Reverses graphemes, words or lines",
+        long_about = "Reverses the user-perceived characters of each line (default), the order of the words in each line, or the order of all lines with --line-order (like tac). The flag isn't called --lines, since --lines selects the lines any operation applies to, so `str reverse --lines` would be read as a line selection."
+    )]
+    #[command(group(ArgGroup::new("unit")))]
+    Reverse {
+        #[arg(
+            help = "Reverse the order of words, keeping the whitespace between them",
+            long,
+            group = "unit"
+        )]
+        words: bool,
+        #[arg(help = "Reverse grapheme clusters (default)", long, group = "unit")]
+        graphemes: bool,
+        // --lines is taken by the global line selection
+        #[arg(
+            help = "Reverse the order of lines (not --lines, which selects lines for any operation)",
+            long,
+            group = "unit"
+        )]
+        line_order: bool,
+    },
+
+    #[command(
+        about = "Removes duplicate lines",
+        long_about = "Removes every repeat of a line seen earlier in the input, keeping the first, or with --adjacent only repeats that directly follow each other (like uniq)."
+    )]
+    Dedup {
+        #[arg(help = "Only remove consecutive duplicates", long)]
+        adjacent: bool,
+        #[arg(
+            help = "Prefix each line with the number of times it occurred (like uniq -c)",
+            long
+        )]
+        count: bool,
+    },
+
+    #[command(
+        about = "Removes near-duplicate lines",
+        long_about = "Removes every line at least --threshold similar to a line kept earlier, keeping the first, so repeats that differ only in a timestamp or an ID collapse into one. Similarity is one minus the edit distance between the lines divided by the length of the longer one. Every kept line is compared against, or with --adjacent only the last."
+    )]
+    DedupFuzzy {
+        #[arg(
+            help = "Similarity from 0 to 1 at which lines count as duplicates",
+            long,
+            default_value_t = 0.9,
+            value_parser = op_functions::fraction
+        )]
+        threshold: f64,
+        #[arg(help = "Only compare each line with the last line kept", long)]
+        adjacent: bool,
+    },
+
+    #[command(
+        about = "Sorts lines",
+        long_about = "Sorts all input lines, byte-wise by default, or by number, natural order or version. With --key, lines are ordered by one of their fields instead of the whole line; lines that compare equal keep their input order."
+    )]
+    #[command(group(ArgGroup::new("order")))]
+    Sort {
+        #[arg(
+            help = "Order by the number each line starts with",
+            long,
+            group = "order"
+        )]
+        numeric: bool,
+        #[arg(
+            help = "Order runs of digits by value (file2 before file10)",
+            long,
+            group = "order"
+        )]
+        natural: bool,
+        #[arg(
+            help = "Order by version number (1.9 before 1.10, 1.0-rc1 before 1.0)",
+            long,
+            group = "order"
+        )]
+        version: bool,
+        #[arg(help = "Reverse the order", short, long)]
+        reverse: bool,
+        #[arg(help = "Optional: field to sort by (zero-based)", short, long)]
+        key: Option<usize>,
+        #[arg(
+            help = "Optional: delimiter separating fields (defaults to whitespace)",
+            short,
+            long
+        )]
+        delim: Option<String>,
+    },
+
+    #[command(
+        about = "Counts how often each line occurs",
+        long_about = "Counts how often each distinct line, or each match of a pattern with --by, occurs in the input, and prints the counts from most to least frequent (like sort | uniq -c | sort -rn)."
+    )]
+    Freq {
+        #[arg(
+            help = "Optional: only print the N most frequent",
+            long,
+            value_name = "N"
+        )]
+        top: Option<usize>,
+        #[arg(
+            help = "Optional: count the matches of PATTERN instead of whole lines",
+            long,
+            value_name = "PATTERN"
+        )]
+        by: Option<Pattern>,
+    },
+
+    #[command(
+        about = "Counts the tokens of the input",
+        long_about = "Splits all input into whitespace-separated tokens, or the matches of --pattern, and prints how often each occurs and its share of all tokens, from most to least frequent (like tr -s ' ' '\\n' | sort | uniq -c | sort -rn). Tokens as frequent as each other stay in the order first seen."
+    )]
+    Tokens {
+        #[arg(
+            help = "Optional: only print the K most frequent",
+            long,
+            value_name = "K"
+        )]
+        top: Option<usize>,
+        #[arg(
+            help = "Optional: take the matches of PATTERN as the tokens",
+            long,
+            value_name = "PATTERN"
+        )]
+        pattern: Option<Pattern>,
+    },
+
+    #[command(
+        about = "Outputs the n-grams of a line",
+        long_about = "Outputs every run of N consecutive words (default) or characters in each line, each on its own line, sliding one word or character at a time. Words are separated by whitespace and joined with a single space. Lines with fewer than N words or characters are dropped."
+    )]
+    #[command(group(ArgGroup::new("unit")))]
+    Ngrams {
+        #[arg(help = "Number of words or characters in each n-gram")]
+        n: usize,
+        #[arg(
+            help = "Slide over whitespace-separated words (default)",
+            long,
+            group = "unit"
+        )]
+        words: bool,
+        #[arg(help = "Slide over characters", long, group = "unit")]
+        chars: bool,
+    },
+
+    #[command(
+        about = "Numbers lines",
+        long_about = "Prefixes each line with its number, formatted by a template in which {n} is the number and may carry a width and alignment such as {n:>6} or {n:03}."
+    )]
+    Number {
+        #[arg(
+            help = "Template for the prefix",
+            long,
+            default_value = "{n:>6}  ",
+            value_parser = |s: &str| Template::parse(s, &["n"])
+        )]
+        format: Template,
+        #[arg(help = "Number to give the first line", long, default_value_t = 1)]
+        start: usize,
+        #[arg(help = "Leave empty lines unnumbered", long)]
+        skip_empty: bool,
+    },
+
+    #[command(
+        about = "Outputs the first lines",
+        long_about = "Outputs only the first N lines of the input, and stops reading once they are through, so it ends endless input such as that of yes. Lines --lines, --if, --unless or --field pass through keep the whole input read."
+    )]
+    Head {
+        #[arg(help = "Number of lines to output")]
+        n: usize,
+    },
+
+    #[command(
+        about = "Outputs the last lines",
+        long_about = "Outputs only the last N lines of the input, holding no more than N lines at a time."
+    )]
+    Tail {
+        #[arg(help = "Number of lines to output")]
+        n: usize,
+    },
+
+    #[command(
+        about = "Outputs a random sample of the lines",
+        long_about = "Outputs N lines picked at random from the input, in input order, holding no more than N lines at a time however long the input (reservoir sampling). With --prob, each line is instead kept with the given probability as it is read, so output starts at once and the sample size varies."
+    )]
+    #[command(group(ArgGroup::new("size").required(true)))]
+    Sample {
+        #[arg(help = "Number of lines to output", group = "size")]
+        n: Option<usize>,
+        #[arg(
+            help = "Keep each line with this probability, between 0 and 1",
+            long,
+            value_parser = op_functions::fraction,
+            group = "size"
+        )]
+        prob: Option<f64>,
+    },
+
+    #[command(
+        about = "Shuffles lines",
+        long_about = "Outputs all input lines in random order. With --seed, the order is the same on every run with the same input, for reproducible tests and benchmarks. Waits for the whole input."
+    )]
+    Shuffle {
+        #[arg(help = "Optional: seed for the random order", long)]
+        seed: Option<u64>,
+    },
+}
+
+impl Default for Operation {
+    fn default() -> Self {
+        Operation::PatternBased(PatternOperation::SplitAtWhitespace {
+            number: None,
+            respect_quotes: false,
+        })
+    }
+}
+
+impl Operation {
+    /// The operations a preset or `run` runs, loaded on first use, or `None` for a single
+    /// operation.
+    pub fn pipeline(&self) -> Result<Option<&Pipeline>, String> {
+        use op_functions::get_or_try_init;
+
+        Ok(match self {
+            Operation::Preset {
+                name,
+                presets,
+                pipeline,
+            } => Some(get_or_try_init(pipeline, || {
+                Pipeline::preset(name, presets.as_deref())
+            })?),
+            Operation::Run { json, pipeline } => {
+                Some(get_or_try_init(pipeline, || Pipeline::json(json))?)
+            }
+            Operation::Stages(pipeline) => Some(pipeline),
+            _ => None,
+        })
+    }
+
+    pub fn execute(&self, input: &str) -> Result<Output, String> {
+        use EncodingOperation::*;
+        use FormattingOperation::*;
+        use IndexOperation::*;
+        use ListOperation::*;
+        use NumericOperation::*;
+        use Operation::*;
+        use PatternOperation::*;
+        use op_functions::*;
+
+        Ok(match self {
+            /* Pattern-Based */
+            PatternBased(SplitAtWhitespace {
+                number,
+                respect_quotes,
+            }) => split_at_whitespace(*number, *respect_quotes, input),
+            PatternBased(SplitAtPat {
+                number,
+                pattern,
+                respect_quotes,
+            }) => split_at_pat(*number, pattern, *respect_quotes, input),
+            PatternBased(SplitAtChar {
+                number,
+                char,
+                respect_quotes,
+            }) => split_at_char(*number, *char, *respect_quotes, input),
+            PatternBased(Segment { n, delimiter }) => segment(*n, delimiter.as_deref(), input),
+            PatternBased(Get {
+                key,
+                kv_sep,
+                pair_sep,
+            }) => get(key, kv_sep, pair_sep, input).map_or(Output::Skip, Output::Single),
+            PatternBased(Url { part }) => {
+                url_part(part, input).map_or(Output::Skip, Output::Single)
+            }
+            PatternBased(CutFromPat { pattern }) => cut_from_pat(pattern, input),
+            PatternBased(CutFromPatToPat {
+                start,
+                end,
+                all: true,
+            }) => all_pat_to_pat(start, end, input),
+            PatternBased(CutFromPatToPat { start, end, .. }) => {
+                cut_span(pat_to_pat(start, end, input), input)
+            }
+            PatternBased(CutFromPatToOffset { pattern, offset }) => {
+                cut_span(pat_to_offset(pattern, *offset, input), input)
+            }
+            PatternBased(CutUntilPat {
+                pattern,
+                patterns,
+                set,
+            }) => cut_until_pat(pattern_set(set, pattern, patterns)?, input),
+            PatternBased(TrimFromPat {
+                pattern,
+                patterns,
+                set,
+            }) => trim_from_pat(pattern_set(set, pattern, patterns)?, input),
+            PatternBased(TrimFromPatToPat { start, end }) => {
+                trim_span(pat_to_pat(start, end, input), input)
+            }
+            PatternBased(TrimUntilPat { pattern }) => trim_until_pat(pattern, input),
+            PatternBased(TrimToPat { pattern }) => trim_to_pat(pattern, input),
+            PatternBased(Filter {
+                pattern,
+                patterns,
+                invert,
+                set,
+            }) => filter(pattern_set(set, pattern, patterns)?, *invert, input),
+            PatternBased(Match { pattern, all }) => match_pattern(pattern, *all, input),
+            PatternBased(Between {
+                open,
+                close,
+                balanced,
+            }) => between(open, close, *balanced, input),
+            PatternBased(Fuzzy {
+                pattern,
+                max_dist,
+                whole_line,
+                positions,
+            }) => fuzzy(pattern, *max_dist, *whole_line, *positions, input),
+            PatternBased(Trim { pattern }) => trim(pattern, input),
+            PatternBased(Replace {
+                patterns,
+                with,
+                number,
+                set,
+            }) => replace(pattern_set(set, patterns, &[])?, with, *number, input),
+            PatternBased(Remove {
+                pattern,
+                patterns,
+                number,
+                set,
+            }) => replace(pattern_set(set, pattern, patterns)?, "", *number, input),
+            PatternBased(ReplaceMany { pairs, map, table }) => replace_many(
+                get_or_try_init(table, || replacement_table(pairs, map.as_deref()))?,
+                input,
+            ),
+            PatternBased(Highlight { patterns }) => highlight(patterns, input),
+            PatternBased(Redact {
+                patterns,
+                preset,
+                mask,
+                hash: algorithm,
+            }) => {
+                let replace = |text: &str| match algorithm {
+                    Some(algorithm) => hash(*algorithm, text),
+                    None => mask.clone(),
+                };
+                Output::Single(crate::redact::redact(preset, patterns, replace, input))
+            }
+
+            /* Index-Based */
+            IndexBased(SplitAtIndex { index }) => Output::Multiple({
+                let (a, b) = input.split_at(from_index(*index).clamp(input).start);
+                vec![a.into(), b.into()]
+            }),
+            IndexBased(CutFromIndex { index }) => cut_span(from_index(*index), input),
+            IndexBased(CutFromIndexToIndex { start, end }) => {
+                cut_span(Span::new(*start as i64, Some(*end as i64)), input)
+            }
+            IndexBased(CutFromIndexToOffset { index, offset }) => {
+                cut_span(index_to_offset(*index, *offset), input)
+            }
+            IndexBased(CutUntilIndex { index }) => cut_span(until_index(*index), input),
+            // trimming from an index keeps what comes before it
+            IndexBased(TrimFromIndex { index }) => cut_span(until_index(*index), input),
+            IndexBased(TrimFromIndexToIndex { start, end }) => {
+                trim_span(Span::new(*start as i64, Some(*end as i64)), input)
+            }
+            IndexBased(TrimFromIndexToOffset { index, offset }) => {
+                trim_span(index_to_offset(*index, *offset), input)
+            }
+            IndexBased(TrimUntilIndex { index }) => cut_span(from_index(*index), input),
+
+            /* Mixed */
+            CutFromPatToIndex { pattern, index } => {
+                cut_span(pat_to_index(pattern, *index, input), input)
+            }
+            CutFromIndexToPat { index, pattern } => {
+                cut_span(index_to_pat(*index, pattern, input), input)
+            }
+            TrimFromPatToIndex { pattern, index } => {
+                trim_span(pat_to_index(pattern, *index, input), input)
+            }
+            TrimFromIndexToPat { index, pattern } => {
+                trim_span(index_to_pat(*index, pattern, input), input)
+            }
+
+            /* Formatting */
+            Formatting(PadLeft { width, fill }) => pad_left(*width, *fill, input),
+            Formatting(PadRight { width, fill }) => pad_right(*width, *fill, input),
+            Formatting(Center { width, fill }) => center(*width, *fill, input),
+            Formatting(CutToWidth { width, ellipsis }) => {
+                Output::Single(cut_to_width(*width, ellipsis.as_deref(), input))
+            }
+            Formatting(TrimToWidth { width }) => {
+                Output::Single(input[width_boundary(*width, true, input)..].to_string())
+            }
+            Formatting(Wrap { width, hard }) => wrap(*width, *hard, input),
+            Formatting(Expand { width }) => Output::Single(expand(*width, input)),
+            Formatting(Unexpand { width, all }) => Output::Single(unexpand(*width, *all, input)),
+            Formatting(Squeeze { all_whitespace }) => {
+                Output::Single(squeeze(*all_whitespace, input))
+            }
+            Formatting(Indent { n, with }) => Output::Single(indent(*n, with, input)),
+            Formatting(Dedent { n, .. }) => Output::Single(dedent(*n, input).to_string()),
+            Formatting(StripCommonPrefix { delim }) => {
+                Output::Multiple(strip_common(&[input.to_string()], false, delim.as_deref()))
+            }
+            Formatting(StripCommonSuffix { delim }) => {
+                Output::Multiple(strip_common(&[input.to_string()], true, delim.as_deref()))
+            }
+            Formatting(Table { delim }) => {
+                Output::Multiple(table(&[input.to_string()], delim.as_deref()))
+            }
+            Formatting(Transpose { delim }) => {
+                Output::Multiple(transpose(&[input.to_string()], delim.as_deref()))
+            }
+            Formatting(Format { template, delim }) => {
+                Output::Single(format(template, delim.as_deref(), (1, ""), input))
+            }
+
+            /* Encoding */
+            Encoding(B64Encode { url_safe, no_pad }) => {
+                Output::Single(b64_encode(*url_safe, *no_pad, input))
+            }
+            Encoding(B64Decode { url_safe }) => Output::Single(b64_decode(*url_safe, input)?),
+
+            Encoding(UrlEncode { full }) => Output::Single(url_encode(*full, input)),
+            Encoding(UrlDecode { full, plus }) => Output::Single(url_decode(*full, *plus, input)),
+            Encoding(HtmlEscape { ascii }) => Output::Single(html_escape(*ascii, input)),
+            Encoding(HtmlUnescape) => {
+                Output::Single(html_escape::decode_html_entities(input).into_owned())
+            }
+            Encoding(ShellQuote { minimal }) => Output::Single(shell_quote(*minimal, input)),
+            Encoding(ShellUnquote) => Output::Single(shell_unquote(input)?),
+            Encoding(JsonEscape { quote, ascii }) => {
+                Output::Single(json_escape(*quote, *ascii, input))
+            }
+            Encoding(JsonUnescape) => Output::Single(json_unescape(input)?),
+            Encoding(Escape { ascii }) => Output::Single(escape(*ascii, input)),
+            Encoding(Unescape) => Output::Single(unescape(input)),
+            Encoding(RegexQuote { flavor }) => Output::Single(regex_quote(*flavor, input)),
+            Encoding(Hash { algorithm, append }) => Output::Single(match append {
+                true => format!("{input}\t{}", hash(*algorithm, input)),
+                false => hash(*algorithm, input),
+            }),
+
+            /* Character Mapping */
+            Rot { n } => Output::Single(map_chars(input, |c| rotate(*n, c))),
+            Slug { separator } => Output::Single(slug(separator, input)),
+            Deunicode { unknown } => Output::Single(deunicode::deunicode_with_tofu(input, unknown)),
+
+            /* Paths */
+            Basename => Output::Single(basename(input).to_string()),
+            Dirname => Output::Single(dirname(input)),
+            Ext => Output::Single(
+                split_ext(split_path(input).1)
+                    .1
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            Stem => Output::Single(split_ext(split_path(input).1).0.to_string()),
+            WithExt { ext } => Output::Single(with_ext(ext, input)),
+
+            /* Time */
+            Time { from, to, tz } => {
+                Output::Single(crate::time::convert(from.as_ref(), to, tz, input))
+            }
+
+            /* Numeric */
+            Numeric(Ratio {
+                field_a,
+                field_b,
+                format,
+                precision,
+                delim,
+                substitute,
+            }) => ratio(
+                (*field_a, *field_b),
+                *format,
+                *precision,
+                delim.as_deref(),
+                *substitute,
+                input,
+            ),
+            Numeric(Metrics { metric, min, max }) => metrics(*metric, *min, *max, input),
+            Numeric(Stats { histogram }) => {
+                Output::Multiple(line_stats(&mut [lengths(input)], *histogram))
+            }
+            Numeric(Numbers {
+                ints,
+                floats,
+                signed,
+                sep,
+            }) => {
+                let numbers = crate::numbers::extract(number_kind(*ints, *floats), *signed, input);
+                match sep {
+                    _ if numbers.is_empty() => Output::Skip,
+                    Some(sep) => Output::Single(numbers.join(sep.as_str())),
+                    None => Output::Multiple(numbers.into_iter().map(str::to_string).collect()),
+                }
+            }
+            Numeric(MapNum { expr }) => Output::Single(crate::numbers::map(expr, input)),
+            Numeric(NumFormat {
+                thousands,
+                precision,
+                width,
+                scientific,
+            }) => Output::Single(crate::numbers::reformat(
+                &crate::numbers::NumFormat {
+                    thousands: thousands.clone(),
+                    precision: *precision,
+                    width: *width,
+                    scientific: *scientific,
+                },
+                input,
+            )),
+            Numeric(Humanize { si, duration, .. }) => {
+                Output::Single(crate::numbers::humanize(quantity(*si, *duration), input))
+            }
+            Numeric(Dehumanize { si, duration, .. }) => {
+                Output::Single(crate::numbers::dehumanize(quantity(*si, *duration), input))
+            }
+            Numeric(Count {
+                words,
+                graphemes,
+                bytes,
+                matches,
+                ..
+            }) => Output::Single(
+                count(*words, *graphemes, *bytes, matches.as_ref(), input).to_string(),
+            ),
+
+            /* Lists */
+            Lists(Join { .. }) => Output::Single(input.to_string()), // a single line joins to itself
+            Lists(OnlyIn { file, set }) => {
+                match get_or_try_init(set, || line_set(file))?.contains(input) {
+                    true => Output::Skip,
+                    false => Output::Single(input.to_string()),
+                }
+            }
+            Lists(AlsoIn { file, set }) => {
+                match get_or_try_init(set, || line_set(file))?.contains(input) {
+                    true => Output::Single(input.to_string()),
+                    false => Output::Skip,
+                }
+            }
+            Lists(Union { .. }) => Output::Single(input.to_string()),
+            // each line is paired with the next of the file, which only its aggregate keeps track of
+            Lists(Zip { .. }) => return Err("zip can't be applied to lines one at a time".into()),
+            Lists(Repeat { n, sep }) => repeat(*n, sep.as_deref(), input),
+            Lists(Dedup { count, .. }) => match count {
+                true => Output::Single(counted(1, input)),
+                false => Output::Single(input.to_string()), // a single line has no duplicates
+            },
+            Lists(DedupFuzzy { .. }) => Output::Single(input.to_string()), // a single line has no duplicates
+            Lists(Sort { .. }) => Output::Single(input.to_string()), // a single line is already sorted
+            Lists(Number { format, start, .. }) => Output::Single(number(format, *start, input)),
+            Lists(Head { n }) | Lists(Tail { n }) => match n {
+                0 => Output::Skip,
+                _ => Output::Single(input.to_string()),
+            },
+            Lists(Shuffle { .. }) => Output::Single(input.to_string()), // a single line shuffles to itself
+            Lists(Sample { n, prob }) => match (n, prob) {
+                (Some(0), _) => Output::Skip,
+                (_, Some(prob)) if fastrand::f64() >= *prob => Output::Skip,
+                _ => Output::Single(input.to_string()),
+            },
+            Lists(Ngrams { n, chars, .. }) => ngrams(*n, *chars, input),
+            Lists(Tokens { pattern, .. }) => {
+                let words = words(pattern.as_ref(), input);
+                let total = words.len();
+                Output::Multiple(
+                    words
+                        .into_iter()
+                        .map(|word| token_share(1, total, word))
+                        .collect(),
+                )
+            }
+            Lists(Freq { by, .. }) => Output::Multiple(
+                tokens(by.as_ref(), input)
+                    .into_iter()
+                    .map(|token| counted(1, token))
+                    .collect(),
+            ),
+            Lists(Reverse {
+                words, line_order, ..
+            }) => match line_order {
+                true => Output::Single(input.to_string()), // a single line reverses to itself
+                false => Output::Single(reverse(*words, input)),
+            },
+
+            /* Predicates */
+            Is { predicate, quiet } => match quiet {
+                true => Output::Skip,
+                false => Output::Single(is(predicate, input).to_string()),
+            },
+
+            /* Pipelines */
+            Preset { .. } | Run { .. } | Stages(_) => match self.pipeline()? {
+                Some(pipeline) => pipeline.execute(input)?,
+                None => Output::Skip,
+            },
+            Script { script, delim } => {
+                op_functions::script(script, delim.as_deref(), (1, ""), input)?
+            }
+            Tui => Output::Skip, // run by main instead of reading the input
+
+            /* Help */
+            Examples { .. } => Output::Skip, // printed by main before any input is read
+        })
+    }
+
+    /// Whether the patterns the operation is anchored on occur in `input`. Operations that
+    /// aren't anchored on a pattern always match.
+    pub fn matches(&self, input: &str) -> Result<bool, String> {
+        use ListOperation::*;
+        use NumericOperation::*;
+        use Operation::*;
+        use PatternOperation::*;
+        use op_functions::get_or_try_init;
+
+        Ok(match self {
+            PatternBased(SplitAtPat { pattern, .. })
+            | PatternBased(CutFromPat { pattern })
+            | PatternBased(CutFromPatToOffset { pattern, .. })
+            | PatternBased(TrimUntilPat { pattern })
+            | PatternBased(TrimToPat { pattern })
+            | CutFromPatToIndex { pattern, .. }
+            | CutFromIndexToPat { pattern, .. }
+            | TrimFromPatToIndex { pattern, .. }
+            | TrimFromIndexToPat { pattern, .. } => pattern.is_match(input),
+            PatternBased(SplitAtChar { char, .. }) => input.contains(*char),
+            PatternBased(CutFromPatToPat { start, end, .. })
+            | PatternBased(TrimFromPatToPat { start, end }) => {
+                start.is_match(input) && end.is_match(input)
+            }
+            PatternBased(Trim {
+                pattern: Some(pattern),
+            }) => pattern.is_prefix_of(input) || pattern.is_suffix_of(input),
+            PatternBased(CutUntilPat {
+                pattern,
+                patterns,
+                set,
+            })
+            | PatternBased(TrimFromPat {
+                pattern,
+                patterns,
+                set,
+            }) => op_functions::pattern_set(set, pattern, patterns)?.is_match(input),
+            PatternBased(Remove {
+                pattern,
+                patterns,
+                set,
+                ..
+            }) => op_functions::pattern_set(set, pattern, patterns)?.is_match(input),
+            PatternBased(Replace { patterns, set, .. }) => {
+                op_functions::pattern_set(set, patterns, &[])?.is_match(input)
+            }
+            PatternBased(Highlight { patterns }) => patterns.iter().any(|p| p.is_match(input)),
+            PatternBased(Redact {
+                patterns, preset, ..
+            }) => !crate::redact::sensitive(preset, patterns, input).is_empty(),
+            PatternBased(Match { pattern, .. }) => pattern.is_match(input),
+            PatternBased(Between { open, close, .. }) => {
+                open.is_match(input) && close.is_match(input)
+            }
+            PatternBased(Fuzzy { .. }) => !matches!(self.execute(input)?, Output::Skip),
+            PatternBased(Get {
+                key,
+                kv_sep,
+                pair_sep,
+            }) => op_functions::get(key, kv_sep, pair_sep, input).is_some(),
+            PatternBased(Url { part }) => op_functions::url_part(part, input).is_some(),
+            Lists(OnlyIn { file, set }) => {
+                !get_or_try_init(set, || op_functions::line_set(file))?.contains(input)
+            }
+            Lists(AlsoIn { file, set }) => {
+                get_or_try_init(set, || op_functions::line_set(file))?.contains(input)
+            }
+            Numeric(Numbers { ints, floats, .. }) => {
+                !crate::numbers::extract(op_functions::number_kind(*ints, *floats), false, input)
+                    .is_empty()
+            }
+            PatternBased(ReplaceMany { pairs, map, table }) => get_or_try_init(table, || {
+                op_functions::replacement_table(pairs, map.as_deref())
+            })?
+            .0
+            .is_match(input),
+            _ => true,
+        })
+    }
+
+    /// Every pattern the operation looks for.
+    pub fn patterns(&self) -> Vec<&Pattern> {
+        use ListOperation::*;
+        use NumericOperation::*;
+        use Operation::*;
+        use PatternOperation::*;
+
+        match self {
+            PatternBased(SplitAtPat { pattern, .. })
+            | PatternBased(CutFromPat { pattern })
+            | PatternBased(CutFromPatToOffset { pattern, .. })
+            | PatternBased(TrimUntilPat { pattern })
+            | PatternBased(TrimToPat { pattern })
+            | PatternBased(Match { pattern, .. })
+            | CutFromPatToIndex { pattern, .. }
+            | CutFromIndexToPat { pattern, .. }
+            | TrimFromPatToIndex { pattern, .. }
+            | TrimFromIndexToPat { pattern, .. } => vec![pattern],
+            PatternBased(CutFromPatToPat { start, end, .. })
+            | PatternBased(TrimFromPatToPat { start, end }) => {
+                vec![start, end]
+            }
+            PatternBased(Between { open, close, .. }) => vec![open, close],
+            PatternBased(CutUntilPat {
+                pattern, patterns, ..
+            })
+            | PatternBased(TrimFromPat {
+                pattern, patterns, ..
+            })
+            | PatternBased(Filter {
+                pattern, patterns, ..
+            }) => pattern.iter().chain(patterns).collect(),
+            PatternBased(Remove {
+                pattern, patterns, ..
+            }) => pattern.iter().chain(patterns).collect(),
+            PatternBased(Replace { patterns, .. })
+            | PatternBased(Highlight { patterns })
+            | PatternBased(Redact { patterns, .. }) => patterns.iter().collect(),
+            PatternBased(Trim { pattern })
+            | Numeric(Count {
+                matches: pattern, ..
+            })
+            | Lists(Freq { by: pattern, .. })
+            | Lists(Tokens { pattern, .. }) => pattern.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The operation outputting the complement of what this one keeps (`--invert`): each cut as
+    /// the trim of the same range and the reverse, or filter keeping the lines it would drop.
+    pub fn inverted(self) -> Result<Operation, String> {
+        use FormattingOperation::*;
+        use IndexOperation::*;
+        use Operation::*;
+        use PatternOperation::*;
+
+        Ok(match self {
+            PatternBased(CutFromPat { pattern }) => PatternBased(TrimFromPat {
+                pattern: Some(pattern),
+                patterns: Vec::new(),
+                set: OnceLock::new(),
+            }),
+            PatternBased(TrimFromPat {
+                pattern: Some(pattern),
+                patterns,
+                ..
+            }) if patterns.is_empty() => PatternBased(CutFromPat { pattern }),
+            PatternBased(CutUntilPat {
+                pattern: Some(pattern),
+                patterns,
+                ..
+            }) if patterns.is_empty() => PatternBased(TrimUntilPat { pattern }),
+            PatternBased(TrimUntilPat { pattern }) => PatternBased(CutUntilPat {
+                pattern: Some(pattern),
+                patterns: Vec::new(),
+                set: OnceLock::new(),
+            }),
+            PatternBased(CutFromPatToPat {
+                start,
+                end,
+                all: false,
+            }) => PatternBased(TrimFromPatToPat { start, end }),
+            PatternBased(TrimFromPatToPat { start, end }) => PatternBased(CutFromPatToPat {
+                start,
+                end,
+                all: false,
+            }),
+            // at index zero each of these keeps the whole line, so the complement keeps none of it
+            IndexBased(
+                CutFromIndex { index: 0 }
+                | TrimFromIndex { index: 0 }
+                | CutUntilIndex { index: 0 }
+                | TrimUntilIndex { index: 0 },
+            ) => IndexBased(CutFromIndexToIndex { start: 0, end: 0 }),
+            IndexBased(CutFromIndex { index }) => IndexBased(TrimFromIndex { index }),
+            IndexBased(TrimFromIndex { index }) => IndexBased(CutFromIndex { index }),
+            IndexBased(CutUntilIndex { index }) => IndexBased(TrimUntilIndex { index }),
+            IndexBased(TrimUntilIndex { index }) => IndexBased(CutUntilIndex { index }),
+            IndexBased(CutFromIndexToIndex { start, end }) => {
+                IndexBased(TrimFromIndexToIndex { start, end })
+            }
+            IndexBased(TrimFromIndexToIndex { start, end }) => {
+                IndexBased(CutFromIndexToIndex { start, end })
+            }
+            IndexBased(CutFromIndexToOffset { index, offset }) => {
+                IndexBased(TrimFromIndexToOffset { index, offset })
+            }
+            IndexBased(TrimFromIndexToOffset { index, offset }) => {
+                IndexBased(CutFromIndexToOffset { index, offset })
+            }
+            CutFromPatToIndex { pattern, index } => TrimFromPatToIndex { pattern, index },
+            TrimFromPatToIndex { pattern, index } => CutFromPatToIndex { pattern, index },
+            CutFromIndexToPat { index, pattern } => TrimFromIndexToPat { index, pattern },
+            TrimFromIndexToPat { index, pattern } => CutFromIndexToPat { index, pattern },
+            Formatting(CutToWidth { width, .. }) => Formatting(TrimToWidth { width }),
+            Formatting(TrimToWidth { width }) => Formatting(CutToWidth {
+                width,
+                ellipsis: None,
+            }),
+            PatternBased(Filter {
+                pattern,
+                patterns,
+                invert,
+                set,
+            }) => PatternBased(Filter {
+                pattern,
+                patterns,
+                invert: !invert,
+                set,
+            }),
+            PatternBased(CutUntilPat { .. }) | PatternBased(TrimFromPat { .. }) => {
+                return Err("--invert can't be used with more than one pattern".to_string());
+            }
+            PatternBased(CutFromPatToPat { .. }) => {
+                return Err("--invert can't be used with --all".to_string());
+            }
+            _ => {
+                return Err(
+                    "--invert only applies to cut and trim operations and filter".to_string(),
+                );
+            }
+        })
+    }
+
+    /// The span between the two anchors of a range operation on `input`, or `None` for
+    /// operations that don't take a range.
+    pub fn span(&self, input: &str) -> Option<Span> {
+        use IndexOperation::*;
+        use Operation::*;
+        use PatternOperation::*;
+        use op_functions::*;
+
+        Some(match self {
+            // every span is taken whole, so there is no one range to check
+            PatternBased(CutFromPatToPat { all: true, .. }) => return None,
+            PatternBased(CutFromPatToPat { start, end, .. })
+            | PatternBased(TrimFromPatToPat { start, end }) => pat_to_pat(start, end, input),
+            PatternBased(CutFromPatToOffset { pattern, offset }) => {
+                pat_to_offset(pattern, *offset, input)
+            }
+            IndexBased(SplitAtIndex { index })
+            | IndexBased(CutFromIndex { index })
+            | IndexBased(TrimUntilIndex { index }) => from_index(*index),
+            IndexBased(CutUntilIndex { index }) | IndexBased(TrimFromIndex { index }) => {
+                until_index(*index)
+            }
+            IndexBased(CutFromIndexToIndex { start, end })
+            | IndexBased(TrimFromIndexToIndex { start, end }) => {
+                Span::new(*start as i64, Some(*end as i64))
+            }
+            IndexBased(CutFromIndexToOffset { index, offset })
+            | IndexBased(TrimFromIndexToOffset { index, offset }) => {
+                index_to_offset(*index, *offset)
+            }
+            CutFromPatToIndex { pattern, index } | TrimFromPatToIndex { pattern, index } => {
+                pat_to_index(pattern, *index, input)
+            }
+            CutFromIndexToPat { index, pattern } | TrimFromIndexToPat { index, pattern } => {
+                index_to_pat(*index, pattern, input)
+            }
+            _ => return None,
+        })
+    }
+
+    /// The operation as it is stored in a config file or sent as JSON, from which it can be
+    /// parsed again.
+    pub fn definition(&self) -> Definition {
+        use Operation::*;
+        use {
+            EncodingOperation::*, FormattingOperation::*, IndexOperation::*, ListOperation::*,
+            NumericOperation::*, PatternOperation::*,
+        };
+
+        // an operation's definition from the words each of its fields is written as
+        macro_rules! define {
+            ($op:literal $(, $field:ident)*) => {
+                Definition::new($op, vec![$((stringify!($field), $field.words())),*])
+            };
+        }
+
+        match self {
+            /* Pattern-Based */
+            PatternBased(SplitAtWhitespace {
+                number,
+                respect_quotes,
+            }) => define!("split-at-whitespace", number, respect_quotes),
+            PatternBased(SplitAtPat {
+                pattern,
+                number,
+                respect_quotes,
+            }) => define!("split-at-pat", pattern, number, respect_quotes),
+            PatternBased(SplitAtChar {
+                char,
+                number,
+                respect_quotes,
+            }) => define!("split-at-char", char, number, respect_quotes),
+            PatternBased(Segment { n, delimiter }) => define!("segment", n, delimiter),
+            PatternBased(Get {
+                key,
+                kv_sep,
+                pair_sep,
+            }) => define!("get", key, kv_sep, pair_sep),
+            PatternBased(Url { part }) => define!("url", part),
+            PatternBased(CutFromPat { pattern }) => define!("cut-from-pat", pattern),
+            PatternBased(CutFromPatToPat { start, end, all }) => {
+                define!("cut-from-pat-to-pat", start, end, all)
+            }
+            PatternBased(CutFromPatToOffset { pattern, offset }) => {
+                define!("cut-from-pat-to-offset", pattern, offset)
+            }
+            PatternBased(CutUntilPat {
+                pattern, patterns, ..
+            }) => define!("cut-until-pat", pattern, patterns),
+            PatternBased(TrimFromPat {
+                pattern, patterns, ..
+            }) => define!("trim-from-pat", pattern, patterns),
+            PatternBased(TrimFromPatToPat { start, end }) => {
+                define!("trim-from-pat-to-pat", start, end)
+            }
+            PatternBased(TrimUntilPat { pattern }) => define!("trim-until-pat", pattern),
+            PatternBased(TrimToPat { pattern }) => define!("trim-to-pat", pattern),
+            PatternBased(Filter {
+                pattern,
+                patterns,
+                invert,
+                ..
+            }) => {
+                let mut definition = define!("filter", pattern, patterns);
+                // --invert is taken by every stage
+                if *invert {
+                    definition
+                        .options
+                        .insert("invert".to_string(), OptionValue::Flag(true));
+                }
+                definition
+            }
+            PatternBased(Match { pattern, all }) => define!("match", pattern, all),
+            PatternBased(Between {
+                open,
+                close,
+                balanced,
+            }) => define!("between", open, close, balanced),
+            PatternBased(Fuzzy {
+                pattern,
+                max_dist,
+                whole_line,
+                positions,
+            }) => define!("fuzzy", pattern, max_dist, whole_line, positions),
+            PatternBased(Trim { pattern }) => define!("trim", pattern),
+            PatternBased(Replace {
+                patterns,
+                with,
+                number,
+                ..
+            }) => define!("replace", patterns, with, number),
+            PatternBased(Remove {
+                pattern,
+                patterns,
+                number,
+                ..
+            }) => define!("remove", pattern, patterns, number),
+            PatternBased(ReplaceMany { pairs, map, .. }) => define!("replace-many", pairs, map),
+            PatternBased(Highlight { patterns }) => define!("highlight", patterns),
+            PatternBased(Redact {
+                patterns,
+                preset,
+                mask,
+                hash,
+            }) => define!("redact", patterns, preset, mask, hash),
+
+            /* Index-Based */
+            IndexBased(SplitAtIndex { index }) => define!("split-at-index", index),
+            IndexBased(CutFromIndex { index }) => define!("cut-from-index", index),
+            IndexBased(CutFromIndexToIndex { start, end }) => {
+                define!("cut-from-index-to-index", start, end)
+            }
+            IndexBased(CutFromIndexToOffset { index, offset }) => {
+                define!("cut-from-index-to-offset", index, offset)
+            }
+            IndexBased(CutUntilIndex { index }) => define!("cut-until-index", index),
+            IndexBased(TrimFromIndex { index }) => define!("trim-from-index", index),
+            IndexBased(TrimFromIndexToIndex { start, end }) => {
+                define!("trim-from-index-to-index", start, end)
+            }
+            IndexBased(TrimFromIndexToOffset { index, offset }) => {
+                define!("trim-from-index-to-offset", index, offset)
+            }
+            IndexBased(TrimUntilIndex { index }) => define!("trim-until-index", index),
+
+            /* Mixed */
+            CutFromPatToIndex { pattern, index } => {
+                define!("cut-from-pat-to-index", pattern, index)
+            }
+            CutFromIndexToPat { index, pattern } => {
+                define!("cut-from-index-to-pat", index, pattern)
+            }
+            TrimFromPatToIndex { pattern, index } => {
+                define!("trim-from-pat-to-index", pattern, index)
+            }
+            TrimFromIndexToPat { index, pattern } => {
+                define!("trim-from-index-to-pat", index, pattern)
+            }
+
+            /* Formatting */
+            Formatting(PadLeft { width, fill }) => define!("pad-left", width, fill),
+            Formatting(PadRight { width, fill }) => define!("pad-right", width, fill),
+            Formatting(Center { width, fill }) => define!("center", width, fill),
+            Formatting(CutToWidth { width, ellipsis }) => define!("cut-to-width", width, ellipsis),
+            Formatting(TrimToWidth { width }) => define!("trim-to-width", width),
+            Formatting(Wrap { width, hard }) => define!("wrap", width, hard),
+            Formatting(Expand { width }) => define!("expand", width),
+            Formatting(Unexpand { width, all }) => define!("unexpand", width, all),
+            Formatting(Squeeze { all_whitespace }) => define!("squeeze", all_whitespace),
+            Formatting(Indent { n, with }) => define!("indent", n, with),
+            Formatting(Dedent { n, strip_common }) => define!("dedent", n, strip_common),
+            Formatting(StripCommonPrefix { delim }) => define!("strip-common-prefix", delim),
+            Formatting(StripCommonSuffix { delim }) => define!("strip-common-suffix", delim),
+            Formatting(Table { delim }) => define!("table", delim),
+            Formatting(Transpose { delim }) => define!("transpose", delim),
+            Formatting(Format { template, delim }) => define!("format", template, delim),
+
+            /* Encoding */
+            Encoding(B64Encode { url_safe, no_pad }) => define!("b64-encode", url_safe, no_pad),
+            Encoding(B64Decode { url_safe }) => define!("b64-decode", url_safe),
+            Encoding(UrlEncode { full }) => define!("url-encode", full),
+            Encoding(UrlDecode { full, plus }) => define!("url-decode", full, plus),
+            Encoding(HtmlEscape { ascii }) => define!("html-escape", ascii),
+            Encoding(HtmlUnescape) => define!("html-unescape"),
+            Encoding(ShellQuote { minimal }) => define!("shell-quote", minimal),
+            Encoding(ShellUnquote) => define!("shell-unquote"),
+            Encoding(JsonEscape { quote, ascii }) => define!("json-escape", quote, ascii),
+            Encoding(JsonUnescape) => define!("json-unescape"),
+            Encoding(Escape { ascii }) => define!("escape", ascii),
+            Encoding(Unescape) => define!("unescape"),
+            Encoding(RegexQuote { flavor }) => define!("regex-quote", flavor),
+            Encoding(Hash { algorithm, append }) => define!("hash", algorithm, append),
+
+            /* Character Mapping */
+            Rot { n } => define!("rot", n),
+            Slug { separator } => define!("slug", separator),
+            Deunicode { unknown } => define!("deunicode", unknown),
+
+            /* Paths */
+            Basename => define!("basename"),
+            Dirname => define!("dirname"),
+            Ext => define!("ext"),
+            Stem => define!("stem"),
+            WithExt { ext } => define!("with-ext", ext),
+
+            /* Time */
+            Time { from, to, tz } => define!("time", from, to, tz),
+
+            /* Numeric */
+            Numeric(Ratio {
+                field_a,
+                field_b,
+                format,
+                precision,
+                delim,
+                substitute,
+            }) => define!(
+                "ratio", field_a, field_b, format, precision, delim, substitute
+            ),
+            Numeric(Numbers {
+                ints,
+                floats,
+                signed,
+                sep,
+            }) => define!("numbers", ints, floats, signed, sep),
+            Numeric(MapNum { expr }) => define!("map-num", expr),
+            Numeric(NumFormat {
+                thousands,
+                precision,
+                width,
+                scientific,
+            }) => define!("num-format", thousands, precision, width, scientific),
+            Numeric(Metrics { metric, min, max }) => define!("metrics", metric, min, max),
+            Numeric(Stats { histogram }) => define!("stats", histogram),
+            Numeric(Humanize {
+                bytes,
+                si,
+                duration,
+            }) => define!("humanize", bytes, si, duration),
+            Numeric(Dehumanize {
+                bytes,
+                si,
+                duration,
+            }) => define!("dehumanize", bytes, si, duration),
+            Numeric(Count {
+                words,
+                chars,
+                graphemes,
+                bytes,
+                matches,
+                total,
+            }) => define!("count", words, chars, graphemes, bytes, matches, total),
+
+            /* Lists */
+            Lists(Join { delim, final_delim }) => define!("join", delim, final_delim),
+            Lists(Zip { file, delim }) => define!("zip", file, delim),
+            Lists(OnlyIn { file, .. }) => define!("only-in", file),
+            Lists(AlsoIn { file, .. }) => define!("also-in", file),
+            Lists(Union { file }) => define!("union", file),
+            Lists(Repeat { n, sep }) => define!("repeat", n, sep),
+            Lists(Reverse {
+                words,
+                graphemes,
+                line_order,
+            }) => define!("reverse", words, graphemes, line_order),
+            Lists(Dedup { adjacent, count }) => define!("dedup", adjacent, count),
+            Lists(DedupFuzzy {
+                threshold,
+                adjacent,
+            }) => define!("dedup-fuzzy", threshold, adjacent),
+            Lists(Sort {
+                numeric,
+                natural,
+                version,
+                reverse,
+                key,
+                delim,
+            }) => define!("sort", numeric, natural, version, reverse, key, delim),
+            Lists(Freq { top, by }) => define!("freq", top, by),
+            Lists(Tokens { top, pattern }) => define!("tokens", top, pattern),
+            Lists(Ngrams { n, words, chars }) => define!("ngrams", n, words, chars),
+            Lists(Number {
+                format,
+                start,
+                skip_empty,
+            }) => define!("number", format, start, skip_empty),
+            Lists(Head { n }) => define!("head", n),
+            Lists(Tail { n }) => define!("tail", n),
+            Lists(Sample { n, prob }) => define!("sample", n, prob),
+            Lists(Shuffle { seed }) => define!("shuffle", seed),
+
+            /* Predicates */
+            Is { predicate, quiet } => {
+                let mut definition = define!("is", quiet);
+                definition.args = predicate.words().into_iter().map(Word::from).collect();
+                definition
+            }
+
+            /* Pipelines */
+            Preset { name, presets, .. } => define!("preset", name, presets),
+            Run { json, .. } => define!("run", json),
+            Script { script, delim } => define!("script", script, delim),
+            Tui => define!("tui"),
+            // a script given with --file runs as its operations would in JSON
+            Stages(pipeline) => Definition::new("run", vec![("json", pipeline.words())]),
+
+            /* Help */
+            Examples { operation } => define!("examples", operation),
+        }
+    }
+}
+
+pub(crate) mod op_functions {
+    use std::collections::HashSet;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+    use std::ops::Range;
+    use std::path::Path;
+    use std::sync::OnceLock;
+
+    use aho_corasick::{AhoCorasick, MatchKind};
+
+    use super::{HashAlgorithm, Metric, Output, Predicate, RatioFormat, RegexFlavor, UrlPart};
+    use crate::compare::Order;
+    use crate::numbers::{NumberKind, Quantity};
+    use crate::pattern::{Pattern, PatternSet, split_at};
+    use crate::script::Script;
+    use crate::span::Span;
+    use crate::template::{Template, Value};
+
+    /* Pattern-Based */
+    pub fn split_at_whitespace(number: Option<i64>, respect_quotes: bool, input: &str) -> Output {
+        let words: Vec<&str> = match respect_quotes {
+            true => {
+                let spaces = input
+                    .char_indices()
+                    .filter(|(_, c)| c.is_whitespace())
+                    .map(|(i, c)| i..i + c.len_utf8());
+                split_at(input, unquoted(input, spaces), None, false)
+                    .into_iter()
+                    .filter(|word| !word.is_empty())
+                    .collect()
+            }
+            false => input.split_whitespace().collect(),
+        };
+        let trimmed = || words.iter().map(|&word| word.to_owned());
+
+        use Output::*;
+        match number {
+            None => Multiple(trimmed().collect()),
+            Some(x) if x.is_negative() => {
+                Multiple(trimmed().rev().take(x.unsigned_abs() as usize).collect())
+            } // not exactly intended behavior, collect remaining and return as one entry
+            Some(x) if x.is_positive() => Multiple(trimmed().take(x as usize).collect()),
+            _ => Single(input.to_owned()),
+        }
+    }
+
+    pub fn split_at_pat(
+        number: Option<i64>,
+        pattern: &Pattern,
+        respect_quotes: bool,
+        input: &str,
+    ) -> Output {
+        let matches = match respect_quotes {
+            true => unquoted(input, pattern.find_iter(input)),
+            false => pattern.find_iter(input).collect(),
+        };
+        split_at_matches(number, matches, input)
+    }
+
+    // the pieces between matches, no more than `number` of them, counted from the end when negative
+    fn split_at_matches(number: Option<i64>, matches: Vec<Range<usize>>, input: &str) -> Output {
+        let parts = |limit, from_end| {
+            split_at(input, matches.clone(), limit, from_end)
+                .into_iter()
+                .map(str::to_owned)
+        };
+
+        use super::Output::*;
+        match number {
+            None => Multiple(parts(None, false).collect()),
+            Some(x) if x.is_negative() => Multiple(
+                parts(Some(x.unsigned_abs() as usize - 1), true)
+                    .rev()
+                    .collect(),
+            ),
+            Some(x) if x.is_positive() => Multiple(parts(Some(x as usize - 1), false).collect()),
+            _ => Single(input.to_owned()),
+        }
+    }
+
+    pub fn split_at_char(
+        number: Option<i64>,
+        char: char,
+        respect_quotes: bool,
+        input: &str,
+    ) -> Output {
+        if respect_quotes {
+            let matches = input.match_indices(char).map(|(i, c)| i..i + c.len());
+            return split_at_matches(number, unquoted(input, matches), input);
+        }
+
+        use Output::*;
+        match number {
+            None => Multiple(input.split(char).map(str::to_owned).collect()),
+            Some(x) if x.is_negative() => Multiple(
+                input
+                    .rsplitn(x.unsigned_abs() as usize, char)
+                    .map(str::to_owned)
+                    .collect(),
+            ),
+            Some(x) if x.is_positive() => {
+                Multiple(input.splitn(x as usize, char).map(str::to_owned).collect())
+            }
+            _ => Single(input.to_owned()),
+        }
+    }
+
+    // the matches that don't begin inside quotes
+    fn unquoted(input: &str, matches: impl Iterator<Item = Range<usize>>) -> Vec<Range<usize>> {
+        let quoted = quoted(input);
+        matches
+            .filter(|m| !quoted.iter().any(|q| q.contains(&m.start)))
+            .collect()
+    }
+
+    // byte ranges of the single- and double-quoted parts of `input`, quotes included; a backslash
+    // escapes a double quote inside double quotes, and a quote left open runs to the end
+    fn quoted(input: &str) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut chars = input.char_indices();
+        while let Some((start, quote)) = chars.next() {
+            if quote != '\'' && quote != '"' {
+                continue;
+            }
+            let mut end = input.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' if quote == '"' => {
+                        chars.next();
+                    }
+                    c if c == quote => {
+                        end = i + 1;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            ranges.push(start..end);
+        }
+        ranges
+    }
+
+    // the value of `key` among the pairs of `input`, unquoted
+    pub fn get(key: &str, kv_sep: &str, pair_sep: &str, input: &str) -> Option<String> {
+        let seps = unquoted(
+            input,
+            input.match_indices(pair_sep).map(|(i, s)| i..i + s.len()),
+        );
+        split_at(input, seps, None, false)
+            .into_iter()
+            .find_map(|pair| {
+                let (k, value) = pair.split_once(kv_sep)?;
+                (k == key).then(|| unquote_value(value))
+            })
+    }
+
+    pub fn url_part(part: &UrlPart, input: &str) -> Option<String> {
+        let url = url::Url::parse(input.trim()).ok()?;
+        match part {
+            UrlPart::Scheme => Some(url.scheme().to_string()),
+            UrlPart::Host => url.host_str().map(str::to_owned),
+            UrlPart::Port => url.port_or_known_default().map(|port| port.to_string()),
+            UrlPart::Path => Some(url.path().to_string()),
+            UrlPart::Query => url.query().map(str::to_owned),
+            UrlPart::Fragment => url.fragment().map(str::to_owned),
+            UrlPart::QueryKey(key) => url
+                .query_pairs()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.into_owned()),
+        }
+    }
+
+    // a value with the quotes around it removed, and the escapes inside double quotes resolved
+    fn unquote_value(value: &str) -> String {
+        if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+            return inner.to_string();
+        }
+        let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+            return value.to_string();
+        };
+        let mut out = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => out.extend(chars.next()),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    pub fn segment(n: i64, delimiter: Option<&str>, input: &str) -> Output {
+        let fields = fields(delimiter, input);
+        let index = if n.is_negative() {
+            fields.len().checked_sub(n.unsigned_abs() as usize)
+        } else {
+            Some(n as usize)
+        };
+
+        Output::Single(
+            index
+                .and_then(|i| fields.get(i))
+                .map_or(String::new(), |f| f.to_string()),
+        )
+    }
+
+    pub fn cut_from_pat(pattern: &Pattern, input: &str) -> Output {
+        Output::Single(input[pattern.anchor(input).map_or(0, |m| m.start)..].to_string())
+    }
+
+    // built once per run from the patterns given, positionally and with -e, and reused for every line
+    pub fn pattern_set<'a, 'p>(
+        set: &'a OnceLock<PatternSet>,
+        pattern: impl IntoIterator<Item = &'p Pattern>,
+        patterns: &'p [Pattern],
+    ) -> Result<&'a PatternSet, String> {
+        get_or_try_init(set, || {
+            PatternSet::new(pattern.into_iter().chain(patterns).cloned().collect())
+        })
+    }
+
+    pub fn cut_until_pat(pattern: &PatternSet, input: &str) -> Output {
+        Output::Single(input[..pattern.anchor(input).map_or(input.len(), |m| m.start)].to_string())
+    }
+
+    pub fn trim_from_pat(pattern: &PatternSet, input: &str) -> Output {
+        Output::Single(input[..pattern.anchor(input).map_or(input.len(), |m| m.start)].to_string())
+    }
+
+    pub fn trim_until_pat(pattern: &Pattern, input: &str) -> Output {
+        Output::Single(input[pattern.anchor(input).map_or(0, |m| m.start)..].to_string())
+    }
+
+    pub fn trim_to_pat(pattern: &Pattern, input: &str) -> Output {
+        Output::Single(input[pattern.anchor(input).map_or(0, |m| m.end)..].to_string())
+    }
+
+    pub fn filter(pattern: &PatternSet, invert: bool, input: &str) -> Output {
+        if pattern.is_match(input) != invert {
+            Output::Single(input.to_string())
+        } else {
+            Output::Skip
+        }
+    }
+
+    pub fn match_pattern(pattern: &Pattern, all: bool, input: &str) -> Output {
+        let mut found = pattern.find_iter(input).map(|m| input[m].to_string());
+        match (all, found.next()) {
+            (_, None) => Output::Skip,
+            (false, Some(first)) => Output::Single(first),
+            (true, Some(first)) => Output::Multiple(std::iter::once(first).chain(found).collect()),
+        }
+    }
+
+    pub fn fuzzy(
+        pattern: &str,
+        max_dist: usize,
+        whole_line: bool,
+        positions: bool,
+        input: &str,
+    ) -> Output {
+        use crate::fuzzy;
+
+        let (range, distance) = match whole_line {
+            true => (0..input.len(), fuzzy::distance(pattern, input)),
+            false => fuzzy::find(pattern, input),
+        };
+        match (distance <= max_dist, positions) {
+            (false, _) => Output::Skip,
+            (true, true) => Output::Single(format!("{}..{}", range.start, range.end)),
+            (true, false) => Output::Single(input.to_string()),
+        }
+    }
+
+    // the text inside each pair of delimiters, counting how deeply they nest when balanced
+    pub fn between(open: &Pattern, close: &Pattern, balanced: bool, input: &str) -> Output {
+        let mut opens = open.find_iter(input).filter(|m| !m.is_empty()).peekable();
+        let mut closes = close.find_iter(input).filter(|m| !m.is_empty()).peekable();
+        let mut spans = Vec::new();
+        let (mut depth, mut start, mut next) = (0, 0, 0);
+        loop {
+            // where both match, as when they are the same, a delimiter closes what is open
+            let opening = match (opens.peek(), closes.peek()) {
+                (Some(o), Some(c)) => o.start < c.start || (o.start == c.start && depth == 0),
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let Some(m) = (if opening { opens.next() } else { closes.next() }) else {
+                break;
+            };
+            // a match overlapping a delimiter already taken isn't another one
+            if m.start < next {
+                continue;
+            }
+            next = m.end;
+            match (opening, depth) {
+                (true, 0) => (start, depth) = (m.end, 1),
+                (true, _) if balanced => depth += 1,
+                (false, 1) => {
+                    spans.push(input[start..m.start].to_string());
+                    depth = 0;
+                }
+                (false, 0) | (true, _) => {}
+                (false, _) => depth -= 1,
+            }
+        }
+        match spans.is_empty() {
+            true => Output::Skip,
+            false => Output::Multiple(spans),
+        }
+    }
+
+    pub fn trim(pattern: &Option<Pattern>, input: &str) -> Output {
+        let Some(p) = pattern else {
+            return Output::Single(input.trim().to_owned());
+        };
+        // strips repeated matches from each end, as `trim_matches` does
+        let mut rest = input;
+        while let Some(m) = p.find(rest).filter(|m| m.start == 0 && !m.is_empty()) {
+            rest = &rest[m.end..];
+        }
+        while let Some(m) = p
+            .rfind(rest)
+            .filter(|m| m.end == rest.len() && !m.is_empty())
+        {
+            rest = &rest[..m.start];
+        }
+        Output::Single(rest.to_owned())
+    }
+
+    pub fn replace(pattern: &PatternSet, with: &str, number: Option<i64>, input: &str) -> Output {
+        let mut tmp = input.to_string();
+
+        // first find matches indices and push the first `number`` of them to a vector (place this in match arm?)
+        let found: Vec<(usize, usize)> =
+            pattern.find_iter(input).map(|m| (m.start, m.end)).collect();
+
+        match number {
+            None => {
+                found.iter().rev().for_each(|i| {
+                    tmp.drain(i.0..i.1);
+                    tmp.insert_str(i.0, with);
+                });
+            }
+            Some(x) if x.is_negative() => {
+                found
+                    .iter()
+                    .rev()
+                    .take(x.unsigned_abs() as usize)
+                    .for_each(|i| {
+                        tmp.drain(i.0..i.1);
+                        tmp.insert_str(i.0, with);
+                    });
+            }
+            Some(x) if x.is_positive() => {
+                found
+                    .iter()
+                    .take(x.unsigned_abs() as usize)
+                    .rev()
+                    .for_each(|i| {
+                        tmp.drain(i.0..i.1);
+                        tmp.insert_str(i.0, with);
+                    });
+            }
+            _ => {}
+        };
+
+        Output::Single(tmp.to_owned())
+    }
+
+    // the value in `cell`, set by `init` if it isn't yet (`OnceLock::get_or_try_init`, which
+    // isn't stable)
+    pub fn get_or_try_init<T, E>(
+        cell: &OnceLock<T>,
+        init: impl FnOnce() -> Result<T, E>,
+    ) -> Result<&T, E> {
+        if let Some(value) = cell.get() {
+            return Ok(value);
+        }
+        let value = init()?;
+        Ok(cell.get_or_init(|| value))
+    }
+
+    // the lines of the file a two-input operation reads alongside the input
+    pub fn other_lines(
+        path: &Path,
+    ) -> Result<impl Iterator<Item = Result<String, String>> + Send + Sync + use<>, String> {
+        let file =
+            File::open(path).map_err(|e| format!("failed to open {}: {e}", path.display()))?;
+        let path = path.to_path_buf();
+        Ok(BufReader::new(file)
+            .lines()
+            .map(move |line| line.map_err(|e| format!("failed to read {}: {e}", path.display()))))
+    }
+
+    pub fn line_set(path: &Path) -> Result<HashSet<String>, String> {
+        other_lines(path)?.collect()
+    }
+
+    // built once per run by `ReplaceMany` and reused for every line
+    pub fn replacement_table(
+        pairs: &[String],
+        map: Option<&Path>,
+    ) -> Result<(AhoCorasick, Vec<String>), String> {
+        if !crate::pattern::literal() {
+            return Err(
+                "replace-many only matches literal text, so --regex and --fold-case can't be used with it"
+                    .to_string(),
+            );
+        }
+        let mut lines = pairs.to_vec();
+        if let Some(path) = map {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read map file {}: {e}", path.display()))?;
+            lines.extend(
+                contents
+                    .lines()
+                    .filter(|l| !l.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+
+        let (patterns, replacements): (Vec<&str>, Vec<String>) = lines
+            .iter()
+            .map(|l| match l.split_once('=') {
+                Some((p, r)) if !p.is_empty() => Ok((p, r.to_string())),
+                _ => Err(format!(
+                    "invalid replacement pair '{l}', expected PATTERN=REPLACEMENT"
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .unzip();
+
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(patterns)
+            .map_err(|e| format!("failed to build replacement table: {e}"))?;
+        Ok((automaton, replacements))
+    }
+
+    pub fn replace_many(table: &(AhoCorasick, Vec<String>), input: &str) -> Output {
+        let (automaton, replacements) = table;
+        Output::Single(automaton.replace_all(input, replacements))
+    }
+
+    pub fn highlight(patterns: &[Pattern], input: &str) -> Output {
+        if !crate::color::enabled() {
+            return Output::Single(input.to_string());
+        }
+
+        let mut spans: Vec<(usize, usize)> = patterns
+            .iter()
+            .flat_map(|p| p.find_iter(input))
+            .filter(|m| !m.is_empty())
+            .map(|m| (m.start, m.end))
+            .collect();
+        spans.sort();
+
+        // merge overlapping spans so escape codes never nest
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in spans {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        let mut out = String::new();
+        let mut last = 0;
+        for (start, end) in merged {
+            out.push_str(&input[last..start]);
+            out.push_str(&crate::color::paint(&input[start..end]));
+            last = end;
+        }
+        out.push_str(&input[last..]);
+
+        Output::Single(out)
+    }
+
+    /* Index-Based */
+    // the part of the line from `index` on, which `Processor` checks against the line like any
+    // other span
+    pub fn from_index(index: usize) -> Span {
+        Span::new(index as i64, None)
+    }
+
+    // the part of the line before `index`, or all of it for an index of zero
+    pub fn until_index(index: usize) -> Span {
+        Span::new(0, (index != 0).then_some(index as i64))
+    }
+
+    /* Ranges */
+    // the two anchors of each range operation, resolved to the span between them; `Processor`
+    // checks the span against the line before the operation runs
+    pub fn pat_to_pat(start: &Pattern, end: &Pattern, input: &str) -> Span {
+        let start_match = start.find(input);
+        Span {
+            start: start_match.as_ref().map(|m| m.start as i64),
+            end: end.rfind(input).map(|m| m.start as i64),
+            start_match_end: start_match.map(|m| m.end as i64),
+        }
+    }
+
+    // each span from a match of `start` to the first match of `end` after it, without overlapping
+    pub fn all_pat_to_pat(start: &Pattern, end: &Pattern, input: &str) -> Output {
+        let ends: Vec<Range<usize>> = end.find_iter(input).collect();
+        let mut spans = Vec::new();
+        let mut next = 0;
+        for s in start.find_iter(input) {
+            if s.start < next {
+                continue;
+            }
+            let Some(e) = ends.iter().find(|e| e.start >= s.end) else {
+                break;
+            };
+            spans.push(input[s.start..e.start].to_string());
+            // an empty start and end at the same place mustn't be found again
+            next = e.end.max(s.start + 1);
+        }
+        match spans.is_empty() {
+            true => Output::Skip,
+            false => Output::Multiple(spans),
+        }
+    }
+
+    pub fn pat_to_offset(pattern: &Pattern, offset: i64, input: &str) -> Span {
+        index_to_offset(pattern.anchor(input).map_or(0, |m| m.start), offset)
+    }
+
+    pub fn index_to_offset(index: usize, offset: i64) -> Span {
+        let index = index as i64;
+        match offset.is_negative() {
+            true => Span::new(index + offset, Some(index)),
+            false => Span::new(index, Some(index + offset)),
+        }
+    }
+
+    pub fn pat_to_index(pattern: &Pattern, index: usize, input: &str) -> Span {
+        Span {
+            start: pattern.anchor(input).map(|m| m.start as i64),
+            end: Some(index as i64),
+            start_match_end: None,
+        }
+    }
+
+    pub fn index_to_pat(index: usize, pattern: &Pattern, input: &str) -> Span {
+        Span::new(index as i64, pattern.anchor(input).map(|m| m.start as i64))
+    }
+
+    // a missing start cuts from the beginning of the line
+    pub fn cut_span(span: Span, input: &str) -> Output {
+        Output::Single(input[span.clamp(input)].to_string())
+    }
+
+    // a missing start leaves nothing to trim
+    pub fn trim_span(span: Span, input: &str) -> Output {
+        if span.start.is_none() {
+            return Output::Single(input.to_string());
+        }
+        let range = span.clamp(input);
+        Output::Single(input[..range.start].to_string() + &input[range.end..])
+    }
+
+    /* Formatting */
+    // returns the fill character and how many of it are needed to bring `input` up to `width`,
+    // measuring in display columns rather than chars so wide (e.g. CJK) text lines up in terminals
+    fn padding(width: usize, fill: Option<char>, input: &str) -> (String, usize) {
+        use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+        let fill = fill.unwrap_or(' ');
+        let fill_width = fill.width().unwrap_or(1).max(1);
+        (
+            fill.to_string(),
+            width.saturating_sub(input.width()) / fill_width,
+        )
+    }
+
+    pub fn pad_left(width: usize, fill: Option<char>, input: &str) -> Output {
+        let (fill, count) = padding(width, fill, input);
+        Output::Single(fill.repeat(count) + input)
+    }
+
+    pub fn pad_right(width: usize, fill: Option<char>, input: &str) -> Output {
+        let (fill, count) = padding(width, fill, input);
+        Output::Single(input.to_string() + fill.repeat(count).as_str())
+    }
+
+    pub fn center(width: usize, fill: Option<char>, input: &str) -> Output {
+        let (fill, count) = padding(width, fill, input);
+        Output::Single(fill.repeat(count / 2) + input + fill.repeat(count - count / 2).as_str())
+    }
+
+    // the byte offset after the graphemes that fit in `width` columns, or that start within them
+    // when `straddle` is set
+    pub fn width_boundary(width: usize, straddle: bool, input: &str) -> usize {
+        use unicode_segmentation::UnicodeSegmentation;
+        use unicode_width::UnicodeWidthStr;
+
+        let mut used = 0;
+        for (i, grapheme) in input.grapheme_indices(true) {
+            let fits = match straddle {
+                true => used < width,
+                false => used + grapheme.width() <= width,
+            };
+            if !fits {
+                return i;
+            }
+            used += grapheme.width();
+        }
+        input.len()
+    }
+
+    pub fn cut_to_width(width: usize, ellipsis: Option<&str>, input: &str) -> String {
+        use unicode_width::UnicodeWidthStr;
+
+        match ellipsis {
+            Some(ellipsis) if input.width() > width => {
+                let room = width.saturating_sub(ellipsis.width());
+                input[..width_boundary(room, false, input)].to_string() + ellipsis
+            }
+            _ => input[..width_boundary(width, false, input)].to_string(),
+        }
+    }
+
+    pub fn wrap(width: usize, hard: bool, input: &str) -> Output {
+        use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+        let width = width.max(1);
+        let mut lines: Vec<String> = Vec::new();
+        let mut current = String::new();
+
+        for word in input.split_whitespace() {
+            let needed = if current.is_empty() { 0 } else { 1 } + word.width();
+            if current.width() + needed <= width {
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(word);
+                continue;
+            }
+
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            if !hard || word.width() <= width {
+                current.push_str(word);
+                continue;
+            }
+
+            // break the word into chunks that fill the width, keeping the remainder for the next word
+            for c in word.chars() {
+                if current.width() + c.width().unwrap_or(0) > width {
+                    lines.push(std::mem::take(&mut current));
+                }
+                current.push(c);
+            }
+        }
+        lines.push(current);
+
+        Output::Multiple(lines)
+    }
+
+    pub fn expand(width: usize, input: &str) -> String {
+        use unicode_width::UnicodeWidthChar;
+
+        let width = width.max(1);
+        let mut out = String::new();
+        let mut column = 0;
+        for c in input.chars() {
+            if c == '\t' {
+                let spaces = width - column % width;
+                out.extend(std::iter::repeat_n(' ', spaces));
+                column += spaces;
+            } else {
+                out.push(c);
+                column += c.width().unwrap_or(0);
+            }
+        }
+        out
+    }
+
+    pub fn unexpand(width: usize, all: bool, input: &str) -> String {
+        use unicode_width::UnicodeWidthChar;
+
+        let width = width.max(1);
+        let mut out = String::new();
+        let (mut column, mut spaces, mut leading) = (0, 0, true);
+        for c in expand(width, input).chars() {
+            if c == ' ' && (all || leading) {
+                spaces += 1;
+                column += 1;
+                // a lone space before a tab stop stays a space
+                if column % width == 0 {
+                    out.push(if spaces > 1 { '\t' } else { ' ' });
+                    spaces = 0;
+                }
+                continue;
+            }
+
+            out.extend(std::iter::repeat_n(' ', spaces));
+            spaces = 0;
+            leading &= c == ' ';
+            out.push(c);
+            column += c.width().unwrap_or(0);
+        }
+        out.extend(std::iter::repeat_n(' ', spaces));
+        out
+    }
+
+    pub fn squeeze(all_whitespace: bool, input: &str) -> String {
+        let blank = |c: char| match all_whitespace {
+            true => c.is_whitespace(),
+            false => c == ' ' || c == '\t',
+        };
+        input
+            .split(blank)
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn indent(n: usize, with: &str, input: &str) -> String {
+        match input.is_empty() {
+            true => String::new(),
+            false => with.repeat(n) + input,
+        }
+    }
+
+    // strips at most `n` leading whitespace characters, or all of them
+    pub fn dedent(n: Option<usize>, input: &str) -> &str {
+        let start = input
+            .char_indices()
+            .take_while(|(_, c)| c.is_whitespace())
+            .take(n.unwrap_or(usize::MAX))
+            .last()
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        &input[start..]
+    }
+
+    // the longest leading whitespace shared by every line that isn't blank
+    pub fn common_indent(lines: &[String]) -> &str {
+        lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| &line[..line.len() - line.trim_start().len()])
+            .reduce(|common, indent| {
+                let shared = common
+                    .char_indices()
+                    .zip(indent.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(indent.len()), |((i, _), _)| i);
+                &common[..shared]
+            })
+            .unwrap_or("")
+    }
+
+    // removes the longest prefix (or suffix) shared by all lines, cut back to a delimiter if given
+    pub fn strip_common(lines: &[String], suffix: bool, delim: Option<&str>) -> Vec<String> {
+        let Some(first) = lines.first() else {
+            return Vec::new();
+        };
+        // shared length in bytes, always on a char boundary of every line
+        let shared = lines[1..].iter().fold(first.len(), |shared, line| {
+            let pairs: Box<dyn Iterator<Item = (char, char)>> = match suffix {
+                true => Box::new(first.chars().rev().zip(line.chars().rev())),
+                false => Box::new(first.chars().zip(line.chars())),
+            };
+            pairs
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum::<usize>()
+                .min(shared)
+        });
+
+        let shared = match (delim, suffix) {
+            (None, _) => shared,
+            (Some(d), false) => first[..shared].rfind(d).map_or(0, |i| i + d.len()),
+            (Some(d), true) => first[first.len() - shared..]
+                .find(d)
+                .map_or(0, |i| shared - i),
+        };
+        lines
+            .iter()
+            .map(|line| match suffix {
+                true => line[..line.len() - shared].to_string(),
+                false => line[shared..].to_string(),
+            })
+            .collect()
+    }
+
+    // aligns the fields of every line into columns two spaces apart
+    pub fn table(lines: &[String], delim: Option<&str>) -> Vec<String> {
+        use unicode_width::UnicodeWidthStr;
+
+        let rows: Vec<Vec<&str>> = lines.iter().map(|line| fields(delim, line)).collect();
+        let mut widths: Vec<usize> = Vec::new();
+        for row in &rows {
+            for (i, field) in row.iter().enumerate() {
+                match widths.get_mut(i) {
+                    Some(width) => *width = (*width).max(field.width()),
+                    None => widths.push(field.width()),
+                }
+            }
+        }
+
+        rows.iter()
+            .map(|row| {
+                let mut line = String::new();
+                for (i, field) in row.iter().enumerate() {
+                    if i + 1 == row.len() {
+                        line.push_str(field); // no trailing padding
+                    } else {
+                        let (fill, count) = padding(widths[i], None, field);
+                        line.push_str(field);
+                        line.push_str(&fill.repeat(count + 2));
+                    }
+                }
+                line
+            })
+            .collect()
+    }
+
+    pub fn transpose(lines: &[String], delim: Option<&str>) -> Vec<String> {
+        let rows: Vec<Vec<&str>> = lines.iter().map(|line| fields(delim, line)).collect();
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        (0..columns)
+            .map(|i| {
+                rows.iter()
+                    .map(|row| row.get(i).copied().unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join(delim.unwrap_or(" "))
+            })
+            .collect()
+    }
+
+    // fills in a `Format` template for a line, given its number and source
+    pub fn format(
+        template: &Template,
+        delim: Option<&str>,
+        (n, file): (usize, &str),
+        input: &str,
+    ) -> String {
+        let fields = fields(delim, input);
+        template.render(|name| match name {
+            "line" => Some(Value::Text(input)),
+            "n" => Some(Value::Number(n)),
+            "file" => Some(Value::Text(file)),
+            _ => name
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| fields.get(i))
+                .map(|f| Value::Text(f)),
+        })
+    }
+
+    pub fn script(
+        script: &Script,
+        delim: Option<&str>,
+        (n, file): (usize, &str),
+        input: &str,
+    ) -> Result<Output, String> {
+        script.run(input, &fields(delim, input), (n, file))
+    }
+
+    /* Encoding */
+    // decoded bytes as text, falling back to hex for binary data unless --lossy or --binary says
+    // how to carry it
+    pub fn decoded_text(bytes: Vec<u8>) -> String {
+        crate::records::decoding()
+            .decode(bytes)
+            .unwrap_or_else(|e| e.as_bytes().iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    fn base64_engine(url_safe: bool, pad: bool) -> base64::engine::GeneralPurpose {
+        use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+
+        let alphabet = match url_safe {
+            true => &base64::alphabet::URL_SAFE,
+            false => &base64::alphabet::STANDARD,
+        };
+        let config = GeneralPurposeConfig::new()
+            .with_encode_padding(pad)
+            .with_decode_padding_mode(DecodePaddingMode::Indifferent);
+        GeneralPurpose::new(alphabet, config)
+    }
+
+    pub fn b64_encode(url_safe: bool, no_pad: bool, input: &str) -> String {
+        use base64::Engine;
+
+        base64_engine(url_safe, !no_pad).encode(input)
+    }
+
+    pub fn b64_decode(url_safe: bool, input: &str) -> Result<String, String> {
+        use base64::Engine;
+
+        match base64_engine(url_safe, true).decode(input.trim()) {
+            Ok(bytes) => Ok(decoded_text(bytes)),
+            Err(e) => Err(format!("invalid base64 '{input}': {e}")),
+        }
+    }
+
+    // characters with a structural meaning in a URL (RFC 3986 gen-delims and sub-delims)
+    const URL_RESERVED: &str = ":/?#[]@!$&'()*+,;=";
+
+    pub fn url_encode(full: bool, input: &str) -> String {
+        let mut out = String::new();
+        for c in input.chars() {
+            if c.is_ascii_alphanumeric() || "-._~".contains(c) || (full && URL_RESERVED.contains(c))
+            {
+                out.push(c);
+            } else {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("%{b:02X}"));
+                }
+            }
+        }
+        out
+    }
+
+    pub fn url_decode(full: bool, plus: bool, input: &str) -> String {
+        let bytes = input.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let escaped = bytes
+                .get(i + 1..i + 3)
+                .filter(|_| bytes[i] == b'%')
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+                .filter(|&b| !(full && URL_RESERVED.contains(b as char)));
+            match (escaped, bytes[i]) {
+                (Some(b), _) => {
+                    out.push(b);
+                    i += 3;
+                    continue;
+                }
+                (None, b'+') if plus => out.push(b' '),
+                (None, b) => out.push(b),
+            }
+            i += 1;
+        }
+        decoded_text(out)
+    }
+
+    pub fn html_escape(ascii: bool, input: &str) -> String {
+        let mut out = String::with_capacity(input.len());
+        for c in input.chars() {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&#39;"),
+                c if ascii && !c.is_ascii() => out.push_str(&format!("&#{};", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    pub fn shell_quote(minimal: bool, input: &str) -> String {
+        let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c);
+        if minimal && !input.is_empty() && input.chars().all(safe) {
+            return input.to_string();
+        }
+        format!("'{}'", input.replace('\'', "'\\''"))
+    }
+
+    pub fn shell_unquote(input: &str) -> Result<String, String> {
+        let mut out = String::with_capacity(input.len());
+        let mut chars = input.chars();
+        while let Some(c) = chars.next() {
+            let closed = match c {
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break true,
+                        Some(c) => out.push(c),
+                        None => break false,
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break true,
+                        // inside double quotes a backslash only escapes these
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => out.push(c),
+                            Some(c) => {
+                                out.push('\\');
+                                out.push(c);
+                            }
+                            None => break false,
+                        },
+                        Some(c) => out.push(c),
+                        None => break false,
+                    }
+                },
+                '\\' => {
+                    out.extend(chars.next());
+                    true
+                }
+                c => {
+                    out.push(c);
+                    true
+                }
+            };
+            if !closed {
+                return Err(format!("unterminated quote in '{input}'"));
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn json_escape(quote: bool, ascii: bool, input: &str) -> String {
+        let mut out = String::with_capacity(input.len() + 2);
+        if quote {
+            out.push('"');
+        }
+        for c in input.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                '\u{8}' => out.push_str("\\b"),
+                '\u{c}' => out.push_str("\\f"),
+                c if c.is_control() || (ascii && !c.is_ascii()) => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        out.push_str(&format!("\\u{unit:04x}"));
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+        if quote {
+            out.push('"');
+        }
+        out
+    }
+
+    pub fn json_unescape(input: &str) -> Result<String, String> {
+        let fail = |reason: &str| Err(format!("invalid JSON string '{input}': {reason}"));
+        let body = input
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .unwrap_or(input);
+
+        let mut units = Vec::new();
+        let mut out = String::with_capacity(body.len());
+        let mut chars = body.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push_str(&utf16(&units, input)?);
+                units.clear();
+                out.push(c);
+                continue;
+            }
+            let escaped = match chars.next() {
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    match u16::from_str_radix(&hex, 16) {
+                        Ok(unit)
+                            if hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+                        {
+                            units.push(unit);
+                            continue;
+                        }
+                        _ => return fail(&format!("bad escape \\u{hex}")),
+                    }
+                }
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('/') => '/',
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some(c) => return fail(&format!("bad escape \\{c}")),
+                None => return fail("trailing backslash"),
+            };
+            out.push_str(&utf16(&units, input)?);
+            units.clear();
+            out.push(escaped);
+        }
+        out.push_str(&utf16(&units, input)?);
+        Ok(out)
+    }
+
+    // pending \\uXXXX escapes, which may pair up into one character
+    fn utf16(units: &[u16], input: &str) -> Result<String, String> {
+        String::from_utf16(units)
+            .map_err(|_| format!("invalid JSON string '{input}': unpaired surrogate"))
+    }
+
+    pub fn escape(ascii: bool, input: &str) -> String {
+        let mut out = String::with_capacity(input.len());
+        for c in input.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                '\u{7}' => out.push_str("\\a"),
+                '\u{8}' => out.push_str("\\b"),
+                '\u{1b}' => out.push_str("\\e"),
+                '\u{c}' => out.push_str("\\f"),
+                '\u{b}' => out.push_str("\\v"),
+                c if c.is_ascii_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+                c if c.is_control() || (ascii && !c.is_ascii()) => {
+                    out.push_str(&format!("\\u{{{:x}}}", c as u32))
+                }
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    pub fn unescape(input: &str) -> String {
+        let mut out = Vec::with_capacity(input.len());
+        let mut rest = input;
+        while let Some(i) = rest.find('\\') {
+            out.extend_from_slice(&rest.as_bytes()[..i]);
+            rest = &rest[i + 1..];
+            // the escape's value and how much of the text after the backslash it used
+            let digits = |radix: u32, max: usize| {
+                rest.char_indices()
+                    .skip(1)
+                    .take(max)
+                    .take_while(|(_, c)| c.is_digit(radix))
+                    .last()
+                    .map_or(1, |(i, _)| i + 1)
+            };
+            let (bytes, used): (Vec<u8>, usize) = match rest.chars().next() {
+                Some('n') => (vec![b'\n'], 1),
+                Some('t') => (vec![b'\t'], 1),
+                Some('r') => (vec![b'\r'], 1),
+                Some('\\') => (vec![b'\\'], 1),
+                Some('a') => (vec![0x07], 1),
+                Some('b') => (vec![0x08], 1),
+                Some('e') => (vec![0x1b], 1),
+                Some('f') => (vec![0x0c], 1),
+                Some('v') => (vec![0x0b], 1),
+                Some('0') => {
+                    let used = digits(8, 3);
+                    let value = u32::from_str_radix(&rest[1..used], 8).unwrap_or(0);
+                    (vec![value as u8], used)
+                }
+                Some('x') if digits(16, 2) > 1 => {
+                    let used = digits(16, 2);
+                    (vec![u8::from_str_radix(&rest[1..used], 16).unwrap()], used)
+                }
+                Some('u') if rest[1..].starts_with('{') => {
+                    let decoded = rest[2..].split_once('}').and_then(|(hex, _)| {
+                        let c = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+                        Some((c.to_string().into_bytes(), hex.len() + 3))
+                    });
+                    decoded.unwrap_or((b"\\".to_vec(), 0))
+                }
+                // unknown escapes, and a trailing backslash, stay as they are
+                _ => (b"\\".to_vec(), 0),
+            };
+            out.extend(bytes);
+            rest = &rest[used..];
+        }
+        out.extend_from_slice(rest.as_bytes());
+        decoded_text(out)
+    }
+
+    pub fn regex_quote(flavor: RegexFlavor, input: &str) -> String {
+        let special = match flavor {
+            RegexFlavor::Rust => return regex::escape(input),
+            RegexFlavor::Ere => "\\.[]{}()*+?^$|",
+            // in basic regexes \\( \\{ \\+ and \\? are operators, so those stay bare
+            RegexFlavor::Bre => "\\.[]*^$",
+        };
+        let mut out = String::with_capacity(input.len());
+        for c in input.chars() {
+            if special.contains(c) {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    pub fn hash(algorithm: HashAlgorithm, input: &str) -> String {
+        use sha2::Digest;
+
+        let hex = |digest: &[u8]| digest.iter().map(|b| format!("{b:02x}")).collect();
+        let bytes = input.as_bytes();
+        match algorithm {
+            HashAlgorithm::Md5 => hex(&md5::Md5::digest(bytes)),
+            HashAlgorithm::Sha1 => hex(&sha1::Sha1::digest(bytes)),
+            HashAlgorithm::Sha256 => hex(&sha2::Sha256::digest(bytes)),
+            HashAlgorithm::Blake3 => blake3::hash(bytes).to_hex().to_string(),
+            HashAlgorithm::Crc32 => format!("{:08x}", crc32fast::hash(bytes)),
+        }
+    }
+
+    /* Character Mapping */
+    pub fn map_chars(input: &str, f: impl Fn(char) -> char) -> String {
+        input.chars().map(f).collect()
+    }
+
+    // shifts an ASCII letter n places, wrapping within its case
+    pub fn rotate(n: i64, c: char) -> char {
+        let base = match c {
+            'a'..='z' => b'a',
+            'A'..='Z' => b'A',
+            _ => return c,
+        };
+        let offset = (c as u8 - base) as i64;
+        (base + (offset + n).rem_euclid(26) as u8) as char
+    }
+
+    pub fn slug(separator: &str, input: &str) -> String {
+        deunicode::deunicode(input)
+            .to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    pub fn quantity(si: bool, duration: bool) -> Quantity {
+        match (si, duration) {
+            (true, _) => Quantity::Si,
+            (_, true) => Quantity::Duration,
+            _ => Quantity::Bytes,
+        }
+    }
+
+    pub fn number_kind(ints: bool, floats: bool) -> NumberKind {
+        match (ints, floats) {
+            (true, _) => NumberKind::Int,
+            (_, true) => NumberKind::Float,
+            _ => NumberKind::Any,
+        }
+    }
+
+    /* Paths */
+    fn is_separator(c: char) -> bool {
+        c == '/' || c == '\\'
+    }
+
+    // a path as the directory its last component is in (with the separator after it), that
+    // component, and any separators after it
+    pub fn split_path(path: &str) -> (&str, &str, &str) {
+        let trimmed = path.trim_end_matches(is_separator);
+        let trailing = &path[trimmed.len()..];
+        let start = trimmed.rfind(is_separator).map_or(0, |i| i + 1);
+        (&trimmed[..start], &trimmed[start..], trailing)
+    }
+
+    // a file name as its stem and extension, if it has one
+    pub fn split_ext(name: &str) -> (&str, Option<&str>) {
+        match name.rfind('.') {
+            Some(i) if i > 0 && name != ".." => (&name[..i], Some(&name[i + 1..])),
+            _ => (name, None),
+        }
+    }
+
+    pub fn basename(path: &str) -> &str {
+        match split_path(path) {
+            // nothing but separators is the root
+            (_, "", trailing) if !trailing.is_empty() => &trailing[..1],
+            (_, name, _) => name,
+        }
+    }
+
+    pub fn dirname(path: &str) -> String {
+        let (dir, name, trailing) = split_path(path);
+        if dir.is_empty() {
+            return match name.is_empty() && !trailing.is_empty() {
+                true => trailing[..1].to_string(),
+                false => ".".to_string(),
+            };
+        }
+        let parent = dir.trim_end_matches(is_separator);
+        let is_drive = parent.len() == 2
+            && parent.ends_with(':')
+            && parent.starts_with(|c: char| c.is_ascii_alphabetic());
+        match parent {
+            "" => dir[..1].to_string(),
+            _ if is_drive => dir[..3].to_string(),
+            _ => parent.to_string(),
+        }
+    }
+
+    pub fn with_ext(ext: &str, path: &str) -> String {
+        let (dir, name, trailing) = split_path(path);
+        if matches!(name, "" | "." | "..") {
+            return path.to_string();
+        }
+        let stem = split_ext(name).0;
+        match ext.trim_start_matches('.') {
+            "" => format!("{dir}{stem}{trailing}"),
+            ext => format!("{dir}{stem}.{ext}{trailing}"),
+        }
+    }
+
+    /* Numeric */
+    // splits a line into fields at `delim`, or at runs of whitespace when no delimiter is given
+    pub fn fields<'a>(delim: Option<&str>, input: &'a str) -> Vec<&'a str> {
+        match delim {
+            Some(d) => input.split(d).collect(),
+            None => input.split_whitespace().collect(),
+        }
+    }
+
+    pub fn ratio(
+        (field_a, field_b): (usize, usize),
+        format: RatioFormat,
+        precision: usize,
+        delim: Option<&str>,
+        substitute: bool,
+        input: &str,
+    ) -> Output {
+        let fields = fields(delim, input);
+        let number = |i: usize| fields.get(i).and_then(|f| f.trim().parse::<f64>().ok());
+
+        let (Some(a), Some(b)) = (number(field_a), number(field_b)) else {
+            return Output::Single(input.to_string());
+        };
+        let result = match format {
+            // rather than inf or NaN
+            _ if b == 0.0 => "n/a".to_string(),
+            RatioFormat::Ratio => format!("{:.precision$}", a / b),
+            RatioFormat::Percent => format!("{:.precision$}%", a / b * 100.0),
+        };
+
+        Output::Single(if substitute {
+            result
+        } else {
+            input.to_string() + delim.unwrap_or(" ") + result.as_str()
+        })
+    }
+
+    // the length of a line in bytes, characters and display width, as `Stats` reports them
+    pub fn lengths(input: &str) -> [usize; 3] {
+        use unicode_width::UnicodeWidthStr;
+
+        [input.len(), input.chars().count(), input.width()]
+    }
+
+    // a table of the smallest, largest, mean and median of each kind of length, and optionally a
+    // histogram of lengths in characters in buckets of `histogram`
+    pub fn line_stats(lengths: &mut [[usize; 3]], histogram: Option<usize>) -> Vec<String> {
+        const BAR: usize = 40;
+
+        let n = lengths.len();
+        let mut report = vec![format!("lines {n:>8}")];
+        if n == 0 {
+            return report;
+        }
+        report.push(format!(
+            "      {:>8} {:>8} {:>8} {:>8}",
+            "min", "max", "mean", "median"
+        ));
+        for (kind, name) in ["bytes", "chars", "width"].into_iter().enumerate() {
+            let mut column: Vec<usize> = lengths.iter().map(|l| l[kind]).collect();
+            column.sort_unstable();
+            let (min, max) = (column[0], column[n - 1]);
+            let mean = column.iter().sum::<usize>() as f64 / n as f64;
+            let median = match n % 2 {
+                0 => (column[n / 2 - 1] + column[n / 2]) as f64 / 2.0,
+                _ => column[n / 2] as f64,
+            };
+            report.push(format!(
+                "{name} {min:>8} {max:>8} {mean:>8.1} {median:>8.1}"
+            ));
+        }
+
+        if let Some(width) = histogram {
+            let width = width.max(1);
+            let mut buckets: Vec<usize> = Vec::new();
+            for l in lengths.iter() {
+                let bucket = l[1] / width;
+                if buckets.len() <= bucket {
+                    buckets.resize(bucket + 1, 0);
+                }
+                buckets[bucket] += 1;
+            }
+            let most = buckets.iter().copied().max().unwrap_or(1);
+            report.push(String::new());
+            for (i, count) in buckets.into_iter().enumerate() {
+                let range = format!("{}-{}", i * width, (i + 1) * width - 1);
+                let bar = "#".repeat((count * BAR).div_ceil(most));
+                report.push(format!("{range:>13} {count:>8} {bar}"));
+            }
+        }
+        report
+    }
+
+    pub fn metrics(metric: Metric, min: Option<f64>, max: Option<f64>, input: &str) -> Output {
+        let total = input.chars().count();
+        let proportion = |class: fn(&char) -> bool| {
+            if total == 0 {
+                0.0
+            } else {
+                input.chars().filter(class).count() as f64 / total as f64
+            }
+        };
+
+        let value = match metric {
+            Metric::Entropy => {
+                let mut counts = std::collections::HashMap::new();
+                input
+                    .chars()
+                    .for_each(|c| *counts.entry(c).or_insert(0usize) += 1);
+                counts
+                    .values()
+                    .map(|&n| {
+                        let p = n as f64 / total as f64;
+                        p * (1.0 / p).log2()
+                    })
+                    .sum()
+            }
+            Metric::Alpha => proportion(|c| c.is_alphabetic()),
+            Metric::Digit => proportion(|c| c.is_numeric()),
+            Metric::Upper => proportion(|c| c.is_uppercase()),
+            Metric::Whitespace => proportion(|c| c.is_whitespace()),
+            Metric::Punct => proportion(|c| c.is_ascii_punctuation()),
+            Metric::NonAscii => proportion(|c| !c.is_ascii()),
+        };
+
+        if min.is_none() && max.is_none() {
+            return Output::Single(format!("{value:.3}"));
+        }
+        if min.is_some_and(|m| value < m) || max.is_some_and(|m| value > m) {
+            return Output::Skip;
+        }
+        Output::Single(input.to_string())
+    }
+
+    // counts characters unless another unit is chosen
+    pub fn count(
+        words: bool,
+        graphemes: bool,
+        bytes: bool,
+        matches: Option<&Pattern>,
+        input: &str,
+    ) -> usize {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        match matches {
+            Some(pattern) => pattern.find_iter(input).count(),
+            None if words => input.split_whitespace().count(),
+            None if graphemes => input.graphemes(true).count(),
+            None if bytes => input.len(),
+            None => input.chars().count(),
+        }
+    }
+
+    /* Lists */
+    pub fn join(delim: &str, final_delim: Option<&str>, lines: &[String]) -> String {
+        match (lines, final_delim) {
+            ([init @ .., last], Some(final_delim)) if !init.is_empty() => {
+                init.join(delim) + final_delim + last.as_str()
+            }
+            _ => lines.join(delim),
+        }
+    }
+
+    pub fn repeat(n: usize, sep: Option<&str>, input: &str) -> Output {
+        let repetitions = vec![input.to_string(); n];
+        match sep {
+            _ if n == 0 => Output::Skip,
+            Some(sep) => Output::Single(repetitions.join(sep)),
+            None => Output::Multiple(repetitions),
+        }
+    }
+
+    pub fn reverse(words: bool, input: &str) -> String {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        if !words {
+            return input.graphemes(true).rev().collect();
+        }
+
+        // split into alternating runs of whitespace and non-whitespace so spacing survives
+        let mut tokens: Vec<&str> = Vec::new();
+        let mut start = 0;
+        for (i, c) in input.char_indices().skip(1) {
+            let prev = input[..i].chars().next_back().unwrap();
+            if c.is_whitespace() != prev.is_whitespace() {
+                tokens.push(&input[start..i]);
+                start = i;
+            }
+        }
+        tokens.push(&input[start..]);
+        tokens.into_iter().rev().collect()
+    }
+
+    pub fn number(format: &Template, n: usize, input: &str) -> String {
+        format.render(|_| Some(Value::Number(n))) + input
+    }
+
+    // the items `Freq` counts in a line: the line itself, or every match of the pattern
+    pub fn tokens<'a>(by: Option<&Pattern>, input: &'a str) -> Vec<&'a str> {
+        match by {
+            Some(pattern) => pattern.find_iter(input).map(|m| &input[m]).collect(),
+            None => vec![input],
+        }
+    }
+
+    pub fn ngrams(n: usize, chars: bool, input: &str) -> Output {
+        let grams: Vec<String> = match chars {
+            true => {
+                let chars: Vec<char> = input.chars().collect();
+                chars.windows(n.max(1)).map(String::from_iter).collect()
+            }
+            false => {
+                let words: Vec<&str> = input.split_whitespace().collect();
+                words.windows(n.max(1)).map(|gram| gram.join(" ")).collect()
+            }
+        };
+        match grams.is_empty() || n == 0 {
+            true => Output::Skip,
+            false => Output::Multiple(grams),
+        }
+    }
+
+    // the tokens of a line for `Tokens`: the matches of a pattern, or what whitespace separates
+    pub fn words<'a>(pattern: Option<&Pattern>, input: &'a str) -> Vec<&'a str> {
+        match pattern {
+            Some(pattern) => pattern.find_iter(input).map(|m| &input[m]).collect(),
+            None => input.split_whitespace().collect(),
+        }
+    }
+
+    // formats a token with its number of occurrences and their share of all `total` tokens
+    pub fn token_share(count: usize, total: usize, token: &str) -> String {
+        let share = 100.0 * count as f64 / total.max(1) as f64;
+        format!("{count:>7} {share:>6.2}% {token}")
+    }
+
+    // formats a line with its number of occurrences for `Dedup --count` and `Freq`
+    pub fn counted(count: usize, line: &str) -> String {
+        format!("{count:>7} {line}")
+    }
+
+    // parses a probability or proportion, which must be between 0 and 1
+    pub fn fraction(s: &str) -> Result<f64, String> {
+        match s.parse::<f64>() {
+            Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+            _ => Err("expected a number between 0 and 1".to_string()),
+        }
+    }
+
+    pub fn sort(
+        order: Order,
+        reverse: bool,
+        key: Option<usize>,
+        delim: Option<&str>,
+        lines: Vec<String>,
+    ) -> Vec<String> {
+        // keys are extracted once up front rather than on every comparison
+        let mut keyed: Vec<(String, String)> = lines
+            .into_iter()
+            .map(|line| match key {
+                Some(i) => (
+                    fields(delim, &line)
+                        .get(i)
+                        .copied()
+                        .unwrap_or_default()
+                        .to_string(),
+                    line,
+                ),
+                None => (line.clone(), line),
+            })
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| match reverse {
+            true => order.compare(b, a),
+            false => order.compare(a, b),
+        });
+        keyed.into_iter().map(|(_, line)| line).collect()
+    }
+
+    /* Predicates */
+    pub fn is(predicate: &Predicate, input: &str) -> bool {
+        match predicate {
+            Predicate::Contains { pattern } => pattern.is_match(input),
+            Predicate::StartsWith { pattern } => pattern.is_prefix_of(input),
+            Predicate::EndsWith { pattern } => pattern.is_suffix_of(input),
+            Predicate::Matches { pattern } => pattern.is_match(input),
+            Predicate::Empty => input.is_empty(),
+            Predicate::Number => input.trim().parse::<f64>().is_ok(),
+            Predicate::Ascii => input.is_ascii(),
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(version, about = "Powerful command-line-driven string manipulation tool", long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    pub operation: Option<Operation>,
+
+    #[arg(
+        help = "Apply the operations in a script file instead, one per line, each to what the one before outputs (blank lines and lines starting with # are skipped, so a script can begin with #!/usr/bin/env str and be run directly)",
+        short,
+        long,
+        value_name = "SCRIPT"
+    )]
+    pub file: Option<PathBuf>,
+
+    #[arg(
+        help = "Read input from a file instead of stdin (may be given multiple times)",
+        long = "input",
+        value_name = "FILE",
+        global = true
+    )]
+    pub inputs: Vec<PathBuf>,
+
+    #[arg(
+        help = "Write results back into each input file (requires --input)",
+        short,
+        long,
+        global = true
+    )]
+    pub in_place: bool,
+
+    #[arg(
+        help = "Write results to a file instead of stdout, which is only replaced once every result is written",
+        short,
+        long,
+        value_name = "PATH",
+        global = true
+    )]
+    pub output: Option<PathBuf>,
+
+    #[arg(
+        help = "Write the results for each input file to its own file, named by a template of the input's {name}, {stem} and {ext} such as '{stem}.clean.{ext}' (relative to the input's directory; requires --input)",
+        long,
+        value_name = "TEMPLATE",
+        value_parser = |s: &str| Template::parse(s, &["name", "stem", "ext"]),
+        global = true
+    )]
+    pub output_template: Option<Template>,
+
+    #[arg(
+        help = "Also write results to FILE as they are printed to stdout",
+        long,
+        value_name = "FILE",
+        global = true
+    )]
+    pub tee: Option<PathBuf>,
+
+    #[arg(
+        help = "Report which files and lines would change without writing anything (requires --in-place, --output or --output-template)",
+        long,
+        global = true
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        help = "With --dry-run, print a unified diff of what would change, and the summary to stderr (requires --dry-run)",
+        long,
+        global = true
+    )]
+    pub diff: bool,
+
+    #[arg(
+        help = "Instead of the output, show each line with markers under what the patterns matched and the range cut or trimmed, what it became, and what the operation does",
+        long,
+        global = true
+    )]
+    pub explain: bool,
+
+    #[arg(
+        help = "Exit with status 1 if no line matched the operation's patterns, and 0 if one did, as grep does (on by default for filter, match and fuzzy; errors exit with 2)",
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        global = true
+    )]
+    pub fail_if_no_match: Option<bool>,
+
+    #[arg(
+        help = "Only apply the operation to the given lines, passing others through unchanged (e.g. '10..50,100..' or '/BEGIN/../END/')",
+        long,
+        value_name = "ADDRESSES",
+        global = true
+    )]
+    pub lines: Option<Addresses>,
+
+    #[arg(
+        help = "Only apply the operation to lines matching PATTERN, passing others through unchanged",
+        long = "if",
+        value_name = "PATTERN",
+        global = true
+    )]
+    pub if_pattern: Option<Pattern>,
+
+    #[arg(
+        help = "Only apply the operation to lines not matching PATTERN, passing others through unchanged",
+        long = "unless",
+        value_name = "PATTERN",
+        global = true
+    )]
+    pub unless_pattern: Option<Pattern>,
+
+    #[arg(
+        help = "Only apply the operation to field N of each line (zero-based, negative values count from end), leaving the rest of the line untouched",
+        long,
+        value_name = "N",
+        allow_negative_numbers = true,
+        global = true
+    )]
+    pub field: Option<i64>,
+
+    // --delim is taken by the operations that split into fields themselves
+    #[arg(
+        help = "Delimiter separating the fields for --field (defaults to whitespace)",
+        long,
+        value_name = "DELIM",
+        global = true
+    )]
+    pub field_delim: Option<String>,
+
+    #[arg(
+        help = "When to color output",
+        long,
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        global = true
+    )]
+    pub color: ColorChoice,
+
+    #[arg(
+        help = "Read each input whole and apply the operation to it once, so patterns can span lines",
+        long,
+        global = true
+    )]
+    pub slurp: bool,
+
+    #[arg(
+        help = "Split the input into records at SEP instead of at newlines",
+        long,
+        value_name = "SEP",
+        global = true
+    )]
+    pub record_sep: Option<String>,
+
+    #[arg(
+        help = "Split the input into records at blank lines, so each paragraph is operated on as a whole",
+        long,
+        global = true
+    )]
+    pub paragraph: bool,
+
+    #[arg(
+        help = "Read invalid UTF-8 in the input as U+FFFD replacement characters instead of failing",
+        long,
+        global = true
+    )]
+    pub lossy: bool,
+
+    #[arg(
+        help = "Carry invalid UTF-8 in the input through to the output byte for byte instead of failing",
+        long,
+        global = true
+    )]
+    pub binary: bool,
+
+    #[arg(
+        help = "Leave out the byte order mark an input starts with, which is otherwise written back at the start of the output",
+        long,
+        global = true
+    )]
+    pub strip_bom: bool,
+
+    #[arg(
+        help = "Line endings to write: those of the input, \\n or \\r\\n",
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value_t = LineEnding::Keep,
+        global = true
+    )]
+    pub crlf: LineEnding,
+
+    #[arg(
+        help = "Read the input in ENCODING (such as latin1, utf-16le or shift_jis) instead of UTF-8",
+        long,
+        value_name = "ENCODING",
+        value_parser = crate::records::parse_encoding,
+        global = true
+    )]
+    pub from_encoding: Option<&'static Encoding>,
+
+    #[arg(
+        help = "Write the output in ENCODING instead of UTF-8 (--in-place writes files back in --from-encoding by default)",
+        long,
+        value_name = "ENCODING",
+        value_parser = crate::records::parse_encoding,
+        global = true
+    )]
+    pub to_encoding: Option<&'static Encoding>,
+
+    #[arg(
+        help = "Show a progress bar with the estimated time left on stderr, when it is a terminal (the time is only estimated when the input size is known)",
+        long,
+        global = true
+    )]
+    pub progress: bool,
+
+    #[arg(
+        help = "When done, print how many lines were processed and modified, the bytes read and written, and the time taken to stderr",
+        long,
+        global = true
+    )]
+    pub stats: bool,
+
+    #[arg(
+        help = "Report to stderr, with the input and line number, each line that hit an error or was dropped or passed through unchanged because of one; twice (-vv), also each line the operation found no match in or that was skipped",
+        short,
+        long,
+        action = ArgAction::Count,
+        global = true
+    )]
+    pub verbose: u8,
+
+    #[arg(
+        help = "Map input files into memory instead of reading them, so plain UTF-8 lines are processed without being copied",
+        long,
+        global = true
+    )]
+    pub mmap: bool,
+
+    #[arg(
+        help = "Process lines on N threads, keeping their order (0 uses every core); operations that look at more than one line, such as sort and dedup, always use one",
+        short,
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        global = true
+    )]
+    pub jobs: usize,
+
+    #[arg(
+        help = "Stream each line through in chunks instead of reading it whole, so replace, remove and split-at-pat can handle lines of any length with literal patterns (with --crlf keep, output before the first line ending is read uses \\n)",
+        long,
+        global = true
+    )]
+    pub chunked: bool,
+
+    #[arg(
+        help = "Treat patterns as regular expressions",
+        short = 'E',
+        long,
+        global = true
+    )]
+    pub regex: bool,
+
+    #[arg(
+        help = "Match patterns regardless of case, using full Unicode case folding (ß matches SS; with --regex, folding is per character)",
+        long,
+        global = true
+    )]
+    pub fold_case: bool,
+
+    #[arg(
+        help = "Language whose case rules --fold-case follows (tr and az match I with ı and İ with i)",
+        long,
+        value_name = "LOCALE",
+        global = true
+    )]
+    pub locale: Option<String>,
+
+    #[arg(
+        help = "Print STRING in place of empty results and of lines the operation's pattern doesn't match",
+        long,
+        value_name = "STRING",
+        global = true
+    )]
+    pub empty_as: Option<String>,
+
+    #[arg(
+        help = "Drop empty results and lines the operation's pattern doesn't match",
+        long,
+        global = true
+    )]
+    pub drop_empty: bool,
+
+    #[arg(
+        help = "Write the pieces an operation splits a line into on one line, separated by DELIM, instead of on lines of their own",
+        long,
+        value_name = "DELIM",
+        global = true
+    )]
+    pub output_delim: Option<String>,
+
+    #[arg(
+        help = "Quote the pieces written on one line so they can be read back, such as by spreadsheets with csv (separated by commas unless --output-delim is given)",
+        long,
+        value_enum,
+        value_name = "STYLE",
+        global = true
+    )]
+    pub quote_output: Option<QuoteOutput>,
+
+    #[arg(
+        help = "Prefix each output line with its source, line number and a hash of the original line (kept through later annotated stages)",
+        long,
+        global = true
+    )]
+    pub annotate: bool,
+
+    #[arg(
+        help = "Strip the prefix added by --annotate from input lines before operating on them",
+        long,
+        global = true
+    )]
+    pub restore: bool,
+
+    #[arg(
+        help = "What to do with lines where a range operation's anchors are inverted, overlapping or out of bounds",
+        long,
+        value_enum,
+        default_value_t = OnInvalid::Error,
+        global = true
+    )]
+    pub on_invalid: OnInvalid,
+
+    #[arg(
+        help = "What to do with lines the operation's patterns don't occur in, instead of what each operation does with them on its own",
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value_t = NonMatching::KeepOriginal,
+        global = true
+    )]
+    pub non_matching: NonMatching,
+
+    #[arg(
+        help = "Output the complement of what the operation keeps: cut operations behave as the trim of the same range and trim operations as the cut, and filter keeps the lines without the pattern",
+        long,
+        global = true
+    )]
+    pub invert: bool,
+
+    #[arg(
+        help = "Anchor operations on a single pattern, such as cut-from-pat and trim-until-pat, on its last occurrence in the line instead of its first",
+        long,
+        global = true
+    )]
+    pub last: bool,
+}
+
+/// A runnable usage example, printed by `str examples` and checked against the real output by the tests.
+pub struct Example {
+    pub description: &'static str,
+    /// Arguments following `str`, starting with the operation
+    pub args: &'static [&'static str],
+    pub input: &'static str,
+    pub output: &'static str,
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        description: "Split each line at whitespace",
+        args: &["split-at-whitespace"],
+        input: "alpha beta  gamma",
+        output: "alpha\nbeta\ngamma",
+    },
+    Example {
+        description: "Split at a pattern, at most twice from the start",
+        args: &["split-at-pat", ", ", "2"],
+        input: "a, b, c",
+        output: "a\nb, c",
+    },
+    Example {
+        description: "Split at a character",
+        args: &["split-at-char", ":"],
+        input: "usr:local:bin",
+        output: "usr\nlocal\nbin",
+    },
+    Example {
+        description: "Split at a character, leaving quoted fields whole",
+        args: &["split-at-char", ",", "--respect-quotes"],
+        input: "a,\"b,c\",d",
+        output: "a\n\"b,c\"\nd",
+    },
+    Example {
+        description: "Keep only the last path segment",
+        args: &["segment", "-1", "-d", "/"],
+        input: "/usr/local/bin",
+        output: "bin",
+    },
+    Example {
+        description: "Get the value of a key in a structured log line",
+        args: &["get", "msg"],
+        input: "level=info msg=\"hi there\" user=bob",
+        output: "hi there",
+    },
+    Example {
+        description: "Get the file name of a path",
+        args: &["basename"],
+        input: "/var/log/app/",
+        output: "app",
+    },
+    Example {
+        description: "Get the directory of a path",
+        args: &["dirname"],
+        input: "C:\\Users\\me\\notes.txt",
+        output: "C:\\Users\\me",
+    },
+    Example {
+        description: "Get the extension of a path",
+        args: &["ext"],
+        input: "backup.tar.gz",
+        output: "gz",
+    },
+    Example {
+        description: "Get the file name of a path without its extension",
+        args: &["stem"],
+        input: "src/main.rs",
+        output: "main",
+    },
+    Example {
+        description: "Change the extension of a path",
+        args: &["with-ext", "md"],
+        input: "docs/readme.txt",
+        output: "docs/readme.md",
+    },
+    Example {
+        description: "Convert epoch seconds in a log line to RFC 3339",
+        args: &["time"],
+        input: "login at 1714566600 ok",
+        output: "login at 2024-05-01T12:30:00Z ok",
+    },
+    Example {
+        description: "Get the host of a URL",
+        args: &["url", "host"],
+        input: "https://example.com:8080/a/b?q=1#top",
+        output: "example.com",
+    },
+    Example {
+        description: "Cut from a pattern to the end of the line",
+        args: &["cut-from-pat", "="],
+        input: "key=value",
+        output: "=value",
+    },
+    Example {
+        description: "Cut from a pattern up to the last instance of another",
+        args: &["cut-from-pat-to-pat", "(", ")"],
+        input: "call(a, b);",
+        output: "(a, b",
+    },
+    Example {
+        description: "Cut every span between two patterns, one per line",
+        args: &["cut-from-pat-to-pat", "--all", "[", "]"],
+        input: "[a] and [b]",
+        output: "[a\n[b",
+    },
+    Example {
+        description: "Cut a fixed number of bytes starting at a pattern",
+        args: &["cut-from-pat-to-offset", "id=", "6"],
+        input: "user id=4821 ok",
+        output: "id=482",
+    },
+    Example {
+        description: "Cut up to (not including) a pattern",
+        args: &["cut-until-pat", "?"],
+        input: "/search?q=str",
+        output: "/search",
+    },
+    Example {
+        description: "Cut up to whichever of several patterns comes first",
+        args: &["cut-until-pat", "-e", "?", "-e", "#"],
+        input: "/search#top?q=str",
+        output: "/search",
+    },
+    Example {
+        description: "Trim (remove) from a pattern to the end of the line",
+        args: &["trim-from-pat", "#"],
+        input: "value # comment",
+        output: "value ",
+    },
+    Example {
+        description: "Trim between two patterns, keeping the end pattern",
+        args: &["trim-from-pat-to-pat", "<", ">"],
+        input: "a <b> c",
+        output: "a > c",
+    },
+    Example {
+        description: "Trim up to (not including) a pattern",
+        args: &["trim-until-pat", "ERROR"],
+        input: "12:00 ERROR disk full",
+        output: "ERROR disk full",
+    },
+    Example {
+        description: "Trim up to and including a pattern",
+        args: &["trim-to-pat", "://"],
+        input: "https://example.com",
+        output: "example.com",
+    },
+    Example {
+        description: "Keep only lines containing a pattern",
+        args: &["filter", "ERROR"],
+        input: "INFO ok\nERROR disk full",
+        output: "ERROR disk full",
+    },
+    Example {
+        description: "Print only the matches of a pattern",
+        args: &["match", "--all", "ab"],
+        input: "abcab",
+        output: "ab\nab",
+    },
+    Example {
+        description: "Print what is between nested brackets",
+        args: &["between", "--balanced", "{", "}"],
+        input: "f {a {b} c} g {d}",
+        output: "a {b} c\nd",
+    },
+    Example {
+        description: "Keep lines mentioning a host, even misspelled",
+        args: &["fuzzy", "database", "--max-dist", "2"],
+        input: "connect to databse-1\nconnect to cache-1",
+        output: "connect to databse-1",
+    },
+    Example {
+        description: "Split at a byte index",
+        args: &["split-at-index", "4"],
+        input: "2024-01-01",
+        output: "2024\n-01-01",
+    },
+    Example {
+        description: "Cut from a byte index to the end",
+        args: &["cut-from-index", "5"],
+        input: "2024-01-01",
+        output: "01-01",
+    },
+    Example {
+        description: "Cut between two byte indices (end-exclusive)",
+        args: &["cut-from-index-to-index", "5", "7"],
+        input: "2024-01-01",
+        output: "01",
+    },
+    Example {
+        description: "Cut a number of bytes starting at an index",
+        args: &["cut-from-index-to-offset", "0", "4"],
+        input: "2024-01-01",
+        output: "2024",
+    },
+    Example {
+        description: "Cut up to a byte index",
+        args: &["cut-until-index", "4"],
+        input: "2024-01-01",
+        output: "2024",
+    },
+    Example {
+        description: "Trim from a byte index to the end",
+        args: &["trim-from-index", "4"],
+        input: "2024-01-01",
+        output: "2024",
+    },
+    Example {
+        description: "Trim between two byte indices (end-exclusive)",
+        args: &["trim-from-index-to-index", "4", "7"],
+        input: "2024-01-01",
+        output: "2024-01",
+    },
+    Example {
+        description: "Trim a number of bytes starting at an index",
+        args: &["trim-from-index-to-offset", "4", "3"],
+        input: "2024-01-01",
+        output: "2024-01",
+    },
+    Example {
+        description: "Trim up to a byte index",
+        args: &["trim-until-index", "5"],
+        input: "2024-01-01",
+        output: "01-01",
+    },
+    Example {
+        description: "Trim whitespace from both ends",
+        args: &["trim"],
+        input: "  padded  ",
+        output: "padded",
+    },
+    Example {
+        description: "Trim a pattern from both ends",
+        args: &["trim", "*"],
+        input: "**bold**",
+        output: "bold",
+    },
+    Example {
+        description: "Replace patterns with another pattern",
+        args: &["replace", "cat", "dog", "--", "pet"],
+        input: "cat and dog",
+        output: "pet and pet",
+    },
+    Example {
+        description: "Replace only the last match",
+        args: &["replace", "--number=-1", "o", "--", "0"],
+        input: "foo boo",
+        output: "foo bo0",
+    },
+    Example {
+        description: "Remove patterns",
+        args: &["remove", "-", "_"],
+        input: "a-b_c",
+        output: "abc",
+    },
+    Example {
+        description: "Replace many patterns in one pass",
+        args: &["replace-many", "a=b", "b=a"],
+        input: "abba",
+        output: "baab",
+    },
+    Example {
+        description: "Join lines into a natural-language list",
+        args: &["join", ", ", "--final-delim", ", and "],
+        input: "apples\npears\nplums",
+        output: "apples, pears, and plums",
+    },
+    Example {
+        description: "Repeat each line, joined on one line",
+        args: &["repeat", "3", "--sep", "-"],
+        input: "ab\ncd",
+        output: "ab-ab-ab\ncd-cd-cd",
+    },
+    Example {
+        description: "Reverse the words of each line",
+        args: &["reverse", "--words"],
+        input: "one two  three",
+        output: "three  two one",
+    },
+    Example {
+        description: "Reverse a string without splitting combined characters",
+        args: &["reverse"],
+        input: "noe\u{308}l",
+        output: "le\u{308}on",
+    },
+    Example {
+        description: "Collapse log lines that differ only in their timestamp",
+        args: &["dedup-fuzzy", "--threshold", "0.8"],
+        input: "12:00:01 retrying db\n12:00:07 retrying db\n12:00:09 connected",
+        output: "12:00:01 retrying db\n12:00:09 connected",
+    },
+    Example {
+        description: "Count runs of repeated lines",
+        args: &["dedup", "--adjacent", "--count"],
+        input: "a\na\nb\na",
+        output: "      2 a\n      1 b\n      1 a",
+    },
+    Example {
+        description: "Sort by version number",
+        args: &["sort", "--version"],
+        input: "v1.10.0\nv1.9.2\nv1.10.0-rc1",
+        output: "v1.9.2\nv1.10.0-rc1\nv1.10.0",
+    },
+    Example {
+        description: "Find the most frequent words",
+        args: &["tokens", "--top", "2"],
+        input: "to be or\nnot to be",
+        output: "      2  33.33% to\n      2  33.33% be",
+    },
+    Example {
+        description: "List the word pairs of a line",
+        args: &["ngrams", "2"],
+        input: "to be or not",
+        output: "to be\nbe or\nor not",
+    },
+    Example {
+        description: "Find the most frequent lines",
+        args: &["freq", "--top", "2"],
+        input: "GET\nPOST\nGET\nPUT\nPOST\nGET",
+        output: "      3 GET\n      2 POST",
+    },
+    Example {
+        description: "Number lines with zero-padded numbers",
+        args: &["number", "--format", "{n:03}: "],
+        input: "alpha\nbeta",
+        output: "001: alpha\n002: beta",
+    },
+    Example {
+        description: "Expand tabs to stops every four columns",
+        args: &["expand", "4"],
+        input: "a\tbc\td",
+        output: "a   bc  d",
+    },
+    Example {
+        description: "Indent with tabs instead of spaces",
+        args: &["unexpand", "4"],
+        input: "        x = 1",
+        output: "\t\tx = 1",
+    },
+    Example {
+        description: "Collapse and trim spaces and tabs",
+        args: &["squeeze"],
+        input: "  too \t many   spaces ",
+        output: "too many spaces",
+    },
+    Example {
+        description: "Indent lines by four spaces",
+        args: &["indent", "4"],
+        input: "fn main() {}",
+        output: "    fn main() {}",
+    },
+    Example {
+        description: "Remove the indentation shared by all lines",
+        args: &["dedent", "--strip-common"],
+        input: "    if x:\n        y()",
+        output: "if x:\n    y()",
+    },
+    Example {
+        description: "Remove the directories shared by all paths",
+        args: &["strip-common-prefix", "-d", "/"],
+        input: "/srv/app/src/main.rs\n/srv/app/src/cli.rs\n/srv/app/Cargo.toml",
+        output: "src/main.rs\nsrc/cli.rs\nCargo.toml",
+    },
+    Example {
+        description: "Remove the suffix shared by all lines",
+        args: &["strip-common-suffix"],
+        input: "report.final.txt\nnotes.final.txt",
+        output: "report\nnotes",
+    },
+    Example {
+        description: "Align whitespace-separated fields into columns",
+        args: &["table"],
+        input: "name size\nCargo.toml 412\nsrc 4096",
+        output: "name        size\nCargo.toml  412\nsrc         4096",
+    },
+    Example {
+        description: "Turn columns into rows",
+        args: &["transpose", "-d", ","],
+        input: "a,b,c\n1,2,3",
+        output: "a,1\nb,2\nc,3",
+    },
+    Example {
+        description: "Encode as URL-safe base64 without padding",
+        args: &["b64-encode", "--url-safe", "--no-pad"],
+        input: "str?",
+        output: "c3RyPw",
+    },
+    Example {
+        description: "Decode base64, showing binary data as hex",
+        args: &["b64-decode"],
+        input: "aGVsbG8=\n/wA=",
+        output: "hello\nff00",
+    },
+    Example {
+        description: "Percent-encode a query parameter",
+        args: &["url-encode"],
+        input: "a&b=c d/é",
+        output: "a%26b%3Dc%20d%2F%C3%A9",
+    },
+    Example {
+        description: "Decode form data",
+        args: &["url-decode", "--plus"],
+        input: "q=hello+world%21",
+        output: "q=hello world!",
+    },
+    Example {
+        description: "Escape text for HTML",
+        args: &["html-escape"],
+        input: "<a href=\"x\">Tom & Jerry</a>",
+        output: "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&lt;/a&gt;",
+    },
+    Example {
+        description: "Decode HTML entities",
+        args: &["html-unescape"],
+        input: "caf&eacute; &lt;3 &#x263A;",
+        output: "café <3 ☺",
+    },
+    Example {
+        description: "Quote file names for a shell script",
+        args: &["shell-quote"],
+        input: "it's here.txt",
+        output: "'it'\\''s here.txt'",
+    },
+    Example {
+        description: "Undo shell quoting",
+        args: &["shell-unquote"],
+        input: "'it'\\''s' \"a \\\"b\\\"\" c\\ d",
+        output: "it's a \"b\" c d",
+    },
+    Example {
+        description: "Escape a line for a JSON payload",
+        args: &["json-escape", "--quote"],
+        input: "say \"hi\"\tnow",
+        output: "\"say \\\"hi\\\"\\tnow\"",
+    },
+    Example {
+        description: "Decode a JSON string literal",
+        args: &["json-unescape"],
+        input: "\"caf\\u00e9 \\ud83d\\ude00\"",
+        output: "café 😀",
+    },
+    Example {
+        description: "Make control characters visible",
+        args: &["escape"],
+        input: "a\tb\x1b[0m",
+        output: "a\\tb\\e[0m",
+    },
+    Example {
+        description: "Interpret backslash escapes",
+        args: &["unescape"],
+        input: "caf\\u{e9}\\tx\\x41",
+        output: "café\txA",
+    },
+    Example {
+        description: "Match a line literally with grep -E",
+        args: &["regex-quote", "--flavor", "ere"],
+        input: "price: $4.99 (tax)",
+        output: "price: \\$4\\.99 \\(tax\\)",
+    },
+    Example {
+        description: "Tag lines with a checksum",
+        args: &["hash", "crc32", "--append"],
+        input: "hello",
+        output: "hello\t3610a686",
+    },
+    Example {
+        description: "Apply ROT13",
+        args: &["rot"],
+        input: "Hello, World!",
+        output: "Uryyb, Jbeyq!",
+    },
+    Example {
+        description: "Make URL slugs from titles",
+        args: &["slug"],
+        input: "Crème Brûlée: A How-To!",
+        output: "creme-brulee-a-how-to",
+    },
+    Example {
+        description: "Transliterate text to ASCII",
+        args: &["deunicode"],
+        input: "Crème brûlée, Straße",
+        output: "Creme brulee, Strasse",
+    },
+    Example {
+        description: "Clip mixed-width text to 8 columns",
+        args: &["cut-to-width", "8", "--ellipsis", "…"],
+        input: "東京タワー tower",
+        output: "東京タ…",
+    },
+    Example {
+        description: "Drop the first 4 columns of mixed-width text",
+        args: &["trim-to-width", "4"],
+        input: "東京タワー tower",
+        output: "タワー tower",
+    },
+    Example {
+        description: "Rearrange fields with a template",
+        args: &["format", "{2}-{1} ({0})", "-d", ","],
+        input: "ada,lovelace,1815",
+        output: "1815-lovelace (ada)",
+    },
+    Example {
+        description: "Keep the first two lines",
+        args: &["head", "2"],
+        input: "one\ntwo\nthree",
+        output: "one\ntwo",
+    },
+    Example {
+        description: "Keep the last two lines",
+        args: &["tail", "2"],
+        input: "one\ntwo\nthree",
+        output: "two\nthree",
+    },
+    Example {
+        description: "Shuffle lines in an order that is the same on every run",
+        args: &["shuffle", "--seed", "5"],
+        input: "one\ntwo\nthree",
+        output: "three\none\ntwo",
+    },
+    Example {
+        description: "Pick lines at random, here from fewer lines than asked for",
+        args: &["sample", "5"],
+        input: "one\ntwo\nthree",
+        output: "one\ntwo\nthree",
+    },
+    Example {
+        description: "Highlight matches, here with color turned off",
+        args: &["highlight", "--color", "never", "warn", "error"],
+        input: "warn: disk at 91%",
+        output: "warn: disk at 91%",
+    },
+    Example {
+        description: "Mask email addresses before sharing a log",
+        args: &["redact", "--preset", "email"],
+        input: "login by jane@example.com from 10.0.0.7",
+        output: "login by **** from 10.0.0.7",
+    },
+    Example {
+        description: "Cut from a pattern to a byte index",
+        args: &["cut-from-pat-to-index", "=", "4"],
+        input: "ab=cdef",
+        output: "=c",
+    },
+    Example {
+        description: "Cut from a byte index to a pattern",
+        args: &["cut-from-index-to-pat", "1", "="],
+        input: "ab=cdef",
+        output: "b",
+    },
+    Example {
+        description: "Trim from a pattern to a byte index",
+        args: &["trim-from-pat-to-index", "=", "4"],
+        input: "ab=cdef",
+        output: "abdef",
+    },
+    Example {
+        description: "Trim from a byte index to a pattern",
+        args: &["trim-from-index-to-pat", "1", "="],
+        input: "ab=cdef",
+        output: "a=cdef",
+    },
+    Example {
+        description: "Pad on the left to a display width",
+        args: &["pad-left", "6", "0"],
+        input: "42",
+        output: "000042",
+    },
+    Example {
+        description: "Pad on the right to a display width",
+        args: &["pad-right", "6", "."],
+        input: "北京",
+        output: "北京..",
+    },
+    Example {
+        description: "Center within a display width",
+        args: &["center", "8", "*"],
+        input: "mid",
+        output: "**mid***",
+    },
+    Example {
+        description: "Wrap at word boundaries",
+        args: &["wrap", "10"],
+        input: "the quick brown fox jumps",
+        output: "the quick\nbrown fox\njumps",
+    },
+    Example {
+        description: "Divide one field by another as a percentage",
+        args: &["ratio", "1", "2", "--format", "percent"],
+        input: "GET 3 120",
+        output: "GET 3 120 2.50%",
+    },
+    Example {
+        description: "Mark ratios with a zero divisor as n/a",
+        args: &["ratio", "1", "2", "--substitute"],
+        input: "GET 3 0",
+        output: "n/a",
+    },
+    Example {
+        description: "Extract the numbers of a log line",
+        args: &["numbers", "--sep", " "],
+        input: "GET /api took 250ms, 3 retries",
+        output: "250 3",
+    },
+    Example {
+        description: "Extract signed offsets, including fractions without a leading zero",
+        args: &["numbers", "--signed", "--sep", " "],
+        input: "shifted -.5 then +2",
+        output: "-.5 +2",
+    },
+    Example {
+        description: "Convert kilobytes to megabytes inside text",
+        args: &["map-num", "/1024,round:1"],
+        input: "used 3584 of 8192",
+        output: "used 3.5 of 8.0",
+    },
+    Example {
+        description: "Bump counts and ranges but not dates or hyphenated names",
+        args: &["map-num", "+1"],
+        input: "2024-05-01 node-5 pages 10-20",
+        output: "2024-05-01 node-6 pages 11-21",
     },
-
-    #[command(
-        about = "Trims between two indices",
-        long_about = "Trims (removes) contents of a line between two indices."
-    )]
-    TrimFromIndexToIndex {
-        #[arg(help = "Index to begin trimming from (zero-based, index-inclusive)")]
-        start: usize,
-        #[arg(help = "Index to stop trimming at (zero-based, index-exclusive)")]
-        end: usize,
+    Example {
+        description: "Group the digits of numbers in thousands",
+        args: &["num-format", "--thousands", "--precision", "2"],
+        input: "total 1234567.891 EUR",
+        output: "total 1,234,567.89 EUR",
     },
-
-    #[command(
-        about = "Trims from an index ton an offset from the index",
-        long_about = "Trims (removes) starting from an index to a given offset amount from the index."
-    )]
-    TrimFromIndexToOffset {
-        #[arg(help = "Index to start trimming from (zero-based, index-inclusive)")]
-        index: usize,
-        #[arg(help = "Offset from index to trim to (negative values move backward from index)")]
-        offset: i64,
+    Example {
+        description: "Fix the decimal places of amounts without touching dates or versions",
+        args: &["num-format", "--precision", "2"],
+        input: "v1.2.3 on 2024-05-01: 3.5-4",
+        output: "v1.2.3 on 2024-05-01: 3.50-4.00",
     },
-
-    #[command(
-        about = "Trims until an index",
-        long_about = "Trims (removes) contents of a line until a given index."
-    )]
-    TrimUntilIndex {
-        #[arg(help = "Index to trim until (zero-baesd, index-exclusive)")]
-        index: usize,
+    Example {
+        description: "Write sizes in bytes as readable sizes",
+        args: &["humanize"],
+        input: "app.log 1503238553",
+        output: "app.log 1.4 GiB",
     },
-
-    #[command(
-        about = "Trims whitespace or patterns from both ends",
-        long_about = "Trims (removes) either whitespace (default) or patterns from the beginning and end of each line."
-    )]
-    Trim {
-        #[arg(help = "Optional: pattern to trim from beginning and end of input")]
-        pattern: Option<String>,
+    Example {
+        description: "Write seconds as durations, leaving dates alone",
+        args: &["humanize", "--duration"],
+        input: "2024-05-01 took 90061s",
+        output: "2024-05-01 took 1d 1h 1m 1s",
     },
-
-    #[command(
-        about = "Replaces a list of patterns with another pattern",
-        long_about = "Replaces instances of given patterns with another pattern, optionally a finite number of times per line (to remove patterns entirely, use remove command)."
-    )]
-    Replace {
-        #[arg(help = "Patterns to replace inline from input")]
-        patterns: Vec<String>,
-        #[arg(help = "What to replace pattern with", last(true))]
-        with: String,
-        #[arg(
-            help = "Optional: number of pattern-matches to replace (negative values start from end)",
-            short,
-            long
-        )]
-        number: Option<i64>,
+    Example {
+        description: "Turn durations back into seconds for sorting",
+        args: &["dehumanize", "--duration"],
+        input: "took 1h 30m",
+        output: "took 5400",
     },
-
-    #[command(
-        about = "Removes a pattern",
-        long_about = "Removes inline a given pattern, optioanlly a finite number of times per line."
-    )]
-    Remove {
-        #[arg(help = "List of patterns to remove inline from input")]
-        pattern: Vec<String>,
-        #[arg(
-            help = "Optional: number of pattern-matches to remove (negative values start from end)",
-            short,
-            long
-        )]
-        number: Option<i64>,
+    Example {
+        description: "Report the line lengths of the input",
+        args: &["stats"],
+        input: "hi\nhello\nhey",
+        output: "lines        3\n           min      max     mean   median\nbytes        2        5      3.3      3.0\nchars        2        5      3.3      3.0\nwidth        2        5      3.3      3.0",
     },
-
-    /* Mixed */
-    #[command(
-        about = "Cuts from a pattern to an index",
-        long_about = "Cuts contents of a line starting at the first character of a pattern to a given index (exclusive)."
-    )]
-    CutFromPatToIndex {
-        #[arg(help = "Pattern to begin cutting at (pattern-inclusive)")]
-        pattern: String,
-        #[arg(
-            help = "Index to stop cutting at, must be after pattern (zero-based, index-exclusive)"
-        )]
-        index: usize,
+    Example {
+        description: "Measure the entropy of each line",
+        args: &["metrics", "entropy"],
+        input: "aaaa\nabcd",
+        output: "0.000\n2.000",
     },
-
-    #[command(
-        about = "Cuts from an index to a pattern",
-        long_about = "Cuts contents of a line starting from a given index (inclusive) and the first character of a pattern."
-    )]
-    CutFromIndexToPat {
-        #[arg(help = "Index to begin cutting at (zero-based, index-inclusive)")]
-        index: usize,
-        #[arg(help = "Pattern to stop cutting at (pattern-exclusive)")]
-        pattern: String,
+    Example {
+        description: "Count words on each line",
+        args: &["count", "--words"],
+        input: "one two three",
+        output: "3",
     },
-
-    #[command(
-        about = "Trims between a pattern and an index",
-        long_about = "Trims (removes) contents of a line between the firts character of a pattern and a given index in the line."
-    )]
-    TrimFromPatToIndex {
-        #[arg(help = "Patter to begin trimming from (pattern-inclusive)")]
-        pattern: String,
-        #[arg(help = "Index to stop trimming at (index-exclusive)")]
-        index: usize,
+    Example {
+        description: "Count matches across the whole input",
+        args: &["count", "--total", "--matches", "o"],
+        input: "foo\nbor",
+        output: "3",
     },
-
-    #[command(
-        about = "Trims between an index and a pattern",
-        long_about = "Trims (removes) contents of a line starting from a given index (inclusive) and the first character of a pattern."
-    )]
-    TrimFromIndexToPat {
-        #[arg(help = "Index to begin trimming from (index-inclusive)")]
-        index: usize,
-        #[arg(help = "Pattern to stop trimming at (pattern-exclusive)")]
-        pattern: String,
+    Example {
+        description: "Test whether each line is a number",
+        args: &["is", "number"],
+        input: "42\nnope",
+        output: "true\nfalse",
     },
-}
+    Example {
+        description: "Run a Rhai script on each line",
+        args: &["script", "`${n}: ${fields[1].to_upper()}`"],
+        input: "a b\nc d",
+        output: "1: B\n2: D",
+    },
+    Example {
+        description: "Drop lines with a script",
+        args: &["script", "fields.len() > 1"],
+        input: "a b\nc",
+        output: "a b",
+    },
+    Example {
+        description: "Run operations described as JSON",
+        args: &[
+            "run",
+            "--json",
+            r#"[{"op": "split-at-char", "args": [","]}, {"op": "trim"}]"#,
+        ],
+        input: "b , a",
+        output: "b\na",
+    },
+];
 
-impl Default for Operation {
-    fn default() -> Self {
-        Self::SplitAtWhitespace { number: None }
-    }
-}
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
 
-impl Operation {
-    pub fn execute(&self, input: &String) -> Output {
-        use Operation::*;
-        use op_functions::*;
+    use super::*;
+    use crate::pattern::Folding;
+    use crate::process::Processor;
+    use crate::records::{RecordWriter, Separator};
 
-        match self {
-            /* Pattern-Based */
-            SplitAtWhitespace { number } => split_at_whitespace(*number, input),
-            SplitAtPat { number, pattern } => split_at_pat(*number, pattern, input),
-            SplitAtChar { number, char } => split_at_char(*number, *char, input),
-            CutFromPat { pattern } => cut_from_pat(pattern, input),
-            CutFromPatToPat { start, end } => cut_from_pat_to_pat(start, end, input),
-            CutFromPatToOffset { pattern, offset } => {
-                cut_from_pat_to_offset(pattern, *offset, input)
-            }
-            CutUntilPat { pattern } => cut_until_pat(pattern, input),
-            TrimFromPat { pattern } => trim_from_pat(pattern, input),
-            TrimFromPatToPat { start, end } => trim_from_pat_to_pat(start, end, input),
-            TrimUntilPat { pattern } => trim_until_pat(pattern, input),
-            TrimToPat { pattern } => trim_to_pat(pattern, input),
-            Trim { pattern } => trim(pattern, input),
-            Replace {
-                patterns,
-                with,
-                number,
-            } => replace(patterns, with, *number, input),
-            Remove { pattern, number } => replace(pattern, &"".to_string(), *number, input),
+    // set in the process `isolated` runs a test in
+    const ISOLATED: &str = "STR_TEST_ISOLATED";
 
-            /* Index-Based */
-            SplitAtIndex { index } => Output::Multiple({
-                let (a, b) = input.split_at(*index);
-                vec![a.into(), b.into()]
-            }),
-            CutFromIndex { index } => cut_from_index(*index, input),
-            CutFromIndexToIndex { start, end } => cut_from_index_to_index(*start, *end, input),
-            CutFromIndexToOffset { index, offset } => {
-                cut_from_index_to_offset(*index, *offset, input)
-            }
-            CutUntilIndex { index } => cut_until_index(*index, input),
-            TrimFromIndex { index } => trim_from_index(*index, input),
-            TrimFromIndexToIndex { start, end } => trim_from_index_to_index(*start, *end, input),
-            TrimFromIndexToOffset { index, offset } => {
-                trim_from_index_to_offset(*index, *offset, input)
-            }
-            TrimUntilIndex { index } => trim_until_index(*index, input),
+    /// A file of the given contents in the temporary directory, removed once dropped, as when a
+    /// test's assertion fails.
+    struct TempFile(std::path::PathBuf);
 
-            /* Mixed */
-            CutFromPatToIndex { pattern, index } => cut_from_pat_to_index(pattern, *index, input),
-            CutFromIndexToPat { index, pattern } => cut_from_index_to_pat(*index, pattern, input),
-            TrimFromPatToIndex { pattern, index } => trim_from_pat_to_index(pattern, *index, input),
-            TrimFromIndexToPat { index, pattern } => trim_from_index_to_pat(*index, pattern, input),
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("str-{}-{name}", std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            TempFile(path)
         }
-    }
-}
 
-mod op_functions {
-    use super::Output;
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
 
-    /* Pattern-Based */
-    pub fn split_at_whitespace(number: Option<i64>, input: &String) -> Output {
-        // define as closure to defer execution in case it's not needed
-        let trimmed = || input.split_whitespace().map(str::to_owned);
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
 
-        use Output::*;
-        match number {
-            None => Multiple(input.split_whitespace().map(str::to_owned).collect()),
-            Some(x) if x.is_negative() => {
-                Multiple(trimmed().rev().take(x.abs() as usize).collect())
-            } // not exactly intended behavior, collect remaining and return as one entry
-            Some(x) if x.is_positive() => Multiple(trimmed().take(x as usize).collect()),
-            _ => Single(input.to_owned()),
+    /// What `str` writes given `args` and `input` on stdin, read, processed and written as `main`
+    /// does it.
+    fn try_run(args: &[&str], input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut args = Args::try_parse_from(["str"].iter().chain(args))?;
+        let mut operation = args.operation.take().unwrap_or_default();
+        if args.invert {
+            operation = operation.inverted().map_err(anyhow::Error::msg)?;
         }
+        let separator = Separator::new(&args);
+        let mut writer = RecordWriter::new(Vec::new(), &separator, args.to_encoding);
+        let mut processor = Processor::new(&operation, &args)?;
+        crate::process(
+            &mut processor,
+            "<stdin>",
+            separator.records(input),
+            &mut writer,
+        )?;
+        crate::finish(&mut processor, &mut writer)?;
+        Ok(writer.end()?)
     }
 
-    pub fn split_at_pat(number: Option<i64>, pattern: &String, input: &String) -> Output {
-        use super::Output::*;
-        match number {
-            None => Multiple(input.split(pattern).map(str::to_owned).collect()),
-            Some(x) if x.is_negative() => Multiple(
-                input
-                    .rsplitn(x.abs() as usize, pattern)
-                    .map(str::to_owned)
-                    .collect(),
-            ),
-            Some(x) if x.is_positive() => Multiple(
-                input
-                    .splitn(x as usize, pattern)
-                    .map(str::to_owned)
-                    .collect(),
-            ),
-            _ => Single(input.to_owned()),
+    /// Runs `test` in a process of its own, in which the settings `main` makes once for the whole
+    /// process (`records::configure` and `pattern::configure`) are made from `args`: the test
+    /// binary is run again for the test `name` alone, which calls `test`. Returns what that process
+    /// wrote to stderr, or `None` within it. As the settings can only be made once, a test can
+    /// only call it once.
+    fn isolated(name: &str, args: &[&str], test: impl FnOnce()) -> Option<String> {
+        if std::env::var_os(ISOLATED).is_some() {
+            let args = Args::try_parse_from(["str"].iter().chain(args)).unwrap();
+            crate::records::configure(&args);
+            crate::pattern::configure(
+                args.regex,
+                Separator::new(&args).multiline(),
+                Folding::new(args.fold_case, args.locale.as_deref()),
+                args.last,
+            );
+            test();
+            return None;
         }
+
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([&format!("cli::tests::{name}"), "--exact", "--nocapture"])
+            .env(ISOLATED, "1")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        // a misspelled name would run no test at all
+        let ran = String::from_utf8_lossy(&output.stdout).contains("1 passed");
+        assert!(output.status.success() && ran, "{name}: {stderr}");
+        Some(stderr)
     }
 
-    pub fn split_at_char(number: Option<i64>, char: char, input: &String) -> Output {
-        use Output::*;
-        match number {
-            None => Multiple(input.split(char).map(str::to_owned).collect()),
-            Some(x) if x.is_negative() => Multiple(
-                input
-                    .rsplitn(x.abs() as usize, char)
-                    .map(str::to_owned)
-                    .collect(),
-            ),
-            Some(x) if x.is_positive() => {
-                Multiple(input.splitn(x as usize, char).map(str::to_owned).collect())
-            }
-            _ => Single(input.to_owned()),
+    /// `try_run` on text, for runs that succeed.
+    fn run(args: &[&str], input: &str) -> String {
+        let output = try_run(args, input.as_bytes()).unwrap_or_else(|e| panic!("{args:?}: {e:?}"));
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn examples_produce_their_output() {
+        for example in EXAMPLES {
+            let mut args = Args::try_parse_from(["str"].iter().chain(example.args))
+                .unwrap_or_else(|e| panic!("{:?}: {e}", example.args));
+            let operation = args.operation.take().unwrap();
+            let mut processor = Processor::new(&operation, &args).unwrap();
+
+            let mut output: Vec<String> = example
+                .input
+                .lines()
+                .filter_map(|line| processor.apply(line).unwrap())
+                .collect();
+            output.extend(processor.finish().unwrap());
+            assert_eq!(output.join("\n"), example.output, "{:?}", example.args);
         }
     }
 
-    pub fn cut_from_pat(pattern: &String, input: &String) -> Output {
-        Output::Single(input[input.find(pattern).unwrap_or(0)..].to_string())
+    #[test]
+    fn operations_round_trip_through_json() {
+        for example in EXAMPLES {
+            let mut args = Args::try_parse_from(["str"].iter().chain(example.args)).unwrap();
+            let json = serde_json::to_string(&args.operation.take().unwrap()).unwrap();
+            let operation: Operation = serde_json::from_str(&json)
+                .unwrap_or_else(|e| panic!("{:?}: {json}: {e}", example.args));
+            assert_eq!(serde_json::to_string(&operation).unwrap(), json);
+
+            let mut processor = Processor::new(&operation, &args).unwrap();
+            let mut output: Vec<String> = example
+                .input
+                .lines()
+                .filter_map(|line| processor.apply(line).unwrap())
+                .collect();
+            output.extend(processor.finish().unwrap());
+            assert_eq!(output.join("\n"), example.output, "{json}");
+        }
     }
 
-    pub fn cut_from_pat_to_pat(start: &String, end: &String, input: &String) -> Output {
-        Output::Single(
-            input[input.find(start).unwrap_or(0)..input.rfind(end).unwrap_or(input.capacity())]
-                .to_string(),
-        )
+    #[test]
+    fn every_operation_has_an_example() {
+        for subcommand in Args::command().get_subcommands() {
+            let name = subcommand.get_name();
+            // presets and the operations on a second input read a file of the user's, so they are
+            // tested below instead, and tui is interactive
+            assert!(
+                matches!(
+                    name,
+                    "examples" | "preset" | "zip" | "only-in" | "also-in" | "union" | "tui"
+                ) || EXAMPLES.iter().any(|e| e.args[0] == name),
+                "no example for {name}"
+            );
+        }
     }
 
-    // separate fn for cut from last pat?
-    pub fn cut_from_pat_to_offset(pattern: &String, offset: i64, input: &String) -> Output {
-        let start_idx = input.find(pattern).unwrap_or(0);
-        if offset as usize + start_idx > input.len() - 1 {
-            eprintln!("Offset exits bounds of input");
-            std::process::exit(1); // this could be better with anyhow
+    #[test]
+    fn presets_run_their_stages_in_order() {
+        let file = TempFile::new(
+            "presets.toml",
+            "tidy = [\"split-at-char ,\", { op = \"trim\" }, \"sort\", { op = \"head\", args = [2] }]\nlast = \"tail 1\"\n",
+        );
+        for (name, output) in [("tidy", "a\nb\n"), ("last", "c,a , b\n")] {
+            assert_eq!(
+                run(
+                    &["preset", name, "--presets", file.path()],
+                    "  d,c \nc,a , b"
+                ),
+                output,
+                "{name}"
+            );
         }
+    }
 
-        Output::Single(
-            input[if offset.is_negative() {
-                start_idx + (offset.abs() as usize)..start_idx
-            } else {
-                start_idx..start_idx + (offset as usize)
-            }]
-            .to_string(),
-        )
+    #[test]
+    fn zip_pairs_lines_until_both_inputs_run_out() {
+        let file = TempFile::new("zip.txt", "1\n2\n3\n");
+        assert_eq!(
+            run(&["zip", file.path(), "-d", ","], "a\nb"),
+            "a,1\nb,2\n,3\n"
+        );
     }
 
-    // separate fn for cut until last pat?
-    pub fn cut_until_pat(pattern: &String, input: &String) -> Output {
-        Output::Single(input[..input.find(pattern).unwrap_or(input.len())].to_string())
+    #[test]
+    fn set_operations_compare_whole_lines() {
+        let file = TempFile::new("set.txt", "b\nc\nd\n");
+        for (name, output) in [
+            ("only-in", "a\nab\n"),
+            ("also-in", "b\nb\n"),
+            ("union", "a\nb\nab\nc\nd\n"),
+        ] {
+            assert_eq!(run(&[name, file.path()], "a\nb\nab\nb"), output, "{name}");
+        }
     }
 
-    pub fn trim_from_pat(pattern: &String, input: &String) -> Output {
-        Output::Single(input[input.find(pattern).unwrap_or(0)..].to_string())
+    #[test]
+    fn scripts_skip_comments_and_blank_lines() {
+        let file = TempFile::new("script.str", "# tidy up\nsplit-at-char ,\n\n  trim\n");
+        let operation = Operation::Stages(Pipeline::script(&file.0).unwrap());
+        let args = Args::try_parse_from(["str"]).unwrap();
+        let mut processor = Processor::new(&operation, &args).unwrap();
+        assert_eq!(processor.apply("b , a").unwrap().as_deref(), Some("b\na"));
     }
 
-    pub fn trim_from_pat_to_pat(start: &String, end: &String, input: &String) -> Output {
-        Output::Single(
-            input[input.find(start).unwrap_or(0)..input.rfind(end).unwrap_or(input.len() - 1)]
-                .to_string(),
-        )
+    #[test]
+    fn lines_select_numbers_ranges_and_pattern_ranges() {
+        let input = "a\nBEGIN a\na\nEND a\na\na";
+        for (lines, output) in [
+            ("1", "b\nBEGIN a\na\nEND a\na\na"),
+            ("2-3,5", "a\nBEGIN b\nb\nEND a\nb\na"),
+            ("5..", "a\nBEGIN a\na\nEND a\nb\nb"),
+            ("..2", "b\nBEGIN b\na\nEND a\na\na"),
+            ("4-2", "a\nBEGIN a\na\nEND b\na\na"),
+            ("/BEGIN/../END/", "a\nBEGIN b\nb\nEND b\na\na"),
+            ("/END/..", "a\nBEGIN a\na\nEND b\nb\nb"),
+        ] {
+            let output = output.to_string() + "\n";
+            assert_eq!(
+                run(&["--lines", lines, "replace", "a", "--", "b"], input),
+                output,
+                "{lines}"
+            );
+        }
+        for lines in ["0", "1-/a/", "/a", "2x", ""] {
+            assert!(
+                Args::try_parse_from(["str", "--lines", lines, "trim"]).is_err(),
+                "{lines}"
+            );
+        }
     }
 
-    // separate fn for trim until last pat?
-    pub fn trim_until_pat(pattern: &String, input: &String) -> Output {
-        Output::Single(input[input.find(pattern).unwrap_or(0)..].to_string())
+    #[test]
+    fn if_and_unless_only_apply_the_operation_to_some_lines() {
+        let input = "# a,b\nc,d\n# e,f";
+        assert_eq!(
+            run(&["--if", "#", "split-at-char", ","], input),
+            "# a\nb\nc,d\n# e\nf\n"
+        );
+        assert_eq!(
+            run(&["--unless", "#", "split-at-char", ","], input),
+            "# a,b\nc\nd\n# e,f\n"
+        );
+        assert_eq!(
+            run(&["--if", "#", "--unless", "e", "split-at-char", ","], input),
+            "# a\nb\nc,d\n# e,f\n"
+        );
     }
 
-    pub fn trim_to_pat(pattern: &String, input: &String) -> Output {
-        Output::Single(input[input.find(pattern).unwrap_or(0) + input.len()..].to_string())
+    #[test]
+    fn slurp_applies_the_operation_once_to_the_whole_input() {
+        let input = "a\nSTART x\ny\nEND z\nb\n";
+        assert_eq!(
+            run(&["--slurp", "cut-from-pat-to-pat", "START", "END"], input),
+            "START x\ny\n\n"
+        );
+        assert_eq!(run(&["--slurp", "count", "--words"], input), "7\n");
+        assert_eq!(run(&["--slurp", "trim"], ""), "");
     }
 
-    pub fn trim(pattern: &Option<String>, input: &String) -> Output {
-        Output::Single(match pattern {
-            None => input.trim().to_owned(),
-            Some(p) => input
-                .to_owned()
-                .trim_start_matches(p)
-                .trim_end_matches(p)
-                .to_owned(),
-        })
+    #[test]
+    fn record_sep_and_paragraph_split_the_input_into_records() {
+        assert_eq!(
+            run(&["--record-sep", ";", "trim"], " a ; b\nc ;"),
+            "a;b\nc\n"
+        );
+        assert_eq!(
+            run(&["--paragraph", "count", "--words"], "\na b\nc\n\n\nd\n"),
+            "3\n\n1\n"
+        );
+        assert_eq!(
+            run(
+                &["--paragraph", "filter", "ERROR"],
+                "ok\n\nERROR x\nat y\n\nok"
+            ),
+            "ERROR x\nat y\n"
+        );
     }
 
-    pub fn replace(
-        pattern: &Vec<String>,
-        with: &String,
-        number: Option<i64>,
-        input: &String,
-    ) -> Output {
-        let mut tmp = input.clone();
+    #[test]
+    fn invalid_utf8_fails_unless_read_lossily() {
+        assert!(try_run(&["trim"], b"a\xffb").is_err());
+        isolated(
+            "invalid_utf8_fails_unless_read_lossily",
+            &["--lossy"],
+            || {
+                assert_eq!(
+                    try_run(&["--lossy", "replace", "a", "--", "c"], b"a\xffb\n").unwrap(),
+                    "c\u{fffd}b\n".as_bytes()
+                );
+            },
+        );
+    }
 
-        // first find matches indices and push the first `number`` of them to a vector (place this in match arm?)
-        let mut found: Vec<(usize, usize)> = pattern
-            .iter()
-            .map(|p| input.match_indices(p).map(|m| (m.0, m.0 + p.len())))
-            .flatten()
-            .collect();
-        found.sort();
+    #[test]
+    fn binary_carries_invalid_utf8_through() {
+        isolated("binary_carries_invalid_utf8_through", &["--binary"], || {
+            assert_eq!(
+                try_run(&["--binary", "replace", "a", "--", "c"], b"a\xff\x80b\n").unwrap(),
+                b"c\xff\x80b\n"
+            );
+            assert_eq!(
+                try_run(
+                    &["--binary", "split-at-char", "\u{fffd}"],
+                    b"\xef\xbf\xbd\xff"
+                )
+                .unwrap(),
+                b"\n\xff\n"
+            );
+            // a character in the range invalid bytes are carried as is written as it was read
+            assert_eq!(
+                try_run(&["--binary", "trim"], b" \xee\xbe\x80\xff ").unwrap(),
+                b"\xee\xbe\x80\xff\n"
+            );
+        });
+    }
 
-        match number {
-            None => {
-                found.iter().rev().for_each(|i| {
-                    tmp.drain(i.0..i.1);
-                    tmp.insert_str(i.0, &with);
-                });
-            }
-            Some(x) if x.is_negative() => {
-                found.iter().rev().take(x.abs() as usize).for_each(|i| {
-                    tmp.drain(i.0..i.1);
-                    tmp.insert_str(i.0, &with);
-                });
-            }
-            Some(x) if x.is_positive() => {
-                found.iter().take(x.abs() as usize).rev().for_each(|i| {
-                    tmp.drain(i.0..i.1);
-                    tmp.insert_str(i.0, &with);
-                });
-            }
-            _ => {}
-        };
+    #[test]
+    fn encodings_are_converted_on_input_and_output() {
+        assert_eq!(
+            try_run(&["--to-encoding", "latin1", "trim"], " café ".as_bytes()).unwrap(),
+            b"caf\xe9\n"
+        );
+        assert_eq!(
+            try_run(&["--to-encoding", "utf-16le", "trim"], " é ".as_bytes()).unwrap(),
+            b"\xe9\x00\n\x00"
+        );
+        assert!(try_run(&["--to-encoding", "latin1", "trim"], "✓".as_bytes()).is_err());
+        assert!(Args::try_parse_from(["str", "--to-encoding", "klingon"]).is_err());
 
-        Output::Single(tmp.to_owned())
+        isolated(
+            "encodings_are_converted_on_input_and_output",
+            &["--from-encoding", "shift_jis"],
+            || {
+                // あ、い
+                let input = b"\x82\xa0\x81\x41\x82\xa2";
+                assert_eq!(
+                    try_run(
+                        &["--from-encoding", "shift_jis", "split-at-char", "、"],
+                        input
+                    )
+                    .unwrap(),
+                    "あ\nい\n".as_bytes()
+                );
+                let args = [
+                    "--from-encoding",
+                    "shift_jis",
+                    "--to-encoding",
+                    "shift_jis",
+                    "split-at-char",
+                    "、",
+                ];
+                assert_eq!(try_run(&args, input).unwrap(), b"\x82\xa0\n\x82\xa2\n");
+            },
+        );
     }
 
-    /* Index-Based */
-    pub fn cut_from_index(index: usize, input: &String) -> Output {
-        Output::Single(input[index..].to_string())
+    #[test]
+    fn the_line_endings_of_the_input_are_kept() {
+        isolated("the_line_endings_of_the_input_are_kept", &[], || {
+            // records never hold the \r, whatever is written
+            assert_eq!(run(&["count"], "ab\r\nc\r\n"), "2\r\n1\r\n");
+            assert_eq!(run(&["count"], "ab\nc\r\n"), "2\n1\n");
+        });
     }
 
-    pub fn cut_from_index_to_index(start: usize, end: usize, input: &String) -> Output {
-        Output::Single(input[start..end].to_string())
+    #[test]
+    fn crlf_crlf_writes_windows_line_endings() {
+        isolated(
+            "crlf_crlf_writes_windows_line_endings",
+            &["--crlf", "crlf"],
+            || {
+                assert_eq!(
+                    run(&["--crlf", "crlf", "split-at-char", ","], "a,b\nc"),
+                    "a\r\nb\r\nc\r\n"
+                );
+            },
+        );
     }
 
-    pub fn cut_from_index_to_offset(index: usize, offset: i64, input: &String) -> Output {
-        Output::Single(
-            input[if offset.is_negative() {
-                index + (offset as usize - 1)..index
-            } else {
-                index..index + offset as usize
-            }]
-            .to_string(),
-        )
+    #[test]
+    fn crlf_lf_writes_unix_line_endings() {
+        isolated(
+            "crlf_lf_writes_unix_line_endings",
+            &["--crlf", "lf"],
+            || {
+                assert_eq!(run(&["--crlf", "lf", "count"], "ab\r\nc\r\n"), "2\n1\n");
+            },
+        );
     }
 
-    pub fn cut_until_index(index: usize, input: &String) -> Output {
-        Output::Single(input[..if index != 0 { index } else { input.len() }].to_string())
+    #[test]
+    fn a_byte_order_mark_is_hidden_from_the_operation_and_written_back() {
+        isolated(
+            "a_byte_order_mark_is_hidden_from_the_operation_and_written_back",
+            &[],
+            || {
+                assert_eq!(run(&["count"], "\u{feff}ab\nc"), "\u{feff}2\n1\n");
+                // a UTF-16 input is recognized by its mark
+                assert_eq!(
+                    try_run(&["count"], b"\xff\xfea\x00b\x00\n\x00").unwrap(),
+                    "\u{feff}2\n".as_bytes()
+                );
+            },
+        );
     }
 
-    pub fn trim_from_index(index: usize, input: &String) -> Output {
-        Output::Single(input[..if index != 0 { index } else { input.len() }].to_string())
+    #[test]
+    fn strip_bom_leaves_out_the_byte_order_mark() {
+        isolated(
+            "strip_bom_leaves_out_the_byte_order_mark",
+            &["--strip-bom"],
+            || assert_eq!(run(&["--strip-bom", "count"], "\u{feff}ab\nc"), "2\n1\n"),
+        );
     }
 
-    pub fn trim_from_index_to_index(start: usize, end: usize, input: &String) -> Output {
-        Output::Single(if end <= start {
-            input.clone()
-        } else {
-            input[..start].to_string() + &input[end..]
-        })
+    #[test]
+    fn chunked_matches_across_the_ends_of_chunks() {
+        use crate::stream::{CHUNK_SIZE, Streamer};
+
+        // the first match starts two bytes before the end of the first chunk
+        let (a, b) = ("a".repeat(CHUNK_SIZE - 2), "b".repeat(3 * CHUNK_SIZE));
+        let line = format!("{a}needle{b}needle");
+        let input = format!("{line}\nneedle a needle\n{line}");
+        for args in [
+            &["replace", "needle", "--", "pin"][..],
+            &["replace", "--number", "1", "needle", "--", "pin"],
+            &["remove", "needle"],
+            &["split-at-pat", "needle"],
+        ] {
+            let mut parsed = Args::try_parse_from(["str"].iter().chain(args)).unwrap();
+            let operation = parsed.operation.take().unwrap();
+            let mut writer = RecordWriter::new(Vec::new(), &Separator::Line, None);
+            let matched = Streamer::new(&operation)
+                .unwrap()
+                .run(input.as_bytes(), &mut writer)
+                .unwrap();
+            let output = String::from_utf8(writer.end().unwrap()).unwrap();
+            assert!(matched, "{args:?}");
+            assert!(output == run(args, &input), "{args:?}");
+        }
     }
 
-    pub fn trim_from_index_to_offset(index: usize, offset: i64, input: &String) -> Output {
-        Output::Single(if offset.is_negative() {
-            input[..index + offset as usize].to_string() + &input[index + 1..]
-        } else {
-            input[..index].to_string() + &input[index + offset as usize..]
-        })
+    #[test]
+    fn jobs_keep_the_order_of_the_lines() {
+        let input: Vec<String> = (0..crate::BATCH_SIZE * 5 / 2)
+            .map(|n| format!("{n} {}", "ab".repeat(n % 7)))
+            .collect();
+        let input = input.join("\n");
+        for args in [
+            &["replace", "a", "--", "c"][..],
+            &["--lines", "/^10 /../^4000 /,9000..", "remove", "b"],
+            &["--annotate", "split-at-char", " "],
+            &["sort"],
+        ] {
+            let jobs: Vec<&str> = ["--jobs", "4"].iter().chain(args).copied().collect();
+            assert!(run(&jobs, &input) == run(args, &input), "{args:?}");
+        }
     }
 
-    pub fn trim_until_index(index: usize, input: &String) -> Output {
-        Output::Single(input[index..].to_string())
+    #[test]
+    fn non_matching_passes_or_drops_lines_without_the_pattern() {
+        let input = "k=v\nnone";
+        for (non_matching, args, output) in [
+            ("keep-original", &["cut-from-pat", "="][..], "=v\nnone\n"),
+            ("drop", &["cut-from-pat", "="], "=v\n"),
+            ("keep-original", &["match", "="], "=\n"),
+            ("pass", &["match", "="], "=\nnone\n"),
+        ] {
+            let args: Vec<&str> = ["--non-matching", non_matching]
+                .iter()
+                .chain(args)
+                .copied()
+                .collect();
+            assert_eq!(run(&args, input), output, "{args:?}");
+        }
     }
 
-    pub fn cut_from_pat_to_index(pattern: &String, index: usize, input: &String) -> Output {
-        // implement only matching after the index?
-        let found_idx = input.find(pattern).unwrap_or(0);
-        if index < found_idx {
-            eprintln!("Pattern was found before desired index");
-            std::process::exit(1);
-        } else if index == found_idx {
-            return Output::Single(input.clone());
+    #[test]
+    fn invert_outputs_the_complement_of_what_the_operation_keeps() {
+        for (args, input, output) in [
+            (&["cut-from-pat", "="][..], "k=v", "k"),
+            (&["trim-from-pat", "="], "k=v", "=v"),
+            (&["cut-until-pat", "="], "k=v", "=v"),
+            (&["cut-from-pat-to-pat", "(", ")"], "f(a);", "f);"),
+            (&["cut-from-index-to-index", "1", "3"], "abcd", "ad"),
+            (&["cut-to-width", "2"], "abcd", "cd"),
+            (&["filter", "ERROR"], "INFO ok\nERROR no", "INFO ok"),
+        ] {
+            let inverted: Vec<&str> = ["--invert"].iter().chain(args).copied().collect();
+            assert_eq!(run(&inverted, input), output.to_string() + "\n", "{args:?}");
+            // the inverse of the inverse is the operation itself
+            let operation = Args::try_parse_from(["str"].iter().chain(args))
+                .unwrap()
+                .operation
+                .unwrap();
+            let json = serde_json::to_string(&operation).unwrap();
+            let twice = operation.inverted().and_then(Operation::inverted).unwrap();
+            assert_eq!(serde_json::to_string(&twice).unwrap(), json);
         }
+        // a single index keeps one side of the line and inverted the other, except at zero, where
+        // it keeps the whole line and inverted none of it
+        for (op, kept) in [
+            ("cut-from-index", "bc"),
+            ("trim-from-index", "a"),
+            ("cut-until-index", "a"),
+            ("trim-until-index", "bc"),
+        ] {
+            assert_eq!(run(&[op, "1"], "abc"), format!("{kept}\n"), "{op}");
+            let complement = if kept == "a" { "bc" } else { "a" };
+            assert_eq!(
+                run(&["--invert", op, "1"], "abc"),
+                format!("{complement}\n"),
+                "{op}"
+            );
+            assert_eq!(run(&[op, "0"], "abc"), "abc\n", "{op}");
+            assert_eq!(run(&["--invert", op, "0"], "abc"), "\n", "{op}");
+        }
+        assert!(try_run(&["--invert", "sort"], b"a").is_err());
+    }
 
-        Output::Single(input[found_idx..index].to_string())
+    #[test]
+    fn replace_many_rejects_regex_and_fold_case() {
+        isolated(
+            "replace_many_rejects_regex_and_fold_case",
+            &["--fold-case"],
+            || {
+                let error = try_run(&["--fold-case", "replace-many", "a=b"], b"A").unwrap_err();
+                assert!(
+                    format!("{error:?}").contains("--regex and --fold-case can't be used"),
+                    "{error:?}"
+                );
+            },
+        );
     }
 
-    pub fn cut_from_index_to_pat(index: usize, pattern: &String, input: &String) -> Output {
-        let found_idx = input.find(pattern).unwrap_or(0);
-        if index > found_idx {
-            eprintln!("First pattern instance was found after desired index");
-            std::process::exit(1);
-        } else if index == found_idx {
-            return Output::Single(input.clone());
-        }
+    #[test]
+    fn last_anchors_on_the_last_occurrence_of_the_pattern() {
+        isolated(
+            "last_anchors_on_the_last_occurrence_of_the_pattern",
+            &["--last"],
+            || {
+                for (op, output) in [
+                    ("cut-from-pat", "/c\n"),
+                    ("trim-from-pat", "a/b\n"),
+                    ("cut-until-pat", "a/b\n"),
+                    ("trim-until-pat", "/c\n"),
+                    ("trim-to-pat", "c\n"),
+                ] {
+                    assert_eq!(run(&["--last", op, "/"], "a/b/c"), output, "{op}");
+                }
+            },
+        );
+    }
 
-        Output::Single(input[index..found_idx].to_string())
+    #[test]
+    fn tee_writes_the_output_to_a_file_as_well() {
+        let file = TempFile::new("tee.txt", "old\n");
+        let args = Args::try_parse_from(["str", "split-at-char", ","]).unwrap();
+        let operation = args.operation.as_ref().unwrap();
+        let tee = std::io::BufWriter::new(std::fs::File::create(file.path()).unwrap());
+        let mut writer = RecordWriter::new(crate::Tee(Vec::new(), tee), &Separator::Line, None);
+        let mut processor = Processor::new(operation, &args).unwrap();
+        let input = "a,b\nc".as_bytes();
+        crate::process(
+            &mut processor,
+            "<stdin>",
+            Separator::Line.records(input),
+            &mut writer,
+        )
+        .unwrap();
+        crate::finish(&mut processor, &mut writer).unwrap();
+
+        let crate::Tee(output, _) = writer.end().unwrap();
+        assert_eq!(output, b"a\nb\nc\n");
+        assert_eq!(std::fs::read(file.path()).unwrap(), output);
     }
 
-    pub fn trim_from_pat_to_index(pattern: &String, index: usize, input: &String) -> Output {
-        let found_idx = input.find(pattern).unwrap_or(0);
+    #[test]
+    fn verbose_reports_lines_dropped_or_passed_through() {
+        let stderr = isolated(
+            "verbose_reports_lines_dropped_or_passed_through",
+            &[],
+            || {
+                let args = [
+                    "-v",
+                    "--lines",
+                    "1-2",
+                    "--non-matching",
+                    "drop",
+                    "cut-from-pat",
+                    "=",
+                ];
+                assert_eq!(run(&args, "k=v\nnone\nx=y\nnone"), "=v\nx=y\nnone\n");
+            },
+        );
+        if let Some(stderr) = stderr {
+            assert!(
+                stderr.contains("<stdin>:2: no match, dropped: \"none\"\n"),
+                "{stderr}"
+            );
+            // passing through lines --lines doesn't select is only reported with -vv
+            assert!(!stderr.contains("<stdin>:3"), "{stderr}");
+        }
+    }
 
-        if index < found_idx {
-            eprintln!("Pattern was found before desired index");
-            std::process::exit(1);
-        } else if index == found_idx {
-            return Output::Single(input.clone());
+    #[test]
+    fn very_verbose_also_reports_lines_left_alone() {
+        let stderr = isolated("very_verbose_also_reports_lines_left_alone", &[], || {
+            let args = ["-vv", "--lines", "1", "cut-from-pat", "="];
+            assert_eq!(run(&args, "none\nx=y"), "none\nx=y\n");
+        });
+        if let Some(stderr) = stderr {
+            assert!(
+                stderr.contains("<stdin>:1: no match: \"none\"\n"),
+                "{stderr}"
+            );
+            assert!(
+                stderr.contains("<stdin>:2: not selected by --lines, passed through: \"x=y\"\n"),
+                "{stderr}"
+            );
         }
+    }
+
+    #[test]
+    fn dry_run_counts_the_lines_that_would_change() {
+        let args = Args::try_parse_from(["str", "--dry-run", "trim-from-pat", "#"]).unwrap();
+        let operation = args.operation.as_ref().unwrap();
+        let mut processor = Processor::new(operation, &args).unwrap();
+        let mut preview = crate::Preview::new(false);
+        let input = "a # b\nc\nd # e".as_bytes();
+        crate::dry_run(
+            &mut processor,
+            "in.txt",
+            Separator::Line.records(input),
+            &mut preview,
+        )
+        .unwrap();
+        crate::dry_run_finish(&mut processor, &mut preview).unwrap();
+        assert_eq!((preview.changed, preview.total), (2, 3));
+        // nothing is kept for a diff that wasn't asked for
+        assert!(preview.before.is_empty() && preview.after.is_empty());
+    }
 
-        Output::Single(input[..found_idx].to_string() + &input[index..])
+    #[test]
+    fn diff_shows_the_lines_that_would_change() {
+        let args =
+            Args::try_parse_from(["str", "--dry-run", "--diff", "split-at-char", ","]).unwrap();
+        let operation = args.operation.as_ref().unwrap();
+        let mut processor = Processor::new(operation, &args).unwrap();
+        let mut preview = crate::Preview::new(true);
+        let input = "a\nb,c\nd".as_bytes();
+        crate::dry_run(
+            &mut processor,
+            "in.txt",
+            Separator::Line.records(input),
+            &mut preview,
+        )
+        .unwrap();
+        crate::dry_run_finish(&mut processor, &mut preview).unwrap();
+        assert_eq!(
+            preview.unified_diff("in.txt", "out.txt"),
+            "--- in.txt\n+++ out.txt\n@@ -1,3 +1,4 @@\n a\n-b,c\n+b\n+c\n d\n"
+        );
+    }
+
+    #[test]
+    fn explain_marks_the_matches_and_the_range_of_each_line() {
+        let args =
+            Args::try_parse_from(["str", "--explain", "cut-from-pat-to-pat", "(", ")"]).unwrap();
+        let operation = args.operation.as_ref().unwrap();
+        // as main runs it, explaining a line a range doesn't fit rather than failing
+        let mut processor = Processor::new(operation, &args)
+            .unwrap()
+            .on_invalid(OnInvalid::Skip);
+        let mut out = Vec::new();
+        let input = "f(a);\nb)c(".as_bytes();
+        crate::explain::explain(
+            &mut processor,
+            operation,
+            "in.txt",
+            Separator::Line.records(input),
+            &mut out,
+        )
+        .unwrap();
+        crate::explain::explain_finish(&mut processor, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\nin.txt\n\
+             \x20   1 | f(a);\n\
+             \x20     |  ^~^\n\
+             \x20     | matches 1..2 3..4, keeps 1..3\n\
+             \x20     = (a\n\
+             \x20   2 | b)c(\n\
+             \x20     |  ^ ^\n\
+             \x20     | matches 3..4 1..2, range 3..1 is inverted (end comes before start)\n\
+             \x20     = (no output)\n"
+        );
     }
 
-    pub fn trim_from_index_to_pat(index: usize, pattern: &String, input: &String) -> Output {
-        let found_idx = input.find(pattern).unwrap_or(0);
+    #[test]
+    fn annotations_are_kept_through_later_stages() {
+        let (a, b) = (
+            "#3d0a411935b82599:1:<stdin>\t",
+            "#bf0db5197f62d8b5:2:<stdin>\t",
+        );
+        let annotated = run(&["--annotate", "cut-from-pat", "="], "k=v\nx=y");
+        assert_eq!(annotated, format!("{a}=v\n{b}=y\n"));
+        // the pieces of a line all carry its envelope, not one of this stage's own
+        assert_eq!(
+            run(&["--annotate", "split-at-char", "="], &annotated),
+            format!("{a}\n{a}v\n{b}\n{b}y\n")
+        );
+        assert_eq!(
+            run(&["--restore", "cut-from-pat", "v"], &annotated),
+            "v\n=y\n"
+        );
+        // a line that only looks annotated is operated on whole
+        assert_eq!(run(&["--restore", "trim"], "#1:2:x\t a "), "#1:2:x\t a\n");
+    }
 
-        if index > found_idx {
-            eprintln!("First pattern was found after desired index");
-            std::process::exit(1);
-        } else if index == found_idx {
-            return Output::Single(input.clone());
+    #[test]
+    fn on_invalid_handles_lines_a_range_does_not_fit() {
+        let input = "f(a);\nb)c(";
+        let error = try_run(&["cut-from-pat-to-pat", "(", ")"], input.as_bytes()).unwrap_err();
+        assert!(
+            format!("{error:?}").starts_with("<stdin>:2: range 3..1 is inverted"),
+            "{error:?}"
+        );
+        for (on_invalid, output) in [
+            ("skip", "(a\n"),
+            ("passthrough", "(a\nb)c(\n"),
+            // an inverted range collapses onto its start
+            ("clamp", "(a\n\n"),
+        ] {
+            assert_eq!(
+                run(
+                    &["--on-invalid", on_invalid, "cut-from-pat-to-pat", "(", ")"],
+                    input
+                ),
+                output,
+                "{on_invalid}"
+            );
         }
+        assert_eq!(
+            run(
+                &["--on-invalid", "clamp", "cut-from-index-to-index", "1", "9"],
+                "abc"
+            ),
+            "bc\n"
+        );
 
-        Output::Single(input[..index].to_string() + &input[found_idx..])
+        // single indices are checked the same way, past the end or inside a character
+        let error = try_run(&["cut-from-index", "10"], b"abc").unwrap_err();
+        assert!(
+            format!("{error:?}").starts_with("<stdin>:1: range 10.. is outside the line (0..3)"),
+            "{error:?}"
+        );
+        let error = try_run(&["split-at-index", "1"], "é".as_bytes()).unwrap_err();
+        assert!(
+            format!("{error:?}").starts_with("<stdin>:1: position 1 of range 1.. falls inside"),
+            "{error:?}"
+        );
+        for (args, output) in [
+            (&["--on-invalid", "skip", "trim-until-index", "10"][..], ""),
+            (
+                &["--on-invalid", "passthrough", "cut-until-index", "1"],
+                "éa\n",
+            ),
+            (&["--on-invalid", "clamp", "cut-from-index", "10"], "\n"),
+            (&["--on-invalid", "clamp", "trim-from-index", "10"], "éa\n"),
+            (&["--on-invalid", "clamp", "split-at-index", "1"], "\néa\n"),
+        ] {
+            assert_eq!(run(args, "éa"), output, "{args:?}");
+        }
     }
-}
 
-#[derive(Parser)]
-#[command(version, about = "Powerful command-line-driven string manipulation tool", long_about = None)]
-pub struct Args {
-    #[command(subcommand)]
-    pub operation: Option<Operation>,
+    #[test]
+    fn head_stops_reading_once_its_lines_are_through() {
+        for args in [
+            &["head", "3"][..],
+            &[
+                "run",
+                "--json",
+                r#"[{"op": "trim"}, {"op": "head", "args": ["3"]}]"#,
+            ],
+        ] {
+            let mut args = Args::try_parse_from(["str"].iter().chain(args)).unwrap();
+            let operation = args.operation.take().unwrap();
+            let separator = Separator::new(&args);
+            let mut writer = RecordWriter::new(Vec::new(), &separator, None);
+            let mut processor = Processor::new(&operation, &args).unwrap();
+            // endless, as `yes` is, so this only returns if reading stops
+            let endless = std::iter::repeat_with(|| Ok::<_, std::io::Error>("y"));
+            crate::process(&mut processor, "<stdin>", endless, &mut writer).unwrap();
+            crate::finish(&mut processor, &mut writer).unwrap();
+            assert_eq!(writer.end().unwrap(), b"y\ny\ny\n");
+        }
+    }
 }
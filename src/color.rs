@@ -0,0 +1,33 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Color only when writing to a terminal and NO_COLOR isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Decides once per run whether output gets colored, given whether it ends up on a terminal.
+pub fn init(choice: ColorChoice, terminal: bool) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+    };
+    let _ = ENABLED.set(enabled);
+}
+
+pub fn enabled() -> bool {
+    *ENABLED.get().unwrap_or(&false)
+}
+
+/// Wraps text in the escape codes used for highlighted matches.
+pub fn paint(text: &str) -> String {
+    format!("\x1b[1;31m{text}\x1b[0m")
+}
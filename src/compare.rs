@@ -0,0 +1,112 @@
+use std::cmp::Ordering;
+
+/// How `Sort` orders lines.
+#[derive(Clone, Copy)]
+pub enum Order {
+    /// Byte-wise, like `sort` under the C locale
+    Lexical,
+    /// By the number each line starts with; lines that don't start with one come first
+    Numeric,
+    /// Lexically, except that runs of digits compare by their value (`file2` before `file10`)
+    Natural,
+    /// By dot-separated version components, with pre-releases (`1.0-rc1`) before their release
+    Version,
+}
+
+impl Order {
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Order::Lexical => a.cmp(b),
+            Order::Numeric => leading_number(a)
+                .zip(leading_number(b))
+                .map(|(x, y)| x.total_cmp(&y))
+                .unwrap_or_else(|| {
+                    leading_number(a)
+                        .is_some()
+                        .cmp(&leading_number(b).is_some())
+                })
+                .then_with(|| a.cmp(b)),
+            Order::Natural => natural(a, b),
+            Order::Version => version(a, b),
+        }
+    }
+}
+
+fn leading_number(s: &str) -> Option<f64> {
+    let s = s.trim_start();
+    let candidate = s
+        .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+        .map_or(s, |end| &s[..end]);
+    // the longest prefix that parses, so `12kb` reads as 12 and `1e` as 1
+    (1..=candidate.len())
+        .rev()
+        .find_map(|i| candidate[..i].parse::<f64>().ok())
+}
+
+// splits into alternating runs of digits and non-digits
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != first.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (chunk, after) = rest.split_at(end);
+        rest = after;
+        Some(chunk)
+    })
+}
+
+fn natural(a: &str, b: &str) -> Ordering {
+    let (mut x, mut y) = (chunks(a), chunks(b));
+    loop {
+        match (x.next(), y.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(p), Some(q)) => {
+                let ordering = match (digits(p), digits(q)) {
+                    (Some(p), Some(q)) => p.len().cmp(&q.len()).then(p.cmp(q)),
+                    _ => p.cmp(q),
+                };
+                if ordering.is_ne() {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+// a run of digits without its leading zeros, so runs compare by length and then lexically
+fn digits(chunk: &str) -> Option<&str> {
+    chunk
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then(|| chunk.trim_start_matches('0'))
+}
+
+// `v1.2.3-rc1` is the release `1.2.3` with the pre-release `rc1`
+fn release_parts(s: &str) -> (&str, Option<&str>) {
+    let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+    match s.split_once('-') {
+        Some((release, pre)) => (release, Some(pre)),
+        None => (s, None),
+    }
+}
+
+fn version(a: &str, b: &str) -> Ordering {
+    let ((x, x_pre), (y, y_pre)) = (release_parts(a), release_parts(b));
+    let (x, y): (Vec<&str>, Vec<&str>) = (x.split('.').collect(), y.split('.').collect());
+
+    x.iter()
+        .zip(&y)
+        .map(|(p, q)| natural(p, q))
+        .find(|o| o.is_ne())
+        .unwrap_or_else(|| x.len().cmp(&y.len()))
+        .then_with(|| match (x_pre, y_pre) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(p), Some(q)) => natural(p, q),
+        })
+        .then_with(|| a.cmp(b))
+}
@@ -0,0 +1,52 @@
+/// Ties an output line back to the input line it came from across multi-step pipelines.
+///
+/// An annotated line looks like `#<hash>:<line>:<source>\t<content>`, where `hash` is a 64-bit
+/// FNV-1a hash of the original line in hex, so later stages can set the envelope aside, work on
+/// the content, and put the same envelope back.
+pub struct Envelope {
+    header: String,
+}
+
+impl Envelope {
+    pub fn new(source: &str, line_number: usize, original: &str) -> Self {
+        Envelope {
+            header: format!("#{:016x}:{line_number}:{source}", fnv1a(original)),
+        }
+    }
+
+    /// Splits an annotated line into its envelope and content, or returns `None` if it isn't annotated.
+    pub fn parse(line: &str) -> Option<(Self, &str)> {
+        let (header, content) = line.split_once('\t')?;
+        let (hash, rest) = header.strip_prefix('#')?.split_once(':')?;
+        let (number, _source) = rest.split_once(':')?;
+
+        let valid = hash.len() == 16
+            && hash.chars().all(|c| c.is_ascii_hexdigit())
+            && !number.is_empty()
+            && number.chars().all(|c| c.is_ascii_digit());
+        valid.then(|| {
+            (
+                Envelope {
+                    header: header.to_string(),
+                },
+                content,
+            )
+        })
+    }
+
+    /// Wraps every line of `content` in this envelope.
+    pub fn wrap(&self, content: &str) -> String {
+        content
+            .split('\n')
+            .map(|line| format!("{}\t{line}", self.header))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// stable across runs and platforms, unlike std's `DefaultHasher`
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,186 @@
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::ops::Range;
+
+use anyhow::{Context, Result};
+use clap::CommandFactory;
+use clap::parser::ValueSource;
+use unicode_width::UnicodeWidthChar;
+
+use crate::cli::{Args, Operation, op_functions};
+use crate::process::Processor;
+
+/// Describes the operation in words: what it does, and the arguments it was given on
+/// `command_line`.
+pub fn describe(args: &Args, command_line: &[OsString]) -> String {
+    if let Some(script) = &args.file {
+        return format!("the operations in {}, one after another", script.display());
+    }
+    let command = Args::command();
+    let Ok(matches) = command.clone().try_get_matches_from(command_line) else {
+        return String::new();
+    };
+    let Some((name, given)) = matches.subcommand() else {
+        return "split-at-whitespace: Splits at whitespace (the default operation)".to_string();
+    };
+    let Some(subcommand) = command.find_subcommand(name) else {
+        return name.to_string();
+    };
+
+    let mut description = format!("{name}: {}", subcommand.get_about().unwrap_or_default());
+    if let Some(long) = subcommand.get_long_about() {
+        description += &format!("\n  {long}");
+    }
+    for arg in subcommand
+        .get_arguments()
+        .filter(|arg| !arg.is_global_set())
+    {
+        let id = arg.get_id().as_str();
+        if given.value_source(id) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let values: Vec<String> = given
+            .get_raw(id)
+            .into_iter()
+            .flatten()
+            .map(|value| format!("{:?}", value.to_string_lossy()))
+            .collect();
+        description += &format!("\n  {id} = {}", values.join(", "));
+    }
+    description
+}
+
+/// Writes each line with markers under where the operation's patterns matched (`^`) and the range
+/// it cut or trimmed (`~`), then what it output for the line (`--explain`).
+pub fn explain(
+    processor: &mut Processor,
+    operation: &Operation,
+    name: &str,
+    records: impl Iterator<Item = io::Result<String>>,
+    out: &mut impl Write,
+) -> Result<()> {
+    processor.begin(name);
+    writeln!(out, "\n{name}")?;
+    for (n, line) in records.enumerate() {
+        let line = line.with_context(|| format!("failed to read {name}"))?;
+        let matches: Vec<Range<usize>> = operation
+            .patterns()
+            .iter()
+            .flat_map(|pattern| pattern.find_iter(&line).collect::<Vec<_>>())
+            .filter(|m| !m.is_empty())
+            .collect();
+        let region = region(operation, &line);
+
+        let gutter = format!("{:>5}", n + 1);
+        let blank = " ".repeat(gutter.len());
+        writeln!(out, "{gutter} | {}", line.replace('\t', " "))?;
+        let marks = markers(&line, &matches, region.as_ref().map(|(range, _)| range));
+        if !marks.is_empty() {
+            writeln!(out, "{blank} | {marks}")?;
+        }
+
+        let mut notes = Vec::new();
+        if !matches.is_empty() {
+            let ranges: Vec<String> = matches.iter().map(|m| format!("{m:?}")).collect();
+            notes.push(format!("matches {}", ranges.join(" ")));
+        }
+        if let Some(span) = operation.span(&line)
+            && let Err(reason) = span.check(&line)
+        {
+            notes.push(reason);
+        } else if let Some((range, action)) = &region {
+            notes.push(format!("{action} {range:?}"));
+        }
+        if !notes.is_empty() {
+            writeln!(out, "{blank} | {}", notes.join(", "))?;
+        }
+
+        match processor.apply(&line)? {
+            Some(result) => {
+                for piece in result.split('\n') {
+                    writeln!(out, "{blank} = {}", piece.replace('\t', " "))?;
+                }
+            }
+            None => writeln!(out, "{blank} = (no output)")?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes what the operation held back until the end of the input.
+pub fn explain_finish(processor: &mut Processor, out: &mut impl Write) -> Result<()> {
+    if let Some(result) = processor.finish()? {
+        writeln!(out, "\nat the end of the input")?;
+        for piece in result.split('\n') {
+            writeln!(out, "      = {piece}")?;
+        }
+    }
+    Ok(())
+}
+
+// the part of the line the operation keeps (cuts) or removes (trims), if it works on one range
+fn region(operation: &Operation, input: &str) -> Option<(Range<usize>, &'static str)> {
+    use crate::cli::IndexOperation::*;
+    use crate::cli::PatternOperation::*;
+    use Operation::*;
+
+    let len = input.len();
+    let index = |i: usize| input.floor_char_boundary(i.min(len));
+    let (range, keeps) = match operation {
+        PatternBased(CutFromPat { pattern }) => (pattern.anchor(input)?.start..len, true),
+        PatternBased(CutUntilPat {
+            pattern,
+            patterns,
+            set,
+        }) => {
+            let set = op_functions::pattern_set(set, pattern, patterns).ok()?;
+            (0..set.anchor(input).map_or(len, |m| m.start), true)
+        }
+        PatternBased(TrimFromPat {
+            pattern,
+            patterns,
+            set,
+        }) => {
+            let set = op_functions::pattern_set(set, pattern, patterns).ok()?;
+            (set.anchor(input)?.start..len, false)
+        }
+        PatternBased(TrimUntilPat { pattern }) => (0..pattern.anchor(input)?.start, false),
+        PatternBased(TrimToPat { pattern }) => (0..pattern.anchor(input)?.end, false),
+        IndexBased(CutFromIndex { index: i }) => (index(*i)..len, true),
+        IndexBased(CutUntilIndex { index: i }) => (0..index(*i), true),
+        IndexBased(TrimFromIndex { index: i }) => (index(*i)..len, false),
+        IndexBased(TrimUntilIndex { index: i }) => (0..index(*i), false),
+        _ => {
+            let span = operation.span(input)?;
+            let keeps = matches!(
+                operation,
+                PatternBased(CutFromPatToPat { .. })
+                    | PatternBased(CutFromPatToOffset { .. })
+                    | IndexBased(CutFromIndexToIndex { .. })
+                    | IndexBased(CutFromIndexToOffset { .. })
+                    | CutFromPatToIndex { .. }
+                    | CutFromIndexToPat { .. }
+            );
+            (span.clamp(input), keeps)
+        }
+    };
+    Some((range, if keeps { "keeps" } else { "removes" }))
+}
+
+// a line of markers lining up with the characters of `input` as a terminal shows them
+fn markers(input: &str, matches: &[Range<usize>], region: Option<&Range<usize>>) -> String {
+    let mut marks = String::new();
+    for (i, c) in input.char_indices() {
+        let mark = match () {
+            _ if matches.iter().any(|m| m.contains(&i)) => '^',
+            _ if region.is_some_and(|r| r.contains(&i)) => '~',
+            _ => ' ',
+        };
+        let width = match c {
+            '\t' => 1,
+            c => c.width().unwrap_or(0),
+        };
+        marks.extend(std::iter::repeat_n(mark, width));
+    }
+    marks.trim_end().to_string()
+}
@@ -0,0 +1,50 @@
+use std::ops::Range;
+
+/// The field of each line an operation is restricted to with `--field`.
+#[derive(Clone)]
+pub struct Field {
+    /// Zero-based, or counting from the end when negative
+    index: i64,
+    /// Whitespace separates fields when there is no delimiter
+    delim: Option<String>,
+}
+
+impl Field {
+    pub fn new(index: i64, delim: Option<String>) -> Self {
+        Field { index, delim }
+    }
+
+    /// The byte range of the field in `line`, or `None` if the line has too few fields.
+    pub fn locate(&self, line: &str) -> Option<Range<usize>> {
+        let ranges: Vec<Range<usize>> = match &self.delim {
+            Some(delim) => {
+                let mut start = 0;
+                let mut ranges: Vec<_> = line
+                    .match_indices(delim.as_str())
+                    .map(|(i, _)| {
+                        let range = start..i;
+                        start = i + delim.len();
+                        range
+                    })
+                    .collect();
+                ranges.push(start..line.len());
+                ranges
+            }
+            None => line
+                .split_whitespace()
+                .map(|word| {
+                    let start = word.as_ptr() as usize - line.as_ptr() as usize;
+                    start..start + word.len()
+                })
+                .collect(),
+        };
+
+        let index = match self.index.is_negative() {
+            true => ranges
+                .len()
+                .checked_sub(self.index.unsigned_abs() as usize)?,
+            false => self.index as usize,
+        };
+        ranges.get(index).cloned()
+    }
+}
@@ -0,0 +1,69 @@
+use std::ops::Range;
+
+/// The Levenshtein distance between `a` and `b`: how many characters must be inserted, deleted or
+/// substituted to turn one into the other.
+pub fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Whether `a` and `b` are at least `threshold` alike, their similarity being one minus their
+/// distance divided by the length of the longer (so 1 for equal strings).
+pub fn similar(a: &str, b: &str, threshold: f64) -> bool {
+    let (a_len, b_len) = (a.chars().count(), b.chars().count());
+    let longer = a_len.max(b_len);
+    if longer == 0 {
+        return true;
+    }
+    let similarity = |distance: usize| 1.0 - distance as f64 / longer as f64;
+    // the distance is at least the difference in length, so strings too different in length are
+    // told apart without computing it
+    similarity(a_len.abs_diff(b_len)) >= threshold && similarity(distance(a, b)) >= threshold
+}
+
+/// The part of `text` closest to `pattern`, as its byte range and distance from `pattern`, taking
+/// the leftmost of equally close parts.
+pub fn find(pattern: &str, text: &str) -> (Range<usize>, usize) {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let offsets: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([text.len()])
+        .collect();
+
+    // for each prefix of the pattern, the distance of the best part of the text ending at the
+    // current character, and the character that part starts at; any part may start anywhere, so
+    // the empty prefix costs nothing
+    let mut column: Vec<(usize, usize)> = (0..=pattern.len()).map(|i| (i, 0)).collect();
+    let mut best = (column[pattern.len()].0, 0..0);
+    for (j, c) in text.chars().enumerate() {
+        let mut diagonal = column[0];
+        column[0] = (0, j + 1);
+        for (i, p) in pattern.iter().enumerate() {
+            let above = column[i];
+            let left = column[i + 1];
+            let substitution = (diagonal.0 + usize::from(p != &c), diagonal.1);
+            diagonal = left;
+            column[i + 1] = [substitution, (above.0 + 1, above.1), (left.0 + 1, left.1)]
+                .into_iter()
+                .min_by_key(|(cost, _)| *cost)
+                .unwrap_or(substitution);
+        }
+        let (cost, start) = column[pattern.len()];
+        if cost < best.0 {
+            best = (cost, start..j + 1);
+        }
+    }
+    let (cost, chars) = best;
+    (offsets[chars.start]..offsets[chars.end], cost)
+}
@@ -1,14 +1,774 @@
+mod address;
+mod aggregate;
 mod cli;
+mod color;
+mod compare;
+mod envelope;
+mod explain;
+mod field;
+mod fuzzy;
+mod numbers;
+mod pattern;
+mod pipeline;
+mod process;
+mod progress;
+mod records;
+mod redact;
+mod script;
+mod span;
+mod stats;
+mod stream;
+mod template;
+mod time;
+mod tui;
 
-use clap::Parser;
-use cli::{ Operation, Args, Output };
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
 
-fn main() {
-    let operation: Operation = Args::parse().operation.unwrap_or_default(); // call at top to enable flags without stdin
-    for i in std::io::stdin().lines().flatten() {
-        match operation.execute(&i) {
-            Output::Multiple(x) => println!("{}", x.join("\n")),
-            Output::Single(x) => println!("{x}"),
+use anyhow::{Context, Result, bail};
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use cli::{ Args, EXAMPLES, Operation };
+use memmap2::Mmap;
+use pattern::Folding;
+use pipeline::Pipeline;
+use process::{NonMatching, Processor};
+use progress::Progress;
+use records::{RecordWriter, Separator};
+use similar::TextDiff;
+use span::OnInvalid;
+use stats::Counted;
+use template::{Template, Value};
+
+// how much output is collected before it is written, unless it goes to a terminal
+const OUTPUT_BUFFER: usize = 64 * 1024;
+
+// how many records of the input `str tui` shows
+const SAMPLE_SIZE: usize = 1000;
+
+// how many lines are handed to the worker pool at a time with --jobs
+const BATCH_SIZE: usize = 4096;
+
+// the exit status of a failed run, kept apart from the 1 of a run that matched nothing
+const ERROR_STATUS: u8 = 2;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        // a verdict didn't hold or nothing matched, as with grep
+        Ok(false) => ExitCode::FAILURE,
+        // the reader went away (`str ... | head`), which is not an error
+        Err(e) if is_broken_pipe(&e) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(ERROR_STATUS)
+        }
+    }
+}
+
+/// The command line, with `--file` put before a script given as the first argument, as it is when
+/// the script is run directly through a `#!/usr/bin/env str` line.
+fn script_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.collect();
+    if let Some(first) = args.get(1)
+        && !first.to_string_lossy().starts_with('-')
+        && Args::command().find_subcommand(first).is_none()
+        && Path::new(first).is_file()
+    {
+        args.insert(1, "--file".into());
+    }
+    args
+}
+
+/// Clears the progress bar, then prints the totals for --stats.
+fn report(args: &Args, started: Instant, progress: Option<Progress>) {
+    drop(progress);
+    if args.stats {
+        stats::report(started.elapsed());
+    }
+}
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// Runs the command line, returning whether it succeeded (see `Processor::success`).
+fn run() -> Result<bool> {
+    let started = Instant::now();
+    let command_line = script_args(std::env::args_os());
+    let args = Args::parse_from(&command_line); // call at top to enable flags without stdin
+    validate(&args);
+    let separator = Separator::new(&args);
+    records::configure(&args);
+    pattern::configure(
+        args.regex,
+        separator.multiline(),
+        Folding::new(args.fold_case, args.locale.as_deref()),
+        args.last,
+    );
+    // parsed again now that --regex is known, so that patterns are compiled as they are parsed
+    // and a bad one is reported before anything is written
+    let mut args = Args::parse_from(&command_line);
+    let mut operation: Operation = match (&args.file, args.operation.take()) {
+        (Some(script), _) => {
+            Operation::Stages(Pipeline::script(script).map_err(anyhow::Error::msg)?)
+        }
+        (None, operation) => operation.unwrap_or_default(),
+    };
+    if args.invert {
+        operation = operation
+            .inverted()
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::ArgumentConflict, e).exit());
+    }
+    if let Operation::Examples { operation } = &operation {
+        print_examples(operation.as_deref())?;
+        return Ok(true);
+    }
+    if args.jobs != 1 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs)
+            .build_global()
+            .context("failed to start the worker pool")?;
+    }
+    color::init(
+        args.color,
+        args.output.is_none()
+            && !args.in_place
+            && args.output_template.is_none()
+            && args.tee.is_none()
+            && io::stdout().is_terminal(),
+    );
+    if let Operation::Tui = operation {
+        let mut sample = Vec::new();
+        // a terminal on stdin is for typing into the editor, not a sample
+        if !args.inputs.is_empty() || !io::stdin().is_terminal() {
+            for (name, input) in open_inputs(&args.inputs, args.mmap)? {
+                for record in separator
+                    .records(input.reader())
+                    .take(SAMPLE_SIZE - sample.len())
+                {
+                    sample.push(record.with_context(|| format!("failed to read {name}"))?);
+                }
+            }
+        }
+        if let Some(command) = tui::run(&args, sample)? {
+            println!("{command}");
+        }
+        return Ok(true);
+    }
+    if args.explain {
+        let mut out = io::stdout().lock();
+        writeln!(out, "{}", explain::describe(&args, &command_line))?;
+        // a line a range doesn't fit is explained rather than ending the run
+        let mut processor = Processor::new(&operation, &args)?;
+        if matches!(args.on_invalid, OnInvalid::Error) {
+            processor = processor.on_invalid(OnInvalid::Skip);
+        }
+        for (name, input) in open_inputs(&args.inputs, args.mmap)? {
+            explain::explain(
+                &mut processor,
+                &operation,
+                &name,
+                separator.records(input.reader()),
+                &mut out,
+            )?;
+        }
+        explain::explain_finish(&mut processor, &mut out)?;
+        return Ok(processor.success());
+    }
+    let progress = args
+        .progress
+        .then(|| Progress::start(progress::total(&args.inputs)));
+
+    if args.in_place || args.output_template.is_some() {
+        // each file has its own processor, but the run succeeds as if they were one input
+        let (mut held, mut found) = (true, false);
+        for (name, input) in open_inputs(&args.inputs, args.mmap)? {
+            let target = match &args.output_template {
+                Some(template) => output_path(template, &name),
+                None => PathBuf::from(&name),
+            };
+            if args.dry_run {
+                let mut processor = Processor::new(&operation, &args)?;
+                let mut preview = Preview::new(args.diff);
+                dry_run(
+                    &mut processor,
+                    &name,
+                    separator.records(input.reader()),
+                    &mut preview,
+                )?;
+                dry_run_finish(&mut processor, &mut preview)?;
+                preview.print(&name, &target.display().to_string());
+                held &= processor.held();
+                found |= processor.found();
+            } else {
+                // the whole file is transformed before it is written
+                let mut buffer = RecordWriter::new(
+                    Vec::new(),
+                    &separator,
+                    args.to_encoding.or(args.from_encoding),
+                );
+                let mut processor = Processor::new(&operation, &args)?;
+                process(
+                    &mut processor,
+                    &name,
+                    separator.records(input.reader()),
+                    &mut buffer,
+                )?;
+                finish(&mut processor, &mut buffer)?;
+                let buffer = buffer.end()?;
+                stats::written(buffer.len());
+                let (file, mut out) = AtomicFile::create(&target)?;
+                out.write_all(&buffer)
+                    .and_then(|()| out.sync_all())
+                    .with_context(|| format!("failed to write {}", target.display()))?;
+                file.commit()?;
+                held &= processor.held();
+                found |= processor.found();
+            }
+        }
+        report(&args, started, progress);
+        return Ok(held && found);
+    }
+
+    if args.dry_run {
+        let mut processor = Processor::new(&operation, &args)?;
+        let mut preview = Preview::new(args.diff);
+        let mut names = Vec::new();
+        for (name, input) in open_inputs(&args.inputs, args.mmap)? {
+            dry_run(
+                &mut processor,
+                &name,
+                separator.records(input.reader()),
+                &mut preview,
+            )?;
+            names.push(name);
+        }
+        dry_run_finish(&mut processor, &mut preview)?;
+        let output = args.output.unwrap_or_default();
+        preview.print(&names.join(" "), &output.display().to_string());
+        return Ok(processor.success());
+    }
+
+    let stdout = io::stdout();
+    let mut output_file = None;
+    let writer: Box<dyn Write> = match &args.output {
+        Some(path) => {
+            let (file, out) = AtomicFile::create(path)?;
+            output_file = Some(file);
+            Box::new(BufWriter::new(Counted(out)))
+        }
+        // a terminal gets each line as soon as it is ready
+        None if stdout.is_terminal() => Box::new(Counted(stdout.lock())),
+        None => Box::new(BufWriter::with_capacity(
+            OUTPUT_BUFFER,
+            Counted(stdout.lock()),
+        )),
+    };
+    let writer: Box<dyn Write> = match &args.tee {
+        Some(path) => Box::new(Tee(
+            writer,
+            BufWriter::new(
+                File::create(path)
+                    .with_context(|| format!("failed to create {}", path.display()))?,
+            ),
+        )),
+        None => writer,
+    };
+    let mut writer = RecordWriter::new(writer, &separator, args.to_encoding);
+    if args.chunked {
+        let streamer = stream::Streamer::new(&operation)?;
+        let mut matched = false;
+        for (name, input) in open_inputs(&args.inputs, args.mmap)? {
+            matched |= streamer
+                .run(input.reader(), &mut writer)
+                .with_context(|| format!("failed to stream {name}"))?;
+        }
+        writer.end()?.flush()?;
+        output_file.map(AtomicFile::commit).transpose()?;
+        report(&args, started, progress);
+        return Ok(matched || !args.fail_if_no_match.unwrap_or(false));
+    }
+    let mut processor = Processor::new(&operation, &args)?;
+    let processed = process_inputs(&mut processor, &args, &separator, &mut writer);
+    // the lines written before a failure are still ended and flushed, though an --output file
+    // is only put in place by a run that succeeds
+    writer.end()?.flush()?;
+    processed?;
+    output_file.map(AtomicFile::commit).transpose()?;
+    report(&args, started, progress);
+    Ok(processor.success())
+}
+
+/// Checks requirements between global flags, which clap can't enforce once they are propagated to subcommands.
+fn validate(args: &Args) {
+    let missing = |msg: &str| {
+        Args::command()
+            .error(ErrorKind::MissingRequiredArgument, msg)
+            .exit()
+    };
+
+    if args.in_place && args.inputs.is_empty() {
+        missing("--in-place requires at least one --input");
+    }
+    if args.file.is_some() && args.operation.is_some() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--file cannot be used with an operation",
+            )
+            .exit();
+    }
+    if args.in_place && args.output.is_some() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--in-place cannot be used with --output",
+            )
+            .exit();
+    }
+    if args.tee.is_some() {
+        let conflicting = [
+            (args.in_place, "--in-place"),
+            (args.output.is_some(), "--output"),
+            (args.output_template.is_some(), "--output-template"),
+            (args.dry_run, "--dry-run"),
+            (args.explain, "--explain"),
+        ];
+        if let Some((_, flag)) = conflicting.iter().find(|(set, _)| *set) {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--tee cannot be used with {flag}"),
+                )
+                .exit();
+        }
+    }
+    if args.output_template.is_some() {
+        if args.inputs.is_empty() {
+            missing("--output-template requires at least one --input");
+        }
+        let conflicting = [
+            (args.in_place, "--in-place"),
+            (args.output.is_some(), "--output"),
+            (args.chunked, "--chunked"),
+        ];
+        if let Some((_, flag)) = conflicting.iter().find(|(set, _)| *set) {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--output-template cannot be used with {flag}"),
+                )
+                .exit();
+        }
+    }
+    if args.dry_run && !args.in_place && args.output.is_none() && args.output_template.is_none() {
+        missing("--dry-run requires --in-place, --output or --output-template");
+    }
+    if args.empty_as.is_some() && args.drop_empty {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--empty-as cannot be used with --drop-empty",
+            )
+            .exit();
+    }
+    if args.annotate && args.restore {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--annotate cannot be used with --restore",
+            )
+            .exit();
+    }
+    if args.diff && !args.dry_run {
+        missing("--diff requires --dry-run");
+    }
+    if args.explain {
+        let unsupported = [
+            (args.in_place, "--in-place"),
+            (args.dry_run, "--dry-run"),
+            (args.output.is_some(), "--output"),
+            (args.output_template.is_some(), "--output-template"),
+            (args.chunked, "--chunked"),
+        ];
+        if let Some((_, flag)) = unsupported.iter().find(|(set, _)| *set) {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--explain cannot be used with {flag}"),
+                )
+                .exit();
+        }
+    }
+    if args.chunked {
+        let unsupported = [
+            (args.in_place, "--in-place"),
+            (args.dry_run, "--dry-run"),
+            (args.lines.is_some(), "--lines"),
+            (args.if_pattern.is_some(), "--if"),
+            (args.unless_pattern.is_some(), "--unless"),
+            (args.field.is_some(), "--field"),
+            (args.regex, "--regex"),
+            (args.fold_case, "--fold-case"),
+            (
+                args.slurp || args.record_sep.is_some() || args.paragraph,
+                "--slurp, --record-sep and --paragraph",
+            ),
+            (
+                args.empty_as.is_some() || args.drop_empty,
+                "--empty-as and --drop-empty",
+            ),
+            (args.annotate || args.restore, "--annotate and --restore"),
+            (args.jobs != 1, "--jobs"),
+            (
+                args.non_matching != NonMatching::KeepOriginal,
+                "--non-matching",
+            ),
+            (
+                args.output_delim.is_some() || args.quote_output.is_some(),
+                "--output-delim and --quote-output",
+            ),
+        ];
+        if let Some((_, flag)) = unsupported.iter().find(|(set, _)| *set) {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--chunked cannot be used with {flag}"),
+                )
+                .exit();
+        }
+    }
+    if args.lossy && args.binary {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--lossy cannot be used with --binary",
+            )
+            .exit();
+    }
+    if args.locale.is_some() && !args.fold_case {
+        missing("--locale requires --fold-case");
+    }
+    if args.field_delim.is_some() && args.field.is_none() {
+        missing("--field-delim requires --field");
+    }
+    let modes = [args.slurp, args.record_sep.is_some(), args.paragraph];
+    if modes.iter().filter(|&&m| m).count() > 1 {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "only one of --slurp, --record-sep and --paragraph can be used",
+            )
+            .exit();
+    }
+}
+
+/// Prints the curated examples as shell commands, limited to one operation when given.
+fn print_examples(operation: Option<&str>) -> Result<()> {
+    let quote = |arg: &str| cli::op_functions::shell_quote(true, arg);
+    let examples: Vec<_> = EXAMPLES
+        .iter()
+        .filter(|e| operation.is_none_or(|op| e.args.first() == Some(&op)))
+        .collect();
+    if examples.is_empty() {
+        bail!("no examples for '{}'", operation.unwrap_or_default());
+    }
+
+    for (i, example) in examples.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let input = example
+            .input
+            .replace('\\', "\\\\")
+            .replace('%', "%%")
+            .replace('\n', "\\n");
+        let args: Vec<String> = example.args.iter().map(|a| quote(a)).collect();
+        println!("# {}", example.description);
+        println!(
+            "$ printf {} | str {}",
+            quote(&format!("{input}\\n")),
+            args.join(" ")
+        );
+        println!("{}", example.output);
+    }
+    Ok(())
+}
+
+/// An input file, or stdin.
+enum Input {
+    Reader(Box<dyn BufRead>),
+    /// A file mapped into memory (`--mmap`)
+    Mapped(Mmap),
+}
+
+impl Input {
+    fn reader(self) -> Box<dyn BufRead> {
+        match self {
+            Input::Reader(reader) => Box::new(Counted(reader)),
+            Input::Mapped(map) => Box::new(Counted(io::Cursor::new(map))),
         }
     }
 }
+
+/// Opens every input file up front so a missing file fails before anything is written, falling back to stdin.
+fn open_inputs(inputs: &[PathBuf], mmap: bool) -> Result<Vec<(String, Input)>> {
+    if inputs.is_empty() {
+        let stdin = Box::new(io::stdin().lock());
+        return Ok(vec![("<stdin>".to_string(), Input::Reader(stdin))]);
+    }
+
+    inputs
+        .iter()
+        .map(|path| {
+            let file =
+                File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+            let input = match mmap {
+                // SAFETY: the map is only ever read; a file truncated by another process while it
+                // is mapped can still end the run with SIGBUS, which is why --mmap is opt-in
+                true => Input::Mapped(
+                    unsafe { Mmap::map(&file) }
+                        .with_context(|| format!("failed to map {}", path.display()))?,
+                ),
+                false => Input::Reader(Box::new(BufReader::new(file))),
+            };
+            Ok((path.display().to_string(), input))
+        })
+        .collect()
+}
+
+/// Runs every input through the processor, then writes whatever it held back.
+fn process_inputs(
+    processor: &mut Processor,
+    args: &Args,
+    separator: &Separator,
+    writer: &mut RecordWriter<impl Write>,
+) -> Result<()> {
+    for (name, input) in open_inputs(&args.inputs, args.mmap)? {
+        if processor.done() {
+            break;
+        }
+        if let Input::Mapped(map) = &input
+            && let Some(lines) = records::borrowed_lines(separator, map)
+        {
+            stats::read(map.len());
+            process(processor, &name, lines, writer)?;
+            continue;
+        }
+        process(processor, &name, separator.records(input.reader()), writer)?;
+    }
+    finish(processor, writer)
+}
+
+fn process<S: AsRef<str> + Sync>(
+    processor: &mut Processor,
+    name: &str,
+    records: impl Iterator<Item = io::Result<S>>,
+    writer: &mut RecordWriter<impl Write>,
+) -> Result<()> {
+    processor.begin(name);
+    if processor.parallel() {
+        let mut records = records.peekable();
+        while records.peek().is_some() {
+            let batch = records
+                .by_ref()
+                .take(BATCH_SIZE)
+                .collect::<io::Result<Vec<S>>>()
+                .with_context(|| format!("failed to read {name}"))?;
+            for (line, result) in batch.iter().zip(processor.apply_batch(&batch)) {
+                // the lines before one that failed are still written
+                let result = result?;
+                stats::line(result.as_deref() != Some(line.as_ref()));
+                if let Some(result) = result {
+                    writer.write(&result)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    for line in records {
+        let line = line.with_context(|| format!("failed to read {name}"))?;
+        let result = processor.apply(line.as_ref())?;
+        stats::line(result.as_deref() != Some(line.as_ref()));
+        if let Some(result) = result {
+            writer.write(&result)?;
+        }
+        if processor.done() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Where `--output-template` puts the results for the input file `name`: the template filled in
+/// with the input's file name, stem and extension, in the input's directory unless it is absolute.
+fn output_path(template: &Template, name: &str) -> PathBuf {
+    use cli::op_functions::{split_ext, split_path};
+
+    let (dir, file_name, _) = split_path(name);
+    let (stem, ext) = split_ext(file_name);
+    let path = PathBuf::from(template.render(|placeholder| match placeholder {
+        "name" => Some(Value::Text(file_name)),
+        "stem" => Some(Value::Text(stem)),
+        "ext" => Some(Value::Text(ext.unwrap_or_default())),
+        _ => None,
+    }));
+    Path::new(dir).join(path)
+}
+
+/// A file written under a temporary name in the directory it belongs in and only renamed into
+/// place by `commit`, so a run that fails partway never leaves a half-written file behind.
+struct AtomicFile {
+    path: PathBuf,
+    temporary: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file, with the permissions of the file it will replace, if any.
+    fn create(path: &Path) -> Result<(Self, File)> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temporary = path.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+        let file = File::create(&temporary)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        let atomic = AtomicFile {
+            path: path.to_path_buf(),
+            temporary,
+            committed: false,
+        };
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())
+                .with_context(|| format!("failed to create {}", path.display()))?;
+        }
+        Ok((atomic, file))
+    }
+
+    /// Moves the finished file into place.
+    fn commit(mut self) -> Result<()> {
+        fs::rename(&self.temporary, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+/// A writer passing everything written to it on to both of its writers (`--tee`).
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// Writes whatever the operation held back until the end of the input.
+fn finish(processor: &mut Processor, writer: &mut RecordWriter<impl Write>) -> Result<()> {
+    if let Some(result) = processor.finish()? {
+        writer.write(&result)?;
+    }
+    Ok(())
+}
+
+/// What a dry run found: how many lines would change, and with --diff, the lines read and the
+/// lines that would be written in their place.
+#[derive(Default)]
+struct Preview {
+    diff: bool,
+    changed: usize,
+    total: usize,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+impl Preview {
+    fn new(diff: bool) -> Self {
+        Preview {
+            diff,
+            ..Default::default()
+        }
+    }
+
+    /// Prints the changes as a unified diff when asked for, then how many lines would change. The
+    /// count goes to stderr after a diff, so that stdout can be fed to `patch`.
+    fn print(&self, from: &str, to: &str) {
+        let summary = format!(
+            "{to}: {} of {} lines would change",
+            self.changed, self.total
+        );
+        if !self.diff {
+            println!("{summary}");
+            return;
+        }
+        print!("{}", self.unified_diff(from, to));
+        eprintln!("{summary}");
+    }
+
+    /// The changes as a unified diff from the file `from` to `to`.
+    fn unified_diff(&self, from: &str, to: &str) -> String {
+        let before: Vec<&str> = self.before.iter().map(String::as_str).collect();
+        let after: Vec<&str> = self.after.iter().map(String::as_str).collect();
+        let diff = TextDiff::from_slices(&before, &after);
+        diff.unified_diff().header(from, to).to_string()
+    }
+}
+
+/// Runs the operation without writing its results, counting the lines it would change.
+fn dry_run(
+    processor: &mut Processor,
+    name: &str,
+    records: impl Iterator<Item = io::Result<String>>,
+    preview: &mut Preview,
+) -> Result<()> {
+    processor.begin(name);
+    for line in records {
+        let line = line.with_context(|| format!("failed to read {name}"))?;
+        let result = processor.apply(&line)?;
+        preview.total += 1;
+        if result.as_ref() != Some(&line) {
+            preview.changed += 1;
+        }
+        if preview.diff {
+            preview
+                .after
+                .extend(result.iter().flat_map(|r| r.lines()).map(String::from));
+            preview.before.push(line);
+        }
+    }
+    Ok(())
+}
+
+/// Counts output held back until the end of the input as one more change.
+fn dry_run_finish(processor: &mut Processor, preview: &mut Preview) -> Result<()> {
+    let Some(result) = processor.finish()? else {
+        return Ok(());
+    };
+    preview.changed += 1;
+    if preview.diff {
+        preview.after.extend(result.lines().map(String::from));
+    }
+    Ok(())
+}
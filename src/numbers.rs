@@ -0,0 +1,424 @@
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+use crate::stats;
+
+/// What the numbers `Humanize` and `Dehumanize` work on count.
+#[derive(Clone, Copy)]
+pub enum Quantity {
+    /// Bytes, in powers of 1024 (KiB, MiB, ...)
+    Bytes,
+    /// Anything, in powers of 1000 (k, M, G, ...)
+    Si,
+    /// Seconds, in days, hours, minutes and seconds
+    Duration,
+}
+
+const SI_UNITS: [&str; 7] = ["", "k", "M", "G", "T", "P", "E"];
+const DURATION_UNITS: [(&str, f64); 4] = [("d", 86400.0), ("h", 3600.0), ("m", 60.0), ("s", 1.0)];
+
+// the byte ranges of the numbers in `input` that the rewriting operations change. A sign or a
+// leading `.` only belongs to a number when it doesn't follow a letter or digit, so `x-5` is the
+// number 5 and the range `10-20` is 10 and 20. Numbers written against a word, as in `v2` or
+// `12px`, are left alone unless `unit` follows them, and so are runs of more than two numbers,
+// such as the date `2024-05-01`, the time `12:30:00` or the version `1.2.3`.
+fn numbers(input: &str, unit: Option<char>) -> Vec<Range<usize>> {
+    static RUN: OnceLock<Regex> = OnceLock::new();
+    let run_pattern = RUN
+        .get_or_init(|| Regex::new(r"\d+(?:[-./:]\d+)*").expect("the number run pattern is valid"));
+    let word = |c: char| c.is_alphanumeric() || c == '_';
+    let before = |i: usize| input[..i].chars().next_back();
+    let mut found = Vec::new();
+    for run in run_pattern.find_iter(input) {
+        let parts: Vec<&str> = run.as_str().split(['-', '/', ':']).collect();
+        let mut start = run.start();
+        // a fraction written without its zero, as in .5
+        let fraction = before(start) == Some('.') && !before(start - 1).is_some_and(word);
+        if fraction {
+            start -= 1;
+        }
+        let dotted = parts.iter().any(|part| part.matches('.').count() > 1)
+            || (fraction && parts[0].contains('.'));
+        if parts.len() > 2 || dotted || before(start).is_some_and(word) {
+            continue;
+        }
+        if matches!(before(start), Some('-' | '+')) && !before(start - 1).is_some_and(word) {
+            start -= 1;
+        }
+        let mut end = run.end();
+        match input[end..].chars().next() {
+            Some(c) if unit == Some(c) && !input[end + 1..].starts_with(word) => end += 1,
+            Some(c) if word(c) => continue,
+            _ => {}
+        }
+        match parts[..] {
+            [first, _] => {
+                let split = run.start() + first.len();
+                found.push(start..split);
+                found.push(split + 1..end);
+            }
+            _ => found.push(start..end),
+        }
+    }
+    found
+}
+
+// `input` with each number `numbers` finds replaced by `rewrite` of it
+fn replace_numbers(
+    input: &str,
+    unit: Option<char>,
+    mut rewrite: impl FnMut(&str) -> String,
+) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut last = 0;
+    for range in numbers(input, unit) {
+        output.push_str(&input[last..range.start]);
+        output.push_str(&rewrite(&input[range.clone()]));
+        last = range.end;
+    }
+    output.push_str(&input[last..]);
+    output
+}
+
+/// Which numbers `Numbers` extracts.
+#[derive(Clone, Copy)]
+pub enum NumberKind {
+    /// Integers and numbers with a fractional part
+    Any,
+    /// Integers
+    Int,
+    /// Numbers with a fractional part
+    Float,
+}
+
+/// Finds the numbers of `kind` in `input`, even inside words, with their sign if `signed` and the
+/// sign doesn't follow a letter or digit (so `2024-05` isn't read as 2024 and -5). A fraction
+/// written without its zero, as in `-.5`, keeps its `.` on the same terms.
+pub fn extract(kind: NumberKind, signed: bool, input: &str) -> Vec<&str> {
+    static TOKEN: OnceLock<Regex> = OnceLock::new();
+    let token = TOKEN
+        .get_or_init(|| Regex::new(r"[-+]?\.?\d+(?:\.\d+)?").expect("the number pattern is valid"));
+    let after_word = |i: usize| {
+        input[..i]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric)
+    };
+    token
+        .find_iter(input)
+        .map(|m| {
+            let mut text = m.as_str();
+            let mut start = m.start();
+            if let Some(unsigned) = text.strip_prefix(['-', '+']) {
+                if !signed || after_word(start) {
+                    text = unsigned;
+                }
+                start += 1;
+            }
+            match text.strip_prefix('.') {
+                Some(whole) if after_word(start) => whole,
+                _ => text,
+            }
+        })
+        .filter(|text| match kind {
+            NumberKind::Any => true,
+            NumberKind::Int => !text.contains('.'),
+            NumberKind::Float => text.contains('.'),
+        })
+        .collect()
+}
+
+/// How `NumFormat` rewrites a number.
+pub struct NumFormat {
+    /// Separator between groups of three digits
+    pub thousands: Option<String>,
+    /// Number of decimal places
+    pub precision: Option<usize>,
+    /// Width to pad to with zeros
+    pub width: Option<usize>,
+    /// Whether to write the number as a mantissa and an exponent
+    pub scientific: bool,
+}
+
+/// Rewrites every number in `input` in `format`.
+pub fn reformat(format: &NumFormat, input: &str) -> String {
+    replace_numbers(input, None, |text| reformat_number(format, text))
+}
+
+fn reformat_number(format: &NumFormat, text: &str) -> String {
+    let Ok(n) = text.parse::<f64>() else {
+        return text.to_string();
+    };
+    let written = match (format.scientific, format.precision) {
+        (true, Some(precision)) => format!("{n:.precision$e}"),
+        (true, None) => format!("{n:e}"),
+        (false, Some(precision)) => format!("{n:.precision$}"),
+        // left as written, so integers too long for a float keep their digits
+        (false, None) => text.to_string(),
+    };
+    // formatting drops a leading plus
+    let written = match text.starts_with('+') && !written.starts_with('+') {
+        true => format!("+{written}"),
+        false => written,
+    };
+    let (sign, digits) = match written.find(|c: char| c.is_ascii_digit() || c == '.') {
+        Some(start) => written.split_at(start),
+        None => return written,
+    };
+    let digits = match &format.thousands {
+        Some(separator) => {
+            let end = digits.find(['.', 'e']).unwrap_or(digits.len());
+            let (whole, rest) = digits.split_at(end);
+            let mut grouped = String::new();
+            for (i, digit) in whole.chars().enumerate() {
+                if i > 0 && (whole.len() - i) % 3 == 0 {
+                    grouped.push_str(separator);
+                }
+                grouped.push(digit);
+            }
+            grouped + rest
+        }
+        None => digits.to_string(),
+    };
+    let zeros = format
+        .width
+        .unwrap_or_default()
+        .saturating_sub(sign.len() + digits.chars().count());
+    format!("{sign}{}{digits}", "0".repeat(zeros))
+}
+
+/// One step of the arithmetic `MapNum` applies to numbers.
+#[derive(Clone, Copy)]
+pub enum NumStep {
+    Add(f64),
+    Subtract(f64),
+    Multiply(f64),
+    Divide(f64),
+    /// Round to a number of decimal places
+    Round(u32),
+    Floor,
+    Ceil,
+    Abs,
+}
+
+/// The steps of a `MapNum` expression such as `*1024`, `+1` or `/1024,round:1`, applied in order.
+#[derive(Clone)]
+pub struct NumExpr {
+    steps: Vec<NumStep>,
+    source: String,
+}
+
+impl FromStr for NumExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "{s:?}: expected steps such as +N, -N, *N, /N, round:PLACES, floor, ceil or abs, separated by commas"
+            )
+        };
+        s.split(',')
+            .map(|step| {
+                let step = step.trim();
+                let operand = || step[1..].trim().parse::<f64>().map_err(|_| error());
+                Ok(match step {
+                    "floor" => NumStep::Floor,
+                    "ceil" => NumStep::Ceil,
+                    "abs" => NumStep::Abs,
+                    "round" => NumStep::Round(0),
+                    _ if step.starts_with('+') => NumStep::Add(operand()?),
+                    _ if step.starts_with('-') => NumStep::Subtract(operand()?),
+                    _ if step.starts_with('*') => NumStep::Multiply(operand()?),
+                    _ if step.starts_with('/') => match operand()? {
+                        0.0 => return Err(format!("{s:?}: can't divide by zero")),
+                        divisor => NumStep::Divide(divisor),
+                    },
+                    _ => match step.strip_prefix("round:").map(str::parse) {
+                        Some(Ok(places)) => NumStep::Round(places),
+                        _ => return Err(error()),
+                    },
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(|steps| NumExpr {
+                steps,
+                source: s.to_string(),
+            })
+    }
+}
+
+impl NumExpr {
+    /// The expression as given on the command line.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+/// Rewrites every number in `input` with the result of `expr` on it.
+pub fn map(expr: &NumExpr, input: &str) -> String {
+    replace_numbers(input, None, |text| {
+        let Ok(mut n) = text.parse::<f64>() else {
+            return text.to_string();
+        };
+        let mut places = None;
+        for step in &expr.steps {
+            n = match *step {
+                NumStep::Add(x) => n + x,
+                NumStep::Subtract(x) => n - x,
+                NumStep::Multiply(x) => n * x,
+                NumStep::Divide(x) => n / x,
+                NumStep::Round(p) => {
+                    places = Some(p as usize);
+                    let scale = 10f64.powi(p as i32);
+                    (n * scale).round() / scale
+                }
+                NumStep::Floor => n.floor(),
+                NumStep::Ceil => n.ceil(),
+                NumStep::Abs => n.abs(),
+            };
+        }
+        match places {
+            // rounding keeps its trailing zeros, as in 1.50
+            Some(places) => format!("{n:.places$}"),
+            // without the float noise of results such as 0.1 * 3
+            None => {
+                let fixed = format!("{n:.12}");
+                let trimmed = fixed.trim_end_matches('0').trim_end_matches('.');
+                match trimmed {
+                    "-0" => "0".to_string(),
+                    trimmed => trimmed.to_string(),
+                }
+            }
+        }
+    })
+}
+
+/// Rewrites every number in `input` as a quantity a person reads at a glance, including numbers
+/// written with the base unit of the quantity, as in `1536B` or `90061s`.
+pub fn humanize(quantity: Quantity, input: &str) -> String {
+    let unit = match quantity {
+        Quantity::Bytes => Some('B'),
+        Quantity::Si => None,
+        Quantity::Duration => Some('s'),
+    };
+    replace_numbers(input, unit, |text| {
+        match text.trim_end_matches(['B', 's']).parse::<f64>() {
+            Ok(n) => human(quantity, n),
+            Err(_) => text.to_string(),
+        }
+    })
+}
+
+fn human(quantity: Quantity, n: f64) -> String {
+    let sign = if n < 0.0 { "-" } else { "" };
+    let n = n.abs();
+    match quantity {
+        Quantity::Bytes => format!("{sign}{}", stats::size(n)),
+        Quantity::Si => {
+            let mut value = n;
+            let mut unit = 0;
+            while value >= 1000.0 && unit < SI_UNITS.len() - 1 {
+                value /= 1000.0;
+                unit += 1;
+            }
+            match unit {
+                0 => format!("{sign}{}", plain(n)),
+                _ => format!("{sign}{value:.1}{}", SI_UNITS[unit]),
+            }
+        }
+        Quantity::Duration => {
+            let mut rest = n;
+            let mut parts = Vec::new();
+            for (unit, seconds) in DURATION_UNITS {
+                let whole = match unit {
+                    "s" => rest,
+                    _ => (rest / seconds).floor(),
+                };
+                if whole > 0.0 || (unit == "s" && parts.is_empty()) {
+                    parts.push(format!(
+                        "{}{unit}",
+                        plain((whole * 1000.0).round() / 1000.0)
+                    ));
+                }
+                rest -= whole * seconds;
+            }
+            format!("{sign}{}", parts.join(" "))
+        }
+    }
+}
+
+// a number the way it is written in the pattern that finds it, for every quantity
+fn human_pattern(quantity: Quantity) -> &'static Regex {
+    static BYTES: OnceLock<Regex> = OnceLock::new();
+    static SI: OnceLock<Regex> = OnceLock::new();
+    static DURATION: OnceLock<Regex> = OnceLock::new();
+    let (cell, pattern) = match quantity {
+        Quantity::Bytes => (&BYTES, r"(-?\d+(?:\.\d+)?) ?([KMGTPE]i?B?|kB?|B)\b"),
+        Quantity::Si => (&SI, r"(-?\d+(?:\.\d+)?)([kKMGTPE])\b"),
+        Quantity::Duration => (
+            &DURATION,
+            r"-?(?:\d+(?:\.\d+)?[dhms] ?)*\d+(?:\.\d+)?[dhms]\b",
+        ),
+    };
+    cell.get_or_init(|| Regex::new(pattern).expect("the quantity patterns are valid"))
+}
+
+/// Rewrites every human-readable quantity in `input`, such as `1.4 GiB`, `12k` or `1h 30m`, as the
+/// plain number it stands for, so it can be sorted or summed.
+pub fn dehumanize(quantity: Quantity, input: &str) -> String {
+    human_pattern(quantity)
+        .replace_all(input, |caps: &Captures| {
+            let n = match quantity {
+                Quantity::Duration => seconds(&caps[0]),
+                _ => caps[1].parse::<f64>().unwrap_or_default() * scale(quantity, &caps[2]),
+            };
+            match quantity {
+                // bytes come whole
+                Quantity::Bytes => plain(n.round()),
+                _ => plain(n),
+            }
+        })
+        .into_owned()
+}
+
+// what a unit multiplies by: KiB and a bare K for bytes (as `ls -h` writes them) by powers of
+// 1024, and kB and SI units by powers of 1000
+fn scale(quantity: Quantity, unit: &str) -> f64 {
+    let power = "BKMGTPE"
+        .find(unit[..1].to_ascii_uppercase().as_str())
+        .unwrap_or_default() as i32;
+    let binary = matches!(quantity, Quantity::Bytes) && (unit.len() == 1 || unit.contains('i'));
+    match binary {
+        true => 1024f64.powi(power),
+        false => 1000f64.powi(power),
+    }
+}
+
+fn seconds(duration: &str) -> f64 {
+    static PART: OnceLock<Regex> = OnceLock::new();
+    let part = PART.get_or_init(|| {
+        Regex::new(r"(\d+(?:\.\d+)?)([dhms])").expect("the duration pattern is valid")
+    });
+    let total: f64 = part
+        .captures_iter(duration)
+        .filter_map(|caps| {
+            let (_, seconds) = DURATION_UNITS.iter().find(|(unit, _)| *unit == &caps[2])?;
+            Some(caps[1].parse::<f64>().ok()? * seconds)
+        })
+        .sum();
+    match duration.starts_with('-') {
+        true => -total,
+        false => total,
+    }
+}
+
+// a number without a fractional part when it has none
+fn plain(n: f64) -> String {
+    match n.fract() == 0.0 && n.abs() < 1e15 {
+        true => format!("{n:.0}"),
+        false => n.to_string(),
+    }
+}
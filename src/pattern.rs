@@ -0,0 +1,370 @@
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use caseless::Caseless;
+use memchr::memmem::{Finder, FinderRev};
+use regex::{Regex, RegexBuilder};
+
+/// How letter case is compared when matching (`--fold-case`).
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Folding {
+    /// Case matters
+    #[default]
+    None,
+    /// Full Unicode case folding, so `ß` matches `SS`
+    Full,
+    /// Full folding with the Turkish and Azerbaijani rules for dotted and dotless i
+    Turkic,
+}
+
+impl Folding {
+    /// The folding for `--fold-case` under `--locale`, which only changes it for Turkic languages.
+    pub fn new(fold_case: bool, locale: Option<&str>) -> Self {
+        let language = locale.map(|l| l.split(['_', '-', '.']).next().unwrap_or_default());
+        match (fold_case, language) {
+            (false, _) => Folding::None,
+            (true, Some("tr" | "az")) => Folding::Turkic,
+            (true, _) => Folding::Full,
+        }
+    }
+
+    fn fold(self, c: char) -> impl Iterator<Item = char> {
+        let c = match (self, c) {
+            (Folding::Turkic, 'I') => 'ı',
+            (Folding::Turkic, 'İ') => 'i',
+            _ => c,
+        };
+        std::iter::once(c).default_case_fold()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Mode {
+    regex: bool,
+    multiline: bool,
+    folding: Folding,
+    last: bool,
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+/// Sets whether patterns are regular expressions rather than literal text, whether `^` and `$`
+/// in them match at every line of a multiline record, how case is compared, and whether
+/// operations anchor on the last match rather than the first. Called once per run, before any
+/// pattern is used.
+pub fn configure(regex: bool, multiline: bool, folding: Folding, last: bool) {
+    let _ = MODE.set(Mode {
+        regex,
+        multiline,
+        folding,
+        last,
+    });
+}
+
+// whether operations anchor on the last match of their pattern (--last)
+fn last() -> bool {
+    MODE.get().is_some_and(|mode| mode.last)
+}
+
+/// Whether patterns are matched as the exact text given, as they are without --regex and
+/// --fold-case.
+pub fn literal() -> bool {
+    MODE.get()
+        .is_none_or(|mode| !mode.regex && mode.folding == Folding::None)
+}
+
+#[derive(Clone)]
+enum Matcher {
+    Literal(Literal),
+    /// A literal compared after case folding both it and the input
+    Folded(Literal, Folding),
+    Regex(Regex),
+}
+
+/// Literal text searched for with SIMD-accelerated substring search, built once per pattern.
+#[derive(Clone)]
+struct Literal {
+    text: String,
+    finder: Finder<'static>,
+    finder_rev: FinderRev<'static>,
+}
+
+impl Literal {
+    fn new(text: String) -> Self {
+        Literal {
+            finder: Finder::new(&text).into_owned(),
+            finder_rev: FinderRev::new(&text).into_owned(),
+            text,
+        }
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        self.finder.find(haystack.as_bytes()).is_some()
+    }
+
+    fn rfind(&self, haystack: &str) -> Option<Range<usize>> {
+        let len = self.text.len();
+        match len {
+            // memmem would find the empty string between the bytes of a character
+            0 => Some(haystack.len()..haystack.len()),
+            _ => self
+                .finder_rev
+                .rfind(haystack.as_bytes())
+                .map(|i| i..i + len),
+        }
+    }
+
+    fn find_iter<'a>(&'a self, haystack: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        let len = self.text.len();
+        match len {
+            0 => Box::new(haystack.match_indices("").map(|(i, _)| i..i)),
+            _ => Box::new(
+                self.finder
+                    .find_iter(haystack.as_bytes())
+                    .map(move |i| i..i + len),
+            ),
+        }
+    }
+}
+
+/// `input` case-folded, with the byte offset in `input` of each position in the folded text that
+/// begins a character's folding (and of its end). Positions inside a multi-character folding,
+/// such as between the two `s` of a folded `ß`, have none.
+fn fold(input: &str, folding: Folding) -> (String, Vec<Option<usize>>) {
+    let mut folded = String::with_capacity(input.len());
+    let mut offsets = Vec::with_capacity(input.len() + 1);
+    for (i, c) in input.char_indices() {
+        offsets.resize(folded.len(), None);
+        offsets.push(Some(i));
+        folded.extend(folding.fold(c));
+    }
+    offsets.resize(folded.len(), None);
+    offsets.push(Some(input.len()));
+    (folded, offsets)
+}
+
+// the regex engine folds one character at a time, so `ß` doesn't match `ss` here
+fn try_regex(source: &str, mode: Mode) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(source)
+        .multi_line(mode.multiline)
+        .case_insensitive(mode.folding != Folding::None)
+        .build()
+}
+
+/// A pattern given on the command line, compiled on first use and reused for every line after.
+#[derive(Clone)]
+pub struct Pattern {
+    source: String,
+    // boxed, as the finders would make every operation and option holding a pattern large
+    matcher: OnceLock<Box<Matcher>>,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = Pattern {
+            source: s.to_string(),
+            matcher: OnceLock::new(),
+        };
+        // patterns parsed once the run is configured, such as the stages typed into `str tui`, are
+        // checked right away rather than when first used
+        if let Some(mode) = MODE.get().filter(|mode| mode.regex) {
+            let re = try_regex(s, *mode).map_err(|e| format!("invalid regular expression: {e}"))?;
+            let _ = pattern.matcher.set(Box::new(Matcher::Regex(re)));
+        }
+        Ok(pattern)
+    }
+}
+
+impl Pattern {
+    fn matcher(&self) -> &Matcher {
+        self.matcher.get_or_init(|| {
+            let mode = MODE.get().copied().unwrap_or_default();
+            Box::new(match (mode.regex, mode.folding) {
+                (false, Folding::None) => Matcher::Literal(Literal::new(self.source.clone())),
+                (false, folding) => {
+                    Matcher::Folded(Literal::new(fold(&self.source, folding).0), folding)
+                }
+                // patterns parsed once --regex is configured were compiled by `from_str`
+                _ => Matcher::Regex(
+                    try_regex(&self.source, mode)
+                        .expect("patterns are parsed after the run is configured"),
+                ),
+            })
+        })
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        match self.matcher() {
+            Matcher::Literal(literal) => literal.is_match(input),
+            Matcher::Folded(..) => self.find(input).is_some(),
+            Matcher::Regex(re) => re.is_match(input),
+        }
+    }
+
+    /// The pattern as given on the command line.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Byte range of the first match in `input`.
+    pub fn find(&self, input: &str) -> Option<Range<usize>> {
+        self.find_iter(input).next()
+    }
+
+    /// Byte range of the last match in `input`.
+    pub fn rfind(&self, input: &str) -> Option<Range<usize>> {
+        match self.matcher() {
+            Matcher::Literal(literal) => literal.rfind(input),
+            _ => self.find_iter(input).last(),
+        }
+    }
+
+    /// Byte range of the match an operation anchors on: the first, or the last with --last.
+    pub fn anchor(&self, input: &str) -> Option<Range<usize>> {
+        match last() {
+            true => self.rfind(input),
+            false => self.find(input),
+        }
+    }
+
+    pub fn is_prefix_of(&self, input: &str) -> bool {
+        self.find(input).is_some_and(|m| m.start == 0)
+    }
+
+    pub fn is_suffix_of(&self, input: &str) -> bool {
+        self.rfind(input).is_some_and(|m| m.end == input.len())
+    }
+
+    /// Byte ranges of every non-overlapping match in `input`, from left to right.
+    pub fn find_iter<'a>(&'a self, input: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        match self.matcher() {
+            Matcher::Literal(literal) => literal.find_iter(input),
+            Matcher::Folded(literal, folding) => {
+                let (folded, offsets) = fold(input, *folding);
+                // matches that begin or end partway through a character's folding are no match
+                let found: Vec<Range<usize>> = literal
+                    .find_iter(&folded)
+                    .filter_map(|m| Some(offsets[m.start]?..offsets[m.end]?))
+                    .collect();
+                Box::new(found.into_iter())
+            }
+            Matcher::Regex(re) => Box::new(re.find_iter(input).map(|m| m.range())),
+        }
+    }
+}
+
+/// The parts of `input` between the given matches, splitting at no more than `limit` of them
+/// when given, counted from the end when `from_end` is set.
+pub fn split_at(
+    input: &str,
+    mut matches: Vec<Range<usize>>,
+    limit: Option<usize>,
+    from_end: bool,
+) -> Vec<&str> {
+    if let Some(limit) = limit {
+        match from_end {
+            true => {
+                matches.drain(..matches.len().saturating_sub(limit));
+            }
+            false => matches.truncate(limit),
+        }
+    }
+    let mut parts = Vec::with_capacity(matches.len() + 1);
+    let mut last = 0;
+    for m in matches {
+        parts.push(&input[last..m.start]);
+        last = m.end;
+    }
+    parts.push(&input[last..]);
+    parts
+}
+
+/// Several patterns matched at once, in a single pass over each line: a match is the leftmost
+/// match of any of them, and the one given first when more than one match starts there.
+#[derive(Clone)]
+pub enum PatternSet {
+    One(Box<Pattern>),
+    Literal(AhoCorasick),
+    Folded(AhoCorasick, Folding),
+    Regex(Regex),
+}
+
+impl PatternSet {
+    pub fn new(patterns: Vec<Pattern>) -> Result<Self, String> {
+        let mode = MODE.get().copied().unwrap_or_default();
+        let mut patterns = patterns;
+        if patterns.len() == 1 {
+            return Ok(PatternSet::One(Box::new(patterns.remove(0))));
+        }
+        if mode.regex {
+            let sources: Vec<String> = patterns
+                .iter()
+                .map(|p| format!("(?:{})", p.source))
+                .collect();
+            return try_regex(&sources.join("|"), mode)
+                .map(PatternSet::Regex)
+                .map_err(|e| format!("invalid regular expression: {e}"));
+        }
+        let sources: Vec<String> = match mode.folding {
+            Folding::None => patterns.iter().map(|p| p.source.clone()).collect(),
+            folding => patterns
+                .iter()
+                .map(|p| fold(&p.source, folding).0)
+                .collect(),
+        };
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(&sources)
+            .map_err(|e| format!("failed to build pattern matcher: {e}"))?;
+        Ok(match mode.folding {
+            Folding::None => PatternSet::Literal(automaton),
+            folding => PatternSet::Folded(automaton, folding),
+        })
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        match self {
+            PatternSet::One(pattern) => pattern.is_match(input),
+            PatternSet::Literal(automaton) => automaton.is_match(input),
+            PatternSet::Folded(..) => self.find(input).is_some(),
+            PatternSet::Regex(re) => re.is_match(input),
+        }
+    }
+
+    /// Byte range of the first match in `input`.
+    pub fn find(&self, input: &str) -> Option<Range<usize>> {
+        self.find_iter(input).next()
+    }
+
+    /// Byte range of the match an operation anchors on: the first, or the last with --last.
+    pub fn anchor(&self, input: &str) -> Option<Range<usize>> {
+        match (self, last()) {
+            (PatternSet::One(pattern), true) => pattern.rfind(input),
+            (_, true) => self.find_iter(input).last(),
+            (_, false) => self.find(input),
+        }
+    }
+
+    /// Byte ranges of every non-overlapping match in `input`, from left to right.
+    pub fn find_iter<'a>(&'a self, input: &'a str) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+        match self {
+            PatternSet::One(pattern) => pattern.find_iter(input),
+            PatternSet::Literal(automaton) => {
+                Box::new(automaton.find_iter(input).map(|m| m.range()))
+            }
+            PatternSet::Folded(automaton, folding) => {
+                let (folded, offsets) = fold(input, *folding);
+                let found: Vec<Range<usize>> = automaton
+                    .find_iter(&folded)
+                    .filter_map(|m| Some(offsets[m.start()]?..offsets[m.end()]?))
+                    .collect();
+                Box::new(found.into_iter())
+            }
+            PatternSet::Regex(re) => Box::new(re.find_iter(input).map(|m| m.range())),
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use clap::{Command, CommandFactory, Parser, ValueEnum};
+use jiff::tz::TimeZone;
+use regex::Regex;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::aggregate::Aggregate;
+use crate::cli::{
+    HashAlgorithm, Metric, Operation, Output, Predicate, RatioFormat, RegexFlavor, UrlPart,
+};
+use crate::numbers::NumExpr;
+use crate::pattern::Pattern;
+use crate::redact::RedactPreset;
+use crate::script::Script;
+use crate::template::Template;
+use crate::time::TimeFormat;
+
+/// Operations applied one after another, each to every line the one before outputs, as when
+/// piping `str` into `str`.
+pub struct Pipeline {
+    stages: Vec<Operation>,
+}
+
+/// An operation as stored in a config file or sent as JSON: its name, its arguments as on the
+/// command line, and its options by their long names, such as
+/// `{"op": "replace", "args": ["cat", "--", "dog"], "number": 1}`.
+#[derive(Serialize, Deserialize)]
+pub struct Definition {
+    pub op: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<Word>,
+    #[serde(flatten)]
+    pub options: BTreeMap<String, OptionValue>,
+}
+
+/// An argument or option value, which may be written as a number.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Word {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+}
+
+/// The value of an option: true to set one that takes no value (false leaves it out), or the
+/// value or values to give it.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Flag(bool),
+    Word(Word),
+    List(Vec<Word>),
+}
+
+// one definition or a list of them, applied one after another
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "an operation, such as {\"op\": \"trim\"}, or a list of operations"
+)]
+enum Definitions {
+    One(Definition),
+    Many(Vec<Definition>),
+}
+
+/// A value of an operation's field as the words it is written as on the command line, from which
+/// its [`Definition`] is made.
+pub trait Words {
+    fn words(&self) -> Vec<String>;
+}
+
+impl From<String> for Word {
+    // numbers are written as such, as long as that doesn't change how they read
+    fn from(text: String) -> Self {
+        match (text.parse::<i64>(), text.parse::<f64>()) {
+            (Ok(n), _) if n.to_string() == text => Word::Integer(n),
+            (_, Ok(n)) if n.is_finite() && n.to_string() == text => Word::Float(n),
+            _ => Word::Text(text),
+        }
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Word::Text(text) => f.write_str(text),
+            Word::Integer(n) => write!(f, "{n}"),
+            Word::Float(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+impl Definition {
+    /// The definition of the operation `op` whose fields, by name, are written as the given
+    /// words. Fields left at their defaults are left out.
+    pub fn new(op: &str, fields: Vec<(&str, Vec<String>)>) -> Self {
+        static COMMAND: OnceLock<Command> = OnceLock::new();
+        let command = COMMAND
+            .get_or_init(Stage::command)
+            .find_subcommand(op)
+            .expect("every operation is a subcommand");
+        let mut fields: BTreeMap<&str, Vec<String>> = fields.into_iter().collect();
+
+        let mut definition = Definition {
+            op: op.to_string(),
+            args: Vec::new(),
+            options: BTreeMap::new(),
+        };
+        let mut escaped = false;
+        for arg in command.get_arguments() {
+            let Some(words) = fields.remove(arg.get_id().as_str()) else {
+                continue;
+            };
+            let defaults: Vec<_> = arg
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_string_lossy())
+                .collect();
+            if words.is_empty() || words == defaults {
+                continue;
+            }
+            if arg.is_positional() {
+                // an argument that could be taken for an option has to follow --
+                let dashed = !arg.is_allow_hyphen_values_set()
+                    && !arg.is_allow_negative_numbers_set()
+                    && words
+                        .iter()
+                        .any(|word| word.len() > 1 && word.starts_with('-'));
+                if !escaped && (arg.is_last_set() || dashed) {
+                    definition.args.push(Word::Text("--".to_string()));
+                    escaped = true;
+                }
+                definition.args.extend(words.into_iter().map(Word::from));
+                continue;
+            }
+            let name = match (arg.get_long(), arg.get_short()) {
+                (Some(long), _) => long.to_string(),
+                (None, Some(short)) => short.to_string(),
+                (None, None) => unreachable!("options have a name"),
+            };
+            let value = match arg.get_action().takes_values() {
+                false => OptionValue::Flag(true),
+                true if words.len() == 1 => OptionValue::Word(Word::from(words[0].clone())),
+                true => OptionValue::List(words.into_iter().map(Word::from).collect()),
+            };
+            definition.options.insert(name, value);
+        }
+        debug_assert!(fields.is_empty(), "'{op}' has no {:?}", fields.keys());
+        definition
+    }
+
+    /// The operation, parsed as if given on the command line.
+    pub fn operation(&self) -> Result<Operation, String> {
+        let mut words = vec![self.op.clone()];
+        for (name, value) in &self.options {
+            let flag = match name.chars().count() {
+                1 => format!("-{name}"),
+                _ => format!("--{}", name.replace('_', "-")),
+            };
+            match value {
+                OptionValue::Flag(true) => words.push(flag),
+                OptionValue::Flag(false) => {}
+                OptionValue::Word(word) => words.push(format!("{flag}={word}")),
+                OptionValue::List(list) => {
+                    words.extend(list.iter().map(|word| format!("{flag}={word}")))
+                }
+            }
+        }
+        words.extend(self.args.iter().map(Word::to_string));
+        parse_stage(&shell_words::join(&words), &words)
+    }
+}
+
+impl Serialize for Operation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.definition().serialize(serializer)
+    }
+}
+
+impl Serialize for Pipeline {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.stages)
+    }
+}
+
+impl<'de> Deserialize<'de> for Operation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Definition::deserialize(deserializer)?
+            .operation()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pipeline {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let definitions = match Definitions::deserialize(deserializer)? {
+            Definitions::One(definition) => vec![definition],
+            Definitions::Many(definitions) => definitions,
+        };
+        if definitions.is_empty() {
+            return Err(de::Error::custom("no operations to run"));
+        }
+        let stages = definitions
+            .iter()
+            .map(Definition::operation)
+            .collect::<Result<_, _>>()
+            .map_err(de::Error::custom)?;
+        Ok(Pipeline { stages })
+    }
+}
+
+// a single stage, parsed from an operation and its arguments
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct Stage {
+    #[command(subcommand)]
+    operation: Operation,
+    // --invert is the one global flag a stage takes, as filter had it before it was global
+    #[arg(long, global = true)]
+    invert: bool,
+}
+
+impl Pipeline {
+    /// Parses each stage from an operation and its arguments, quoted as on the command line.
+    pub fn parse<'s>(stages: impl IntoIterator<Item = &'s str>) -> Result<Self, String> {
+        let stages = stages
+            .into_iter()
+            .map(|text| match stage(text)? {
+                Operation::Preset { .. } => {
+                    Err(format!("'{text}': presets can't use other presets"))
+                }
+                operation => Ok(operation),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Pipeline { stages })
+    }
+
+    /// The script at `path`, with one stage per line. Blank lines and lines starting with `#`,
+    /// such as a `#!` line, are skipped.
+    pub fn script(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read script {}: {e}", path.display()))?;
+        let stages = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(n, line)| stage(line).map_err(|e| format!("{}:{}: {e}", path.display(), n + 1)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if stages.is_empty() {
+            return Err(format!("script {} has no operations", path.display()));
+        }
+        Ok(Pipeline { stages })
+    }
+
+    /// The operation or list of operations described by `text`, as JSON.
+    pub fn json(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| format!("invalid operations JSON: {e}"))
+    }
+
+    /// The preset called `name` in the presets file at `path`, or by default in
+    /// `$XDG_CONFIG_HOME/str/presets.toml` (`~/.config/str/presets.toml`).
+    pub fn preset(name: &str, path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => presets_file().ok_or("no home directory to find presets in")?,
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read presets from {}: {e}", path.display()))?;
+        let presets: toml::Table = text
+            .parse()
+            .map_err(|e| format!("invalid presets file {}: {e}", path.display()))?;
+
+        let stages: Vec<&toml::Value> = match presets.get(name) {
+            Some(toml::Value::Array(stages)) => stages.iter().collect(),
+            Some(stage) => vec![stage],
+            None => {
+                let names: Vec<&str> = presets.keys().map(String::as_str).collect();
+                return Err(format!(
+                    "no preset '{name}' in {} (it has {})",
+                    path.display(),
+                    names.join(", ")
+                ));
+            }
+        };
+        let stages = stages
+            .into_iter()
+            .map(|stage| match stage {
+                toml::Value::String(text) => self::stage(text),
+                toml::Value::Table(_) => stage
+                    .clone()
+                    .try_into::<Definition>()
+                    .map_err(|e| e.to_string())
+                    .and_then(|definition| definition.operation()),
+                _ => Err("stages must be strings or tables".to_string()),
+            })
+            .map(|stage| match stage? {
+                Operation::Preset { .. } => Err("presets can't use other presets".to_string()),
+                operation => Ok(operation),
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("preset '{name}': {e}"))?;
+        Ok(Pipeline { stages })
+    }
+
+    /// The state each stage carries between lines, or `None` if every stage works line by line.
+    pub fn aggregates(&self) -> Result<Option<Vec<Option<Aggregate>>>, String> {
+        let states = self
+            .stages
+            .iter()
+            .map(Aggregate::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(states.iter().any(Option::is_some).then_some(states))
+    }
+
+    /// Runs a line through every stage.
+    pub fn execute(&self, input: &str) -> Result<Output, String> {
+        Ok(output(self.run(&mut [], 0, vec![input.to_string()])?))
+    }
+
+    /// Runs a line through every stage, feeding those that work on more than one line.
+    pub fn push(&self, states: &mut [Option<Aggregate>], input: &str) -> Result<Output, String> {
+        Ok(output(self.run(states, 0, vec![input.to_string()])?))
+    }
+
+    /// Runs whatever each stage held back through the stages after it.
+    pub fn finish(&self, states: &mut [Option<Aggregate>]) -> Result<Output, String> {
+        let mut finished = Vec::new();
+        for (i, operation) in self.stages.iter().enumerate() {
+            let Some(state) = &mut states[i] else {
+                continue;
+            };
+            let held = lines(state.finish(operation)?);
+            finished.extend(self.run(states, i + 1, held)?);
+        }
+        Ok(output(finished))
+    }
+
+    /// Whether no line fed from now on can change the output: the first stage that carries state
+    /// is done, and the stages before it work line by line.
+    pub fn done(&self, states: &[Option<Aggregate>]) -> bool {
+        states
+            .iter()
+            .zip(&self.stages)
+            .find_map(|(state, operation)| Some(state.as_ref()?.done(operation)))
+            .unwrap_or(false)
+    }
+
+    /// Whether every stage that keeps a verdict (`is`) found it true.
+    pub fn success(states: &[Option<Aggregate>]) -> bool {
+        states.iter().flatten().all(Aggregate::success)
+    }
+
+    // runs lines through the stages from `first` on
+    fn run(
+        &self,
+        states: &mut [Option<Aggregate>],
+        first: usize,
+        mut batch: Vec<String>,
+    ) -> Result<Vec<String>, String> {
+        for (i, operation) in self.stages.iter().enumerate().skip(first) {
+            let mut next = Vec::with_capacity(batch.len());
+            for line in &batch {
+                next.extend(lines(match states.get_mut(i).and_then(Option::as_mut) {
+                    Some(state) => state.push(operation, line)?,
+                    None => operation.execute(line)?,
+                }));
+            }
+            batch = next;
+        }
+        Ok(batch)
+    }
+}
+
+// parses one stage, written as on the command line
+fn stage(text: &str) -> Result<Operation, String> {
+    let words = shell_words::split(text).map_err(|e| format!("'{text}': {e}"))?;
+    parse_stage(text, &words)
+}
+
+// parses one stage from its words, quoted together as `text` in errors
+fn parse_stage(text: &str, words: &[String]) -> Result<Operation, String> {
+    let parsed = Stage::try_parse_from(words).map_err(|e| {
+        let message = e.to_string();
+        let first = message.lines().next().unwrap_or_default();
+        format!("'{text}': {}", first.trim_start_matches("error: "))
+    })?;
+    match parsed.operation {
+        Operation::Examples { .. } | Operation::Tui => {
+            Err(format!("'{text}' can't be used as a stage"))
+        }
+        operation if parsed.invert => operation.inverted().map_err(|e| format!("'{text}': {e}")),
+        operation => Ok(operation),
+    }
+}
+
+fn presets_file() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(".config")))?;
+    Some(config.join("str").join("presets.toml"))
+}
+
+// the lines a stage outputs, each fed to the next stage on its own
+fn lines(output: Output) -> Vec<String> {
+    match output {
+        Output::Multiple(lines) => lines,
+        Output::Single(line) => vec![line],
+        Output::Skip => Vec::new(),
+    }
+}
+
+fn output(mut lines: Vec<String>) -> Output {
+    match lines.len() {
+        0 => Output::Skip,
+        1 => Output::Single(lines.remove(0)),
+        _ => Output::Multiple(lines),
+    }
+}
+
+impl Words for bool {
+    fn words(&self) -> Vec<String> {
+        match self {
+            true => vec!["true".to_string()],
+            false => Vec::new(),
+        }
+    }
+}
+
+impl<T: Words> Words for Option<T> {
+    fn words(&self) -> Vec<String> {
+        self.iter().flat_map(Words::words).collect()
+    }
+}
+
+impl<T: Words> Words for Vec<T> {
+    fn words(&self) -> Vec<String> {
+        self.iter().flat_map(Words::words).collect()
+    }
+}
+
+// values written as they display
+macro_rules! displayed {
+    ($($type:ty),*) => {
+        $(impl Words for $type {
+            fn words(&self) -> Vec<String> {
+                vec![self.to_string()]
+            }
+        })*
+    };
+}
+
+displayed!(usize, u64, i64, f64, char, String, UrlPart, TimeFormat);
+
+// values written by their names as clap lists them
+macro_rules! named {
+    ($($type:ty),*) => {
+        $(impl Words for $type {
+            fn words(&self) -> Vec<String> {
+                let value = self.to_possible_value().expect("no value is hidden");
+                vec![value.get_name().to_string()]
+            }
+        })*
+    };
+}
+
+named!(
+    RatioFormat,
+    Metric,
+    RegexFlavor,
+    HashAlgorithm,
+    RedactPreset
+);
+
+impl Words for PathBuf {
+    fn words(&self) -> Vec<String> {
+        vec![self.display().to_string()]
+    }
+}
+
+impl Words for Pattern {
+    fn words(&self) -> Vec<String> {
+        vec![self.source().to_string()]
+    }
+}
+
+impl Words for Regex {
+    fn words(&self) -> Vec<String> {
+        vec![self.as_str().to_string()]
+    }
+}
+
+impl Words for Template {
+    fn words(&self) -> Vec<String> {
+        vec![self.source().to_string()]
+    }
+}
+
+impl Words for NumExpr {
+    fn words(&self) -> Vec<String> {
+        vec![self.source().to_string()]
+    }
+}
+
+impl Words for Script {
+    fn words(&self) -> Vec<String> {
+        vec![self.source().to_string()]
+    }
+}
+
+impl Words for TimeZone {
+    // only the system's zone, given as "local", can be without an IANA name
+    fn words(&self) -> Vec<String> {
+        vec![self.iana_name().unwrap_or("local").to_string()]
+    }
+}
+
+impl Words for Predicate {
+    fn words(&self) -> Vec<String> {
+        let (name, pattern) = match self {
+            Predicate::Contains { pattern } => ("contains", pattern.words()),
+            Predicate::StartsWith { pattern } => ("starts-with", pattern.words()),
+            Predicate::EndsWith { pattern } => ("ends-with", pattern.words()),
+            Predicate::Matches { pattern } => ("matches", pattern.words()),
+            Predicate::Empty => ("empty", Vec::new()),
+            Predicate::Number => ("number", Vec::new()),
+            Predicate::Ascii => ("ascii", Vec::new()),
+        };
+        [vec![name.to_string()], pattern].concat()
+    }
+}
+
+impl Words for Pipeline {
+    fn words(&self) -> Vec<String> {
+        vec![serde_json::to_string(self).expect("operations are written as JSON")]
+    }
+}
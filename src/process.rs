@@ -0,0 +1,409 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Result, anyhow, bail};
+use clap::ValueEnum;
+use rayon::prelude::*;
+
+use crate::address::Addresses;
+use crate::aggregate::Aggregate;
+use crate::cli::{Args, Operation, Output, PatternOperation};
+use crate::envelope::Envelope;
+use crate::field::Field;
+use crate::pattern::Pattern;
+use crate::span::OnInvalid;
+
+/// What to do with a line the patterns an operation is anchored on don't occur in.
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum NonMatching {
+    /// Write the line unchanged
+    Pass,
+    /// Drop the line
+    Drop,
+    /// Write whatever the operation makes of the line, as it does without the flag
+    #[default]
+    KeepOriginal,
+}
+
+/// How to quote the fields of a result written on one line (`--quote-output`).
+#[derive(Clone, Copy, ValueEnum)]
+pub enum QuoteOutput {
+    /// Put fields containing the delimiter, a quote or a line break in double quotes, doubling
+    /// any quotes inside, as spreadsheets read them
+    Csv,
+}
+
+/// Applies an operation to a stream of lines, carrying the state global modifiers need between lines.
+pub struct Processor<'a> {
+    operation: &'a Operation,
+    aggregate: Option<Aggregate>,
+    lines: Option<Addresses>,
+    if_pattern: Option<Pattern>,
+    unless_pattern: Option<Pattern>,
+    field: Option<Field>,
+    line_number: usize,
+    empty_as: Option<String>,
+    drop_empty: bool,
+    source: String,
+    source_line: usize,
+    annotate: bool,
+    restore: bool,
+    on_invalid: OnInvalid,
+    non_matching: NonMatching,
+    output_delim: Option<String>,
+    quote_output: Option<QuoteOutput>,
+    jobs: usize,
+    fail_if_no_match: bool,
+    matched: AtomicBool,
+    verbose: u8,
+}
+
+impl<'a> Processor<'a> {
+    pub fn new(operation: &'a Operation, args: &Args) -> Result<Self> {
+        Ok(Self {
+            operation,
+            aggregate: Aggregate::new(operation).map_err(anyhow::Error::msg)?,
+            lines: args.lines.clone(),
+            if_pattern: args.if_pattern.clone(),
+            unless_pattern: args.unless_pattern.clone(),
+            field: args
+                .field
+                .map(|index| Field::new(index, args.field_delim.clone())),
+            line_number: 0,
+            empty_as: args.empty_as.clone(),
+            drop_empty: args.drop_empty,
+            source: String::new(),
+            source_line: 0,
+            annotate: args.annotate,
+            restore: args.restore,
+            on_invalid: args.on_invalid,
+            non_matching: args.non_matching,
+            // CSV is comma-separated unless told otherwise
+            output_delim: args
+                .output_delim
+                .clone()
+                .or(args.quote_output.map(|_| ",".to_string())),
+            quote_output: args.quote_output,
+            jobs: args.jobs,
+            fail_if_no_match: args.fail_if_no_match.unwrap_or(matches!(
+                operation,
+                Operation::PatternBased(PatternOperation::Filter { .. })
+                    | Operation::PatternBased(PatternOperation::Match { .. })
+                    | Operation::PatternBased(PatternOperation::Fuzzy { .. })
+            )),
+            matched: AtomicBool::new(false),
+            verbose: args.verbose,
+        })
+    }
+
+    /// Handles lines a range doesn't fit as given, rather than as --on-invalid says.
+    pub fn on_invalid(mut self, on_invalid: OnInvalid) -> Self {
+        self.on_invalid = on_invalid;
+        self
+    }
+
+    /// Starts reading from a new input, restarting its line count.
+    pub fn begin(&mut self, source: &str) {
+        self.source = source.to_string();
+        self.source_line = 0;
+        if let Some(aggregate) = &mut self.aggregate {
+            aggregate.begin(source);
+        }
+    }
+
+    /// Renders the result of the operation on the next line the way it is written out, or `None` if the line is dropped.
+    pub fn apply(&mut self, line: &str) -> Result<Option<String>> {
+        let (source_line, selected) = self.advance(line);
+        let operation = self.operation;
+        let mut aggregate = self.aggregate.take();
+        let result = self.apply_at(
+            line,
+            source_line,
+            selected,
+            &mut |input| match &mut aggregate {
+                Some(aggregate) => aggregate.push(operation, input),
+                None => operation.execute(input),
+            },
+        );
+        self.aggregate = aggregate;
+        result
+    }
+
+    /// Whether no line applied from now on can change the output, as once `head` has let its lines
+    /// through, so the rest of the input needn't be read.
+    pub fn done(&self) -> bool {
+        // lines the operation isn't applied to are passed through, so every line still matters
+        let every_line = self.lines.is_none()
+            && self.if_pattern.is_none()
+            && self.unless_pattern.is_none()
+            && self.field.is_none();
+        every_line
+            && self
+                .aggregate
+                .as_ref()
+                .is_some_and(|aggregate| aggregate.done(self.operation))
+    }
+
+    /// Whether `apply_batch` can be used: lines are split across more than one job, and each
+    /// line's result depends on that line alone.
+    pub fn parallel(&self) -> bool {
+        self.jobs != 1 && self.aggregate.is_none()
+    }
+
+    /// `apply` for the next lines at once, on the worker pool, with the results in input order.
+    pub fn apply_batch<S: AsRef<str> + Sync>(
+        &mut self,
+        lines: &[S],
+    ) -> Vec<Result<Option<String>>> {
+        // line numbers and --lines depend on the lines before, so they are settled up front
+        let positions: Vec<(usize, bool)> = lines
+            .iter()
+            .map(|line| self.advance(line.as_ref()))
+            .collect();
+        let this = &*self;
+        lines
+            .par_iter()
+            .zip(positions)
+            .map(|(line, (source_line, selected))| {
+                this.apply_at(line.as_ref(), source_line, selected, &mut |input| {
+                    this.operation.execute(input)
+                })
+            })
+            .collect()
+    }
+
+    // counts the next line, returning its number within the input and whether --lines selects it
+    fn advance(&mut self, line: &str) -> (usize, bool) {
+        self.line_number += 1;
+        self.source_line += 1;
+        let content = match Envelope::parse(line) {
+            Some((_, content)) if self.annotate || self.restore => content,
+            _ => line,
+        };
+        let selected = match &mut self.lines {
+            Some(lines) => lines.select(self.line_number, content),
+            None => true,
+        };
+        (self.source_line, selected)
+    }
+
+    fn apply_at(
+        &self,
+        line: &str,
+        source_line: usize,
+        selected: bool,
+        execute: &mut dyn FnMut(&str) -> Result<Output, String>,
+    ) -> Result<Option<String>> {
+        if !self.annotate && !self.restore {
+            return self.transform(line, source_line, selected, execute);
+        }
+
+        // lines annotated by an earlier stage keep their original envelope
+        let (envelope, line) = match Envelope::parse(line) {
+            Some((envelope, content)) => (envelope, content),
+            None => (Envelope::new(&self.source, source_line, line), line),
+        };
+        let result = self.transform(line, source_line, selected, execute)?;
+        Ok(result.map(|result| match self.annotate {
+            true => envelope.wrap(&result),
+            false => result,
+        }))
+    }
+
+    fn transform(
+        &self,
+        line: &str,
+        source_line: usize,
+        selected: bool,
+        execute: &mut dyn FnMut(&str) -> Result<Output, String>,
+    ) -> Result<Option<String>> {
+        if !selected {
+            self.report(
+                2,
+                source_line,
+                "not selected by --lines, passed through",
+                line,
+            );
+            return Ok(Some(line.to_string()));
+        }
+        if self.if_pattern.as_ref().is_some_and(|p| !p.is_match(line))
+            || self
+                .unless_pattern
+                .as_ref()
+                .is_some_and(|p| p.is_match(line))
+        {
+            self.report(2, source_line, "skipped by --if or --unless", line);
+            return Ok(Some(line.to_string()));
+        }
+
+        // with --field the operation only sees one field, which its result replaces
+        let (input, field) = match &self.field {
+            None => (line, None),
+            Some(field) => match field.locate(line) {
+                Some(range) => (&line[range.clone()], Some(range)),
+                None => {
+                    self.report(1, source_line, "no such field, passed through", line);
+                    return Ok(Some(line.to_string()));
+                }
+            },
+        };
+
+        // an operation that fails on the line ends the run, naming the line
+        let fail = |e: String| anyhow!("{}:{source_line}: {e}", self.source);
+        if self.non_matching != NonMatching::KeepOriginal
+            && !self.operation.matches(input).map_err(fail)?
+        {
+            return Ok(match self.non_matching {
+                NonMatching::Drop => {
+                    self.report(1, source_line, "no match, dropped", line);
+                    None
+                }
+                _ => {
+                    self.report(1, source_line, "no match, passed through", line);
+                    Some(line.to_string())
+                }
+            });
+        }
+        if self.placeholders() && !self.operation.matches(input).map_err(fail)? {
+            return Ok(self.empty());
+        }
+
+        if let Some(span) = self.operation.span(input)
+            && let Err(reason) = span.check(input)
+        {
+            match self.on_invalid {
+                OnInvalid::Error => bail!(
+                    "{}:{source_line}: {reason}: {input:?}\n\
+                     use --on-invalid to skip, pass through or clamp such lines",
+                    self.source
+                ),
+                OnInvalid::Skip => {
+                    self.report(1, source_line, &format!("{reason}, dropped"), line);
+                    return Ok(None);
+                }
+                OnInvalid::Passthrough => {
+                    self.report(1, source_line, &format!("{reason}, passed through"), line);
+                    return Ok(Some(line.to_string()));
+                }
+                OnInvalid::Clamp => {
+                    self.report(1, source_line, &format!("{reason}, clamped"), line);
+                }
+            }
+        }
+
+        let output = execute(input).map_err(fail)?;
+        // a filter matches by keeping the line, which --invert turns around
+        let matched = match self.operation {
+            Operation::PatternBased(PatternOperation::Filter { .. }) => {
+                !matches!(output, Output::Skip)
+            }
+            operation => operation.matches(input).map_err(fail)?,
+        };
+        if matched {
+            self.matched.store(true, Ordering::Relaxed);
+        } else {
+            self.report(2, source_line, "no match", line);
+        }
+        Ok(match field {
+            Some(range) => self.render(output.map(|result| {
+                line[..range.start].to_string() + result.as_str() + &line[range.end..]
+            })),
+            None => self.render(output),
+        })
+    }
+
+    /// Renders whatever the operation held back until the end of the input.
+    pub fn finish(&mut self) -> Result<Option<String>> {
+        let Some(aggregate) = &mut self.aggregate else {
+            return Ok(None);
+        };
+        let output = aggregate
+            .finish(self.operation)
+            .map_err(anyhow::Error::msg)?;
+        Ok(self.render(output))
+    }
+
+    /// Whether the run should exit successfully: every verdict held, and with
+    /// --fail-if-no-match, some line matched.
+    pub fn success(&self) -> bool {
+        self.held() && self.found()
+    }
+
+    /// Whether every verdict held, such as `is` gives.
+    pub fn held(&self) -> bool {
+        self.aggregate.as_ref().is_none_or(Aggregate::success)
+    }
+
+    /// Whether some line matched, or none had to.
+    pub fn found(&self) -> bool {
+        !self.fail_if_no_match || self.matched.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, output: Output) -> Option<String> {
+        let placeholders = self.placeholders();
+        match output {
+            Output::Multiple(x) if placeholders && x.iter().all(String::is_empty) => self.empty(),
+            Output::Multiple(x) if placeholders => Some(
+                self.join(
+                    x.into_iter()
+                        .filter(|s| !s.is_empty() || !self.drop_empty)
+                        .map(|s| match (s.is_empty(), &self.empty_as) {
+                            (true, Some(placeholder)) => placeholder.clone(),
+                            _ => s,
+                        })
+                        .collect(),
+                ),
+            ),
+            Output::Multiple(x) => Some(self.join(x)),
+            Output::Single(x) if x.is_empty() => self.empty(),
+            Output::Single(x) => Some(self.quote(x)),
+            Output::Skip => None,
+        }
+    }
+
+    // the pieces of one result, each on its own line, or on one line with --output-delim
+    fn join(&self, pieces: Vec<String>) -> String {
+        match &self.output_delim {
+            Some(delim) => pieces
+                .into_iter()
+                .map(|piece| self.quote(piece))
+                .collect::<Vec<_>>()
+                .join(delim),
+            None => pieces.join("\n"),
+        }
+    }
+
+    // a field quoted as --quote-output says, if it needs to be
+    fn quote(&self, field: String) -> String {
+        match (self.quote_output, &self.output_delim) {
+            (Some(QuoteOutput::Csv), Some(delim)) => csv_field(field, delim),
+            _ => field,
+        }
+    }
+
+    // tells the user what happened to a line, at --verbose `level` and above
+    fn report(&self, level: u8, source_line: usize, what: &str, line: &str) {
+        if self.verbose >= level {
+            eprintln!("{}:{source_line}: {what}: {line:?}", self.source);
+        }
+    }
+
+    fn placeholders(&self) -> bool {
+        self.drop_empty || self.empty_as.is_some()
+    }
+
+    // what to write for an empty or unmatched result
+    fn empty(&self) -> Option<String> {
+        match &self.empty_as {
+            _ if self.drop_empty => None,
+            Some(placeholder) => Some(placeholder.clone()),
+            None => Some(String::new()),
+        }
+    }
+}
+
+fn csv_field(field: String, delim: &str) -> String {
+    match field.contains(delim) || field.contains(['"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field,
+    }
+}
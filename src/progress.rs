@@ -0,0 +1,108 @@
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::stats;
+
+// how often the bar is redrawn
+const INTERVAL: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 30;
+
+/// The number of bytes the inputs hold, if known: the sizes of the input files, or of stdin when
+/// it is redirected from a file rather than a pipe.
+pub fn total(inputs: &[PathBuf]) -> Option<u64> {
+    let size = |path| {
+        fs::metadata(path)
+            .ok()
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+    };
+    match inputs.is_empty() {
+        true => size(&PathBuf::from("/dev/stdin")),
+        false => inputs.iter().map(size).sum(),
+    }
+}
+
+/// A progress bar on stderr (`--progress`), redrawn from its own thread as input is read and
+/// cleared when dropped. Nothing is drawn unless stderr is a terminal, so piped or redirected
+/// output is never touched.
+pub struct Progress {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    pub fn start(total: Option<u64>) -> Self {
+        if !io::stderr().is_terminal() {
+            return Progress {
+                stop: None,
+                thread: None,
+            };
+        }
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let started = Instant::now();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                draw(stats::bytes_read(), total, started.elapsed());
+            }
+            eprint!("\r\x1b[2K");
+        });
+        Progress {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn draw(done: u64, total: Option<u64>, elapsed: Duration) {
+    let rate = done as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    let line = match total {
+        Some(total) if total > 0 => {
+            let fraction = (done as f64 / total as f64).min(1.0);
+            let filled = (fraction * BAR_WIDTH as f64) as usize;
+            let eta = match done {
+                0 => "--:--".to_string(),
+                _ => clock(total.saturating_sub(done) as f64 / rate),
+            };
+            format!(
+                "[{}{}] {:>3}% {} / {}  {}/s  ETA {eta}",
+                "#".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled),
+                (fraction * 100.0) as u32,
+                stats::size(done as f64),
+                stats::size(total as f64),
+                stats::size(rate),
+            )
+        }
+        _ => format!(
+            "{} read  {}/s  {}",
+            stats::size(done as f64),
+            stats::size(rate),
+            clock(elapsed.as_secs_f64()),
+        ),
+    };
+    let mut stderr = io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[2K{line}");
+    let _ = stderr.flush();
+}
+
+// a duration as minutes and seconds, or hours too when it is that long
+fn clock(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{hours}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+    }
+}
@@ -0,0 +1,556 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, Read, Write};
+use std::iter;
+use std::string::FromUtf8Error;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+use encoding_rs::{EncoderResult, Encoding, UTF_8, UTF_16BE, UTF_16LE};
+
+use crate::cli::Args;
+
+/// What happens to input that isn't valid UTF-8.
+#[derive(Clone, Copy, Default)]
+pub enum Decoding {
+    /// Stop with an error
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD (`--lossy`)
+    Lossy,
+    /// Carry invalid bytes through to the output unchanged (`--binary`)
+    Binary,
+}
+
+/// The line endings written to the output. Records never hold a `\r` before a newline, whatever
+/// the input's line endings were.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum LineEnding {
+    /// The line ending of the input, as found at the end of its first line
+    #[default]
+    Keep,
+    /// Unix line endings (\n)
+    Lf,
+    /// Windows line endings (\r\n)
+    Crlf,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Settings {
+    decoding: Decoding,
+    line_ending: LineEnding,
+    strip_bom: bool,
+    encoding: Option<&'static Encoding>,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+// whether the first line of the input being read ended with \r\n
+static INPUT_CRLF: AtomicBool = AtomicBool::new(false);
+
+// whether the input being read started with a byte order mark
+static INPUT_BOM: AtomicBool = AtomicBool::new(false);
+
+/// Sets the encoding of the input, how invalid UTF-8 is handled on input and output, which line
+/// endings are written, and whether a byte order mark is written back. Called once per run,
+/// before anything is read.
+pub fn configure(args: &Args) {
+    let _ = SETTINGS.set(Settings {
+        decoding: Decoding::new(args.lossy, args.binary),
+        line_ending: args.crlf,
+        strip_bom: args.strip_bom,
+        encoding: args.from_encoding,
+    });
+}
+
+/// The configured handling of invalid UTF-8.
+pub fn decoding() -> Decoding {
+    SETTINGS.get().copied().unwrap_or_default().decoding
+}
+
+// whether output lines end with \r\n
+fn crlf() -> bool {
+    match SETTINGS.get().copied().unwrap_or_default().line_ending {
+        LineEnding::Keep => INPUT_CRLF.load(Ordering::Relaxed),
+        LineEnding::Lf => false,
+        LineEnding::Crlf => true,
+    }
+}
+
+// records the input's line ending from text holding its first line ending
+fn note_line_ending(bytes: &[u8]) {
+    if let Some(i) = bytes.iter().position(|&b| b == b'\n') {
+        INPUT_CRLF.store(bytes[..i].ends_with(b"\r"), Ordering::Relaxed);
+    }
+}
+
+// converts an input to UTF-8 from the configured encoding, or the one its byte order mark names,
+// taking the mark off so operations never see it
+fn open<'a>(mut reader: impl BufRead + 'a) -> Box<dyn BufRead + 'a> {
+    let configured = SETTINGS.get().copied().unwrap_or_default().encoding;
+    let start = reader
+        .fill_buf()
+        .map(|b| b.get(..3).unwrap_or(b).to_vec())
+        .unwrap_or_default();
+    let marked = match (start.as_slice(), configured) {
+        ([0xEF, 0xBB, 0xBF], None) => Some((3, UTF_8)),
+        ([0xEF, 0xBB, 0xBF], Some(e)) if e == UTF_8 => Some((3, UTF_8)),
+        ([0xFF, 0xFE, ..], None) => Some((2, UTF_16LE)),
+        ([0xFE, 0xFF, ..], None) => Some((2, UTF_16BE)),
+        ([0xFF, 0xFE, ..] | [0xFE, 0xFF, ..], Some(e)) if e == UTF_16LE || e == UTF_16BE => {
+            Some((2, if start[0] == 0xFF { UTF_16LE } else { UTF_16BE }))
+        }
+        _ => None,
+    };
+    if let Some((len, _)) = marked {
+        INPUT_BOM.store(true, Ordering::Relaxed);
+        reader.consume(len);
+    }
+    match marked.map(|(_, e)| e).or(configured) {
+        Some(encoding) if encoding != UTF_8 => transcode(reader, encoding),
+        _ => Box::new(reader),
+    }
+}
+
+// turns \r\n line endings inside a record into \n
+fn normalize(text: String) -> String {
+    match text.contains("\r\n") {
+        true => text.replace("\r\n", "\n"),
+        false => text,
+    }
+}
+
+// invalid bytes are held as these private-use characters (as in OPTU-8) until they are written.
+// Input that has one of these characters itself holds it as its bytes, so the character can't be
+// mistaken for a held byte and comes out unchanged.
+const BINARY_BASE: u32 = 0xEF00;
+
+// whether `c` is one of the characters bytes are held as
+fn carrier(c: char) -> bool {
+    (BINARY_BASE + 0x80..=BINARY_BASE + 0xFF).contains(&(c as u32))
+}
+
+// `bytes` as text, with its invalid bytes and the bytes of its carrier characters held as carriers
+fn carry(bytes: &[u8]) -> String {
+    let held = |&b: &u8| char::from_u32(BINARY_BASE + b as u32);
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match carrier(c) {
+                true => text.extend(
+                    c.encode_utf8(&mut [0; 4])
+                        .as_bytes()
+                        .iter()
+                        .filter_map(held),
+                ),
+                false => text.push(c),
+            }
+        }
+        text.extend(chunk.invalid().iter().filter_map(held));
+    }
+    text
+}
+
+impl Decoding {
+    pub fn new(lossy: bool, binary: bool) -> Self {
+        match (lossy, binary) {
+            (true, _) => Decoding::Lossy,
+            (_, true) => Decoding::Binary,
+            _ => Decoding::Strict,
+        }
+    }
+
+    pub fn decode(self, bytes: Vec<u8>) -> Result<String, FromUtf8Error> {
+        let binary = matches!(self, Decoding::Binary);
+        let e = match String::from_utf8(bytes) {
+            Ok(text) if binary && text.contains(carrier) => return Ok(carry(text.as_bytes())),
+            Ok(text) => return Ok(text),
+            Err(e) => e,
+        };
+        match self {
+            Decoding::Strict => Err(e),
+            Decoding::Lossy => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+            Decoding::Binary => Ok(carry(e.as_bytes())),
+        }
+    }
+
+    /// The bytes to write for `text`, restoring the bytes `decode` carried through.
+    pub fn encode(self, text: &str) -> Cow<'_, [u8]> {
+        if !matches!(self, Decoding::Binary) || !text.contains(carrier) {
+            return Cow::Borrowed(text.as_bytes());
+        }
+        let mut bytes = Vec::with_capacity(text.len());
+        for c in text.chars() {
+            match carrier(c) {
+                true => bytes.push((c as u32 - BINARY_BASE) as u8),
+                false => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        Cow::Owned(bytes)
+    }
+}
+
+/// Parses an encoding name such as `latin1`, `utf-16le` or `shift_jis`, for `--from-encoding` and
+/// `--to-encoding`.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes())
+        .or_else(|| Encoding::for_label(label.replace('-', "").as_bytes()))
+        .ok_or_else(|| {
+            format!("unknown encoding '{label}' (try utf-8, latin1, utf-16le or shift_jis)")
+        })
+}
+
+// wraps an input in a reader that converts it from `encoding` to UTF-8
+fn transcode<'a>(reader: impl BufRead + 'a, encoding: &'static Encoding) -> Box<dyn BufRead + 'a> {
+    let reader = encoding_rs_io::DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .build(reader);
+    Box::new(io::BufReader::new(reader))
+}
+
+// decodes input with the configured handling of invalid UTF-8
+fn decode(bytes: Vec<u8>) -> io::Result<String> {
+    decoding().decode(bytes).map_err(|_| invalid_utf8())
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "input is not valid UTF-8 (use --lossy or --binary to read it anyway)",
+    )
+}
+
+// the lines of an input without their line endings, as `BufRead::lines` gives them
+fn lines<'a>(mut reader: impl BufRead + 'a) -> impl Iterator<Item = io::Result<String>> + 'a {
+    let mut buffer = Vec::new();
+    let mut first = true;
+    iter::from_fn(move || {
+        buffer.clear();
+        match reader.read_until(b'\n', &mut buffer) {
+            Ok(0) => None,
+            Ok(_) => {
+                if std::mem::take(&mut first) {
+                    note_line_ending(&buffer);
+                }
+                if buffer.ends_with(b"\n") {
+                    buffer.pop();
+                    if buffer.ends_with(b"\r") {
+                        buffer.pop();
+                    }
+                }
+                Some(decode(std::mem::take(&mut buffer)))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// The lines of an input held in memory (`--mmap`), borrowed from it rather than copied, or
+/// `None` when it has to be decoded or split some other way and is read through
+/// `Separator::records` instead.
+pub fn borrowed_lines<'a>(
+    separator: &Separator,
+    input: &'a [u8],
+) -> Option<impl Iterator<Item = io::Result<&'a str>> + 'a> {
+    let settings = SETTINGS.get().copied().unwrap_or_default();
+    let plain = matches!(separator, Separator::Line)
+        && matches!(settings.decoding, Decoding::Strict)
+        && settings.encoding.is_none_or(|e| e == UTF_8)
+        && !input.starts_with(&[0xFF, 0xFE])
+        && !input.starts_with(&[0xFE, 0xFF]);
+    if !plain {
+        return None;
+    }
+
+    let input = match input.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        Some(rest) => {
+            INPUT_BOM.store(true, Ordering::Relaxed);
+            rest
+        }
+        None => {
+            INPUT_BOM.store(false, Ordering::Relaxed);
+            input
+        }
+    };
+    INPUT_CRLF.store(false, Ordering::Relaxed);
+    note_line_ending(input);
+    let mut rest = input;
+    Some(iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let line = match memchr::memchr(b'\n', rest) {
+            Some(i) => {
+                let line = &rest[..i];
+                rest = &rest[i + 1..];
+                line.strip_suffix(b"\r").unwrap_or(line)
+            }
+            None => std::mem::take(&mut rest),
+        };
+        Some(std::str::from_utf8(line).map_err(|_| invalid_utf8()))
+    }))
+}
+
+/// How an input is split into the records the operation is applied to.
+pub enum Separator {
+    Line,
+    /// The whole input is one record (`--slurp`)
+    Input,
+    /// Records end at each occurrence of the text (`--record-sep`)
+    Text(String),
+    /// Records are separated by one or more blank lines (`--paragraph`)
+    Paragraph,
+}
+
+impl Separator {
+    pub fn new(args: &Args) -> Self {
+        match &args.record_sep {
+            _ if args.slurp => Separator::Input,
+            _ if args.paragraph => Separator::Paragraph,
+            Some(sep) => Separator::Text(sep.clone()),
+            None => Separator::Line,
+        }
+    }
+
+    /// Whether a record can hold more than one line.
+    pub fn multiline(&self) -> bool {
+        !matches!(self, Separator::Line)
+    }
+
+    /// Splits an input into records. The final newline of an input never belongs to its last record.
+    pub fn records<'a>(
+        &self,
+        reader: impl BufRead + 'a,
+    ) -> Box<dyn Iterator<Item = io::Result<String>> + 'a> {
+        INPUT_CRLF.store(false, Ordering::Relaxed);
+        INPUT_BOM.store(false, Ordering::Relaxed);
+        let mut reader = open(reader);
+        match self {
+            Separator::Line => Box::new(lines(reader)),
+            Separator::Input => {
+                let mut bytes = Vec::new();
+                let read = reader.read_to_end(&mut bytes).and_then(|_| {
+                    note_line_ending(&bytes);
+                    decode(bytes)
+                });
+                let input = match read {
+                    Ok(input) => normalize(input),
+                    Err(e) => return Box::new(iter::once(Err(e))),
+                };
+                // an empty input holds no record at all
+                match chomp(&input) {
+                    _ if input.is_empty() => Box::new(iter::empty()),
+                    record => Box::new(iter::once(Ok(record.to_string()))),
+                }
+            }
+            Separator::Text(sep) => {
+                let sep = sep.clone();
+                let mut buffer = String::new();
+                let mut line = Vec::new();
+                let mut first = true;
+                let mut done = false;
+                Box::new(iter::from_fn(move || {
+                    loop {
+                        if let Some(i) = buffer.find(&sep) {
+                            let record = buffer[..i].to_string();
+                            buffer.drain(..i + sep.len());
+                            return Some(Ok(record));
+                        }
+                        if done {
+                            return None;
+                        }
+                        line.clear();
+                        match reader.read_until(b'\n', &mut line) {
+                            Ok(0) => {
+                                done = true;
+                                let record = chomp(&buffer).to_string();
+                                buffer.clear();
+                                return (!record.is_empty()).then_some(Ok(record));
+                            }
+                            Ok(_) => {
+                                if std::mem::take(&mut first) {
+                                    note_line_ending(&line);
+                                }
+                                match decode(std::mem::take(&mut line)) {
+                                    Ok(text) => buffer.push_str(&normalize(text)),
+                                    Err(e) => return Some(Err(e)),
+                                }
+                            }
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+                }))
+            }
+            Separator::Paragraph => {
+                let mut lines = lines(reader);
+                Box::new(iter::from_fn(move || {
+                    let mut paragraph: Vec<String> = Vec::new();
+                    for line in lines.by_ref() {
+                        match line {
+                            Ok(line) if line.trim().is_empty() && paragraph.is_empty() => {}
+                            Ok(line) if line.trim().is_empty() => break,
+                            Ok(line) => paragraph.push(line),
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+                    (!paragraph.is_empty()).then(|| Ok(paragraph.join("\n")))
+                }))
+            }
+        }
+    }
+
+    // what goes between records on output
+    fn joiner(&self) -> &str {
+        match self {
+            Separator::Line | Separator::Input => "\n",
+            Separator::Text(sep) => sep,
+            Separator::Paragraph => "\n\n",
+        }
+    }
+}
+
+/// Reads an input as pieces of text of about `size` bytes, ignoring the separator, for operations
+/// that stream lines too long to hold in memory.
+pub fn chunks<'a>(
+    reader: impl BufRead + 'a,
+    size: usize,
+) -> impl Iterator<Item = io::Result<String>> + 'a {
+    INPUT_CRLF.store(false, Ordering::Relaxed);
+    INPUT_BOM.store(false, Ordering::Relaxed);
+    let mut reader = open(reader);
+    let mut pending = Vec::new();
+    let mut first = true;
+    let mut last = None;
+    iter::from_fn(move || {
+        let mut bytes = std::mem::take(&mut pending);
+        let read = (&mut reader).take(size as u64).read_to_end(&mut bytes);
+        match read {
+            Err(e) => return Some(Err(e)),
+            Ok(0) if bytes.is_empty() => return None,
+            Ok(0) => {}
+            // a character cut off at the end of the chunk waits for the rest of it
+            Ok(_) => {
+                if let Err(e) = std::str::from_utf8(&bytes)
+                    && e.error_len().is_none()
+                {
+                    pending = bytes.split_off(e.valid_up_to());
+                }
+            }
+        }
+        // the \r of the first line ending may have come at the end of the chunk before
+        if let Some(i) = bytes.iter().position(|&b| b == b'\n').filter(|_| first) {
+            first = false;
+            let cr = i
+                .checked_sub(1)
+                .map_or(last == Some(b'\r'), |j| bytes[j] == b'\r');
+            INPUT_CRLF.store(cr, Ordering::Relaxed);
+        }
+        last = bytes.last().copied().or(last);
+        Some(decode(bytes))
+    })
+}
+
+// strips the final newline from an input
+fn chomp(input: &str) -> &str {
+    let input = input.strip_suffix('\n').unwrap_or(input);
+    input.strip_suffix('\r').unwrap_or(input)
+}
+
+/// Writes records back out with the separator they were read with between them, ending with a newline.
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    joiner: String,
+    started: bool,
+    encoder: Option<(&'static Encoding, encoding_rs::Encoder)>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// A writer of UTF-8, or of `encoding` when given.
+    pub fn new(writer: W, separator: &Separator, encoding: Option<&'static Encoding>) -> Self {
+        RecordWriter {
+            writer,
+            joiner: separator.joiner().to_string(),
+            started: false,
+            encoder: encoding
+                .filter(|&e| e != UTF_8)
+                .map(|e| (e, e.new_encoder())),
+        }
+    }
+
+    pub fn write(&mut self, record: &str) -> io::Result<()> {
+        self.start()?;
+        self.put(record)
+    }
+
+    /// Starts a record whose text is then given in parts with `append`, for records too long to
+    /// hold at once.
+    pub fn start(&mut self) -> io::Result<()> {
+        let settings = SETTINGS.get().copied().unwrap_or_default();
+        if !self.started && INPUT_BOM.load(Ordering::Relaxed) && !settings.strip_bom {
+            self.put("\u{feff}")?;
+        }
+        if self.started {
+            let joiner = std::mem::take(&mut self.joiner);
+            self.put(&joiner)?;
+            self.joiner = joiner;
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    pub fn append(&mut self, text: &str) -> io::Result<()> {
+        self.put(text)
+    }
+
+    /// Ends the output and hands back the underlying writer.
+    pub fn end(mut self) -> io::Result<W> {
+        if self.started {
+            self.put("\n")?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn put(&mut self, text: &str) -> io::Result<()> {
+        let text = match crlf() {
+            true => Cow::Owned(text.replace('\n', "\r\n")),
+            false => Cow::Borrowed(text),
+        };
+        let text = text.as_ref();
+        let Some((encoding, encoder)) = &mut self.encoder else {
+            return self.writer.write_all(&decoding().encode(text));
+        };
+        let encoding = *encoding;
+        // encoding_rs only decodes UTF-16, so it is written here
+        if encoding == UTF_16LE || encoding == UTF_16BE {
+            let bytes: Vec<u8> = text
+                .encode_utf16()
+                .flat_map(|unit| match encoding == UTF_16BE {
+                    true => unit.to_be_bytes(),
+                    false => unit.to_le_bytes(),
+                })
+                .collect();
+            return self.writer.write_all(&bytes);
+        }
+
+        let mut bytes = Vec::with_capacity(text.len());
+        let mut rest = text;
+        loop {
+            bytes.reserve(rest.len() * 2 + 16);
+            let (result, read) =
+                encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut bytes, false);
+            rest = &rest[read..];
+            match result {
+                EncoderResult::InputEmpty => break,
+                EncoderResult::OutputFull => {}
+                EncoderResult::Unmappable(c) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("'{c}' cannot be written in {}", encoding.name()),
+                    ));
+                }
+            }
+        }
+        self.writer.write_all(&bytes)
+    }
+}
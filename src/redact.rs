@@ -0,0 +1,112 @@
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use regex::Regex;
+
+use crate::pattern::Pattern;
+
+/// Kinds of sensitive text `Redact` knows how to find.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RedactPreset {
+    /// Email addresses, such as jane@example.com
+    Email,
+    /// IPv4 addresses, such as 192.168.0.1
+    Ipv4,
+    /// US social security numbers, such as 123-45-6789
+    Ssn,
+    /// Payment card numbers of 13 to 19 digits, possibly grouped by spaces or dashes, that pass
+    /// the Luhn check
+    CreditCard,
+}
+
+impl RedactPreset {
+    fn regex(self) -> &'static Regex {
+        static EMAIL: OnceLock<Regex> = OnceLock::new();
+        static IPV4: OnceLock<Regex> = OnceLock::new();
+        static SSN: OnceLock<Regex> = OnceLock::new();
+        static CREDIT_CARD: OnceLock<Regex> = OnceLock::new();
+        let (cell, pattern) = match self {
+            RedactPreset::Email => (
+                &EMAIL,
+                r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}\b",
+            ),
+            RedactPreset::Ipv4 => (
+                &IPV4,
+                r"\b(?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\b",
+            ),
+            RedactPreset::Ssn => (&SSN, r"\b\d{3}-\d{2}-\d{4}\b"),
+            RedactPreset::CreditCard => (&CREDIT_CARD, r"\b\d(?:[ -]?\d){12,18}\b"),
+        };
+        cell.get_or_init(|| Regex::new(pattern).expect("the preset patterns are valid"))
+    }
+
+    // whether a match of the preset's pattern is really what it looks for
+    fn accepts(self, text: &str) -> bool {
+        match self {
+            RedactPreset::CreditCard => luhn(text),
+            _ => true,
+        }
+    }
+}
+
+// the checksum card numbers end with, which random runs of digits rarely pass
+fn luhn(text: &str) -> bool {
+    let sum: u32 = text
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, digit)| match (i % 2 == 1, digit * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => digit,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// The ranges of `input` matched by any of the presets or patterns, in order, with overlapping
+/// ranges merged.
+pub fn sensitive(presets: &[RedactPreset], patterns: &[Pattern], input: &str) -> Vec<Range<usize>> {
+    let mut found: Vec<Range<usize>> = presets
+        .iter()
+        .flat_map(|preset| {
+            preset
+                .regex()
+                .find_iter(input)
+                .filter(|m| preset.accepts(m.as_str()))
+                .map(|m| m.range())
+        })
+        .chain(patterns.iter().flat_map(|pattern| pattern.find_iter(input)))
+        .filter(|range| !range.is_empty())
+        .collect();
+    found.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in found {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Replaces the sensitive parts of `input` with what `replace` makes of them.
+pub fn redact(
+    presets: &[RedactPreset],
+    patterns: &[Pattern],
+    replace: impl Fn(&str) -> String,
+    input: &str,
+) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut last = 0;
+    for range in sensitive(presets, patterns, input) {
+        output.push_str(&input[last..range.start]);
+        output.push_str(&replace(&input[range.clone()]));
+        last = range.end;
+    }
+    output.push_str(&input[last..]);
+    output
+}
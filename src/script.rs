@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use rhai::{AST, Array, Dynamic, Engine, Scope};
+
+use crate::cli::Output;
+
+/// A Rhai script, compiled once and evaluated for each line.
+#[derive(Clone)]
+pub struct Script {
+    engine: Arc<Engine>,
+    ast: AST,
+    source: String,
+}
+
+impl Script {
+    pub fn compile(code: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        // printing to stdout would mix with the output
+        engine.on_print(|text| eprintln!("{text}"));
+        let ast = engine.compile(code).map_err(|e| e.to_string())?;
+        Ok(Script {
+            engine: Arc::new(engine),
+            ast,
+            source: code.to_string(),
+        })
+    }
+
+    /// The script as given on the command line.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluates the script with `line`, its `fields`, its number `n` and the `file` it was read
+    /// from in scope. A string replaces the line, an array replaces it with a line per element,
+    /// false drops it, and true or nothing keeps it, as the script may have changed it.
+    pub fn run(
+        &self,
+        line: &str,
+        fields: &[&str],
+        (n, file): (usize, &str),
+    ) -> Result<Output, String> {
+        let mut scope = Scope::new();
+        scope.push("line", line.to_string());
+        scope.push(
+            "fields",
+            fields
+                .iter()
+                .map(|field| Dynamic::from(field.to_string()))
+                .collect::<Array>(),
+        );
+        scope.push("n", n as i64);
+        scope.push("file", file.to_string());
+
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+        let line = || {
+            scope
+                .get_value::<Dynamic>("line")
+                .map(|line| line.to_string())
+                .unwrap_or_default()
+        };
+        Ok(if result.is_unit() {
+            Output::Single(line())
+        } else if let Ok(keep) = result.as_bool() {
+            match keep {
+                true => Output::Single(line()),
+                false => Output::Skip,
+            }
+        } else if result.is_array() {
+            match result.into_array().unwrap_or_default() {
+                lines if lines.is_empty() => Output::Skip,
+                lines => Output::Multiple(lines.iter().map(Dynamic::to_string).collect()),
+            }
+        } else {
+            Output::Single(result.to_string())
+        })
+    }
+}
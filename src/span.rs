@@ -0,0 +1,79 @@
+use std::ops::Range;
+
+use clap::ValueEnum;
+
+/// What to do with a line whose resolved range doesn't fit it.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum OnInvalid {
+    /// Report the offending line and stop
+    #[default]
+    Error,
+    /// Drop the line
+    Skip,
+    /// Write the line unchanged
+    Passthrough,
+    /// Pull the range back inside the line and apply the operation anyway
+    Clamp,
+}
+
+/// The byte range between the two anchors of a range operation, as resolved on one line.
+///
+/// Positions are signed because offsets can move them before the start of the line. A missing
+/// start means the start pattern wasn't found, and a missing end means the end of the line.
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    /// Where the match of the start pattern ends, when the end pattern mustn't begin inside it
+    pub start_match_end: Option<i64>,
+}
+
+impl Span {
+    pub fn new(start: i64, end: Option<i64>) -> Self {
+        Self {
+            start: Some(start),
+            end,
+            start_match_end: None,
+        }
+    }
+
+    /// Explains why the span doesn't fit `input`, naming the positions it resolved to.
+    pub fn check(&self, input: &str) -> Result<(), String> {
+        let len = input.len() as i64;
+        let (start, end) = (self.start.unwrap_or(0), self.end.unwrap_or(len));
+        // a range running to the end of the line is written open, as it was given
+        let range = match self.end {
+            Some(_) => format!("{start}..{end}"),
+            None => format!("{start}.."),
+        };
+
+        if start > end && self.end.is_some() {
+            Err(format!(
+                "range {range} is inverted (end comes before start)"
+            ))
+        } else if let Some(match_end) = self.start_match_end.filter(|&m| end < m) {
+            Err(format!(
+                "end pattern at {end} overlaps the start pattern at {start}..{match_end}"
+            ))
+        } else if start < 0 || start > len || end > len {
+            Err(format!("range {range} is outside the line (0..{len})"))
+        } else if let Some(i) = [start, end]
+            .into_iter()
+            .find(|&i| !input.is_char_boundary(i as usize))
+        {
+            Err(format!(
+                "position {i} of range {range} falls inside a character"
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The span pulled back inside `input`, with an inverted span collapsing onto its start.
+    pub fn clamp(&self, input: &str) -> Range<usize> {
+        let len = input.len() as i64;
+        let start = input.floor_char_boundary(self.start.unwrap_or(0).clamp(0, len) as usize);
+        let end = self.end.unwrap_or(len).clamp(start as i64, len) as usize;
+        start..input.floor_char_boundary(end).max(start)
+    }
+}
@@ -0,0 +1,95 @@
+use std::io::{self, BufRead, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// running totals for the whole run, reported by `--stats`
+static LINES: AtomicU64 = AtomicU64::new(0);
+static MODIFIED: AtomicU64 = AtomicU64::new(0);
+static BYTES_IN: AtomicU64 = AtomicU64::new(0);
+static BYTES_OUT: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a processed line, and whether the operation changed or dropped it.
+pub fn line(modified: bool) {
+    LINES.fetch_add(1, Ordering::Relaxed);
+    if modified {
+        MODIFIED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts input that was read without going through a `Counted` reader.
+pub fn read(bytes: usize) {
+    BYTES_IN.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Counts output that was written without going through a `Counted` writer.
+pub fn written(bytes: usize) {
+    BYTES_OUT.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// How much input has been read so far.
+pub fn bytes_read() -> u64 {
+    BYTES_IN.load(Ordering::Relaxed)
+}
+
+/// Prints the totals to stderr.
+pub fn report(elapsed: Duration) {
+    let bytes_in = BYTES_IN.load(Ordering::Relaxed);
+    let seconds = elapsed.as_secs_f64();
+    eprintln!(
+        "str: {} lines, {} modified; {} in, {} out; {seconds:.2}s ({}/s)",
+        LINES.load(Ordering::Relaxed),
+        MODIFIED.load(Ordering::Relaxed),
+        size(bytes_in as f64),
+        size(BYTES_OUT.load(Ordering::Relaxed) as f64),
+        size(bytes_in as f64 / seconds.max(f64::EPSILON)),
+    );
+}
+
+/// A byte count in the largest unit that keeps it at 1 or more.
+pub fn size(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes:.0} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+/// A reader or writer that adds the bytes passing through it to the totals.
+pub struct Counted<T>(pub T);
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        read(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        read(amount);
+        self.0.consume(amount);
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        written(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
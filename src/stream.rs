@@ -0,0 +1,160 @@
+use std::io::{BufRead, Write};
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use anyhow::{Context, Result, bail};
+
+use crate::cli::{Operation, PatternOperation};
+use crate::records::{self, RecordWriter};
+use crate::stats;
+
+// how much input is read at a time
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What is done with each match on a streamed line.
+enum Action<'a> {
+    /// Write this text in its place
+    Replace(&'a str),
+    /// End the output record there, as `split-at-pat` does
+    Split,
+}
+
+/// Applies `replace`, `remove` or `split-at-pat` to lines a chunk at a time, so lines of any length
+/// are processed in bounded memory (`--chunked`).
+pub struct Streamer<'a> {
+    automaton: AhoCorasick,
+    action: Action<'a>,
+    limit: Option<usize>,
+    // the longest pattern, which bounds how much of a line must be held back in case a match
+    // continues into the next chunk
+    longest: usize,
+}
+
+impl<'a> Streamer<'a> {
+    pub fn new(operation: &'a Operation) -> Result<Self> {
+        let (patterns, action, number) = match operation {
+            Operation::PatternBased(PatternOperation::Replace {
+                patterns,
+                with,
+                number,
+                ..
+            }) => (patterns.clone(), Action::Replace(with), *number),
+            Operation::PatternBased(PatternOperation::Remove {
+                pattern,
+                patterns,
+                number,
+                ..
+            }) => (
+                pattern.iter().chain(patterns).cloned().collect(),
+                Action::Replace(""),
+                *number,
+            ),
+            Operation::PatternBased(PatternOperation::SplitAtPat {
+                pattern,
+                number,
+                respect_quotes: false,
+            }) => {
+                // `split-at-pat` counts pieces rather than splits
+                let number = number.map(|n| if n > 0 { n - 1 } else { n });
+                (vec![pattern.clone()], Action::Split, number)
+            }
+            Operation::PatternBased(PatternOperation::SplitAtPat { .. }) => {
+                bail!("--chunked can't be used with --respect-quotes")
+            }
+            _ => bail!("--chunked only works with replace, remove and split-at-pat"),
+        };
+        let limit = match number {
+            None => None,
+            Some(n) if n < 0 => bail!("--chunked can't count matches from the end of a line"),
+            Some(n) => Some(n as usize),
+        };
+
+        let sources: Vec<&str> = patterns.iter().map(|p| p.source()).collect();
+        if sources.iter().any(|s| s.is_empty() || s.contains('\n')) {
+            bail!("--chunked needs patterns that are neither empty nor span lines");
+        }
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(&sources)
+            .context("failed to build the pattern matcher")?;
+        Ok(Streamer {
+            automaton,
+            action,
+            limit,
+            longest: sources.iter().map(|s| s.len()).max().unwrap_or(0),
+        })
+    }
+
+    /// Streams one input through to `writer`, a line at a time, returning whether any line
+    /// matched.
+    pub fn run(&self, reader: impl BufRead, writer: &mut RecordWriter<impl Write>) -> Result<bool> {
+        let mut buffer = String::new();
+        let mut line = Line::default();
+        let mut matched = false;
+        for chunk in records::chunks(reader, CHUNK_SIZE) {
+            buffer.push_str(&chunk?);
+            while let Some(end) = buffer.find('\n') {
+                let text = buffer[..end].strip_suffix('\r').unwrap_or(&buffer[..end]);
+                self.apply(text, text.len(), &mut line, writer)?;
+                stats::line(line.matches > 0);
+                matched |= line.matches > 0;
+                line = Line::default();
+                buffer.drain(..=end);
+            }
+            if buffer.is_empty() {
+                continue;
+            }
+            // hold back what a match might still continue from, and a \r that may end the line
+            let mut safe =
+                buffer.floor_char_boundary(buffer.len().saturating_sub(self.longest - 1));
+            if buffer[..safe].ends_with('\r') {
+                safe -= 1;
+            }
+            let used = self.apply(&buffer, safe, &mut line, writer)?;
+            buffer.drain(..used);
+        }
+        if !buffer.is_empty() || line.started {
+            self.apply(&buffer, buffer.len(), &mut line, writer)?;
+            stats::line(line.matches > 0);
+            matched |= line.matches > 0;
+        }
+        Ok(matched)
+    }
+
+    // writes out `text` up to `safe`, acting on matches that start before it, and returns how much
+    // of `text` was used
+    fn apply(
+        &self,
+        text: &str,
+        safe: usize,
+        line: &mut Line,
+        writer: &mut RecordWriter<impl Write>,
+    ) -> Result<usize> {
+        if !line.started {
+            writer.start()?;
+            line.started = true;
+        }
+        let mut used = 0;
+        for m in self.automaton.find_iter(text) {
+            if m.start() >= safe || self.limit.is_some_and(|limit| line.matches >= limit) {
+                break;
+            }
+            writer.append(&text[used..m.start()])?;
+            match self.action {
+                Action::Replace(with) => writer.append(with)?,
+                Action::Split => writer.start()?,
+            }
+            line.matches += 1;
+            used = m.end();
+        }
+        let end = safe.max(used);
+        writer.append(&text[used..end])?;
+        Ok(end)
+    }
+}
+
+// progress through the line being streamed
+#[derive(Default)]
+struct Line {
+    started: bool,
+    matches: usize,
+}
@@ -0,0 +1,161 @@
+use std::fmt::Write;
+
+/// A value substituted into a template.
+pub enum Value<'a> {
+    Number(usize),
+    Text(&'a str),
+}
+
+#[derive(Clone)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// How a placeholder is padded, following Rust's `[[fill]align][0][width]` format spec.
+#[derive(Clone)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    width: usize,
+}
+
+#[derive(Clone)]
+enum Piece {
+    Literal(String),
+    Placeholder(String, Spec),
+}
+
+/// Text with `{name}` or `{name:spec}` placeholders, such as `{n:>6}  `. Braces are escaped by
+/// doubling them.
+#[derive(Clone)]
+pub struct Template {
+    pieces: Vec<Piece>,
+    source: String,
+}
+
+impl Template {
+    /// Parses a template, rejecting placeholders other than `names`. A name of `#` allows any
+    /// field number.
+    pub fn parse(s: &str, names: &[&str]) -> Result<Self, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => {
+                    return Err(format!(
+                        "unmatched '}}' in '{s}' (write '}}}}' for a literal brace)"
+                    ));
+                }
+                '{' => {
+                    let mut body = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => body.push(c),
+                            None => return Err(format!("unterminated placeholder in '{s}'")),
+                        }
+                    }
+                    let (name, spec) = body.split_once(':').unwrap_or((&body, ""));
+                    let known = names.contains(&name)
+                        || (names.contains(&"#") && name.parse::<usize>().is_ok());
+                    if !known {
+                        return Err(format!(
+                            "unknown placeholder '{{{name}}}' in '{s}' (expected one of {})",
+                            names
+                                .iter()
+                                .map(|&n| if n == "#" { "a field number" } else { n })
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Placeholder(name.to_string(), parse_spec(spec)?));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Template {
+            pieces,
+            source: s.to_string(),
+        })
+    }
+
+    /// The template as given on the command line.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Fills in every placeholder with the value `lookup` gives for its name, or nothing if it
+    /// gives none.
+    pub fn render<'a>(&self, lookup: impl Fn(&str) -> Option<Value<'a>>) -> String {
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(text) => out.push_str(text),
+                Piece::Placeholder(name, spec) => {
+                    let (text, default) = match lookup(name) {
+                        Some(Value::Number(n)) => (n.to_string(), Align::Right),
+                        Some(Value::Text(t)) => (t.to_string(), Align::Left),
+                        None => (String::new(), Align::Left),
+                    };
+                    let padding = spec.width.saturating_sub(text.chars().count());
+                    let (before, after) = match spec.align.as_ref().unwrap_or(&default) {
+                        Align::Left => (0, padding),
+                        Align::Right => (padding, 0),
+                        Align::Center => (padding / 2, padding - padding / 2),
+                    };
+                    let fill = |n: usize| spec.fill.to_string().repeat(n);
+                    let _ = write!(out, "{}{text}{}", fill(before), fill(after));
+                }
+            }
+        }
+        out
+    }
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let align = |c: char| match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    };
+
+    let chars: Vec<char> = spec.chars().collect();
+    let (fill, align, rest) = match chars.as_slice() {
+        [fill, a, ..] if align(*a).is_some() => (*fill, align(*a), &chars[2..]),
+        [a, ..] if align(*a).is_some() => (' ', align(*a), &chars[1..]),
+        _ => (' ', None, &chars[..]),
+    };
+    // a leading zero pads numbers with zeros, like `{n:06}`
+    let (fill, align, rest) = match rest {
+        ['0', rest @ ..] if align.is_none() => ('0', Some(Align::Right), rest),
+        _ => (fill, align, rest),
+    };
+
+    let width: String = rest.iter().collect();
+    let width = match width.is_empty() {
+        true => 0,
+        false => width
+            .parse()
+            .map_err(|_| format!("invalid format spec ':{spec}'"))?,
+    };
+    Ok(Spec { fill, align, width })
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use jiff::fmt::strtime;
+use jiff::tz::TimeZone;
+use jiff::{Timestamp, civil};
+use regex::{Captures, Regex};
+
+// an Apache/nginx access log timestamp
+const CLF: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+/// How `Time` reads or writes a timestamp.
+#[derive(Clone)]
+pub enum TimeFormat {
+    /// Seconds since 1970-01-01 UTC
+    Epoch,
+    /// Milliseconds since 1970-01-01 UTC
+    EpochMs,
+    /// `2024-05-01T12:30:00Z`, or with an offset, a space for the T, or no offset at all
+    Rfc3339,
+    /// The common log format of access logs, `01/May/2024:12:30:00 +0000`
+    Clf,
+    /// A strftime format such as `%Y-%m-%d %H:%M`
+    Custom(String),
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "epoch" => TimeFormat::Epoch,
+            "epoch-ms" => TimeFormat::EpochMs,
+            "rfc3339" | "iso8601" => TimeFormat::Rfc3339,
+            "clf" => TimeFormat::Clf,
+            s if s.contains('%') => TimeFormat::Custom(s.to_string()),
+            _ => {
+                return Err(
+                    "expected epoch, epoch-ms, rfc3339, clf or a strftime format such as %Y-%m-%d"
+                        .to_string(),
+                );
+            }
+        })
+    }
+}
+
+/// Parses a time zone name for `--tz`: an IANA name such as `Europe/Paris`, `UTC`, or `local`.
+impl fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeFormat::Epoch => f.write_str("epoch"),
+            TimeFormat::EpochMs => f.write_str("epoch-ms"),
+            TimeFormat::Rfc3339 => f.write_str("rfc3339"),
+            TimeFormat::Clf => f.write_str("clf"),
+            TimeFormat::Custom(format) => f.write_str(format),
+        }
+    }
+}
+
+pub fn parse_zone(name: &str) -> Result<TimeZone, String> {
+    match name {
+        "local" => Ok(TimeZone::system()),
+        name => TimeZone::get(name).map_err(|e| e.to_string()),
+    }
+}
+
+/// Rewrites the timestamps in `input` from one format to another, in the time zone `zone`.
+///
+/// Without a format to read, every timestamp in a format str knows is found and converted; with a
+/// strftime format, the whole line is read as one timestamp. Timestamps that can't be read are
+/// left as they are, and timestamps without an offset are taken as UTC.
+pub fn convert(from: Option<&TimeFormat>, to: &TimeFormat, zone: &TimeZone, input: &str) -> String {
+    let write = |timestamp: Timestamp| format(timestamp, to, zone);
+    if let Some(TimeFormat::Custom(format)) = from {
+        return parse_custom(format, input.trim()).map_or(input.to_string(), write);
+    }
+    detector()
+        .replace_all(input, |caps: &Captures| {
+            let (kind, text) = caps
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find_map(|(i, m)| Some((i, m?.as_str())))
+                .unwrap_or_default();
+            let wanted = match from {
+                None => true,
+                Some(TimeFormat::Epoch) => kind == 3,
+                Some(TimeFormat::EpochMs) => kind == 4,
+                Some(TimeFormat::Rfc3339) => kind == 1,
+                Some(TimeFormat::Clf) => kind == 2,
+                Some(TimeFormat::Custom(_)) => false,
+            };
+            let parsed = match kind {
+                1 if wanted => parse_rfc3339(text),
+                2 if wanted => strtime::parse(CLF, text)
+                    .and_then(|t| t.to_timestamp())
+                    .ok(),
+                3 if wanted => text
+                    .parse()
+                    .ok()
+                    .and_then(|s| Timestamp::from_second(s).ok()),
+                4 if wanted => text
+                    .parse()
+                    .ok()
+                    .and_then(|ms| Timestamp::from_millisecond(ms).ok()),
+                _ => None,
+            };
+            parsed.map_or(caps[0].to_string(), write)
+        })
+        .into_owned()
+}
+
+// one alternative per format, in the order `convert` numbers their groups
+fn detector() -> &'static Regex {
+    static DETECTOR: OnceLock<Regex> = OnceLock::new();
+    DETECTOR.get_or_init(|| {
+        Regex::new(concat!(
+            r"(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:?\d{2})?)",
+            r"|(\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4})",
+            r"|\b(\d{10})\b",
+            r"|\b(\d{13})\b",
+        ))
+        .expect("the timestamp patterns are valid")
+    })
+}
+
+fn parse_rfc3339(text: &str) -> Option<Timestamp> {
+    text.parse::<Timestamp>().ok().or_else(|| {
+        let naive: civil::DateTime = text.parse().ok()?;
+        naive.to_zoned(TimeZone::UTC).ok().map(|z| z.timestamp())
+    })
+}
+
+fn parse_custom(format: &str, text: &str) -> Option<Timestamp> {
+    let parsed = strtime::parse(format, text).ok()?;
+    parsed.to_timestamp().ok().or_else(|| {
+        let naive = parsed.to_datetime().ok()?;
+        naive.to_zoned(TimeZone::UTC).ok().map(|z| z.timestamp())
+    })
+}
+
+fn format(timestamp: Timestamp, to: &TimeFormat, zone: &TimeZone) -> String {
+    let zoned = timestamp.to_zoned(zone.clone());
+    match to {
+        TimeFormat::Epoch => timestamp.as_second().to_string(),
+        TimeFormat::EpochMs => timestamp.as_millisecond().to_string(),
+        TimeFormat::Rfc3339 if zone == &TimeZone::UTC => timestamp.to_string(),
+        TimeFormat::Rfc3339 => zoned.strftime("%Y-%m-%dT%H:%M:%S%.f%:z").to_string(),
+        TimeFormat::Clf => zoned.strftime(CLF).to_string(),
+        TimeFormat::Custom(format) => zoned.strftime(format).to_string(),
+    }
+}
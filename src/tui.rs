@@ -0,0 +1,184 @@
+use std::io;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::cli::{Args, Operation};
+use crate::pipeline::Pipeline;
+use crate::process::Processor;
+use crate::span::OnInvalid;
+
+const HELP: &str = "type to edit  enter: add stage  up/down: select  ctrl-d: delete stage  ctrl-e: export  esc: quit";
+
+/// The state of `str tui`: sample input, the stages typed so far, and their output on the sample.
+struct Builder<'a> {
+    args: &'a Args,
+    sample: Vec<String>,
+    stages: Vec<String>,
+    selected: usize,
+    output: Result<Vec<String>, String>,
+}
+
+/// Lets the user build a pipeline on `sample` until they quit, returning it as a shell command
+/// if they exported it.
+pub fn run(args: &Args, sample: Vec<String>) -> io::Result<Option<String>> {
+    let mut builder = Builder {
+        args,
+        sample,
+        stages: vec![String::new()],
+        selected: 0,
+        output: Ok(Vec::new()),
+    };
+    builder.update();
+
+    let mut terminal = Terminal::init();
+    builder.event_loop(&mut terminal.0)
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped, however `run` ends.
+struct Terminal(DefaultTerminal);
+
+impl Terminal {
+    fn init() -> Self {
+        Terminal(ratatui::init())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+impl Builder<'_> {
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Option<String>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if control => return Ok(None),
+                KeyCode::Char('e') if control => return Ok(Some(self.command())),
+                KeyCode::Char('d') if control => {
+                    if self.stages.len() > 1 {
+                        self.stages.remove(self.selected);
+                        self.selected = self.selected.min(self.stages.len() - 1);
+                    } else {
+                        self.stages[0].clear();
+                    }
+                }
+                KeyCode::Enter => {
+                    self.selected += 1;
+                    self.stages.insert(self.selected, String::new());
+                }
+                KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Down => self.selected = (self.selected + 1).min(self.stages.len() - 1),
+                KeyCode::Backspace => {
+                    self.stages[self.selected].pop();
+                }
+                KeyCode::Char(c) if !control => self.stages[self.selected].push(c),
+                _ => continue,
+            }
+            self.update();
+        }
+    }
+
+    // the stages that have been typed, skipping blank ones
+    fn typed(&self) -> Vec<&str> {
+        self.stages
+            .iter()
+            .map(|stage| stage.trim())
+            .filter(|stage| !stage.is_empty())
+            .collect()
+    }
+
+    // runs the sample through the stages again
+    fn update(&mut self) {
+        let stages = self.typed();
+        if stages.is_empty() {
+            self.output = Ok(self.sample.clone());
+            return;
+        }
+        self.output = Pipeline::parse(stages).and_then(|pipeline| {
+            let operation = Operation::Stages(pipeline);
+            // a line a range doesn't fit mustn't end the session
+            let mut processor = Processor::new(&operation, self.args)
+                .map_err(|e| e.to_string())?
+                .on_invalid(OnInvalid::Passthrough);
+            let mut output = Vec::new();
+            for line in &self.sample {
+                output.extend(processor.apply(line).map_err(|e| e.to_string())?);
+            }
+            output.extend(processor.finish().map_err(|e| e.to_string())?);
+            Ok(output)
+        });
+    }
+
+    // the pipeline as it would be typed into a shell
+    fn command(&self) -> String {
+        match self.typed().as_slice() {
+            [] => "str".to_string(),
+            stages => format!("str {}", stages.join(" | str ")),
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [panes, stages, help] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(self.stages.len() as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [input, output] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(panes);
+
+        let sample = match self.sample.is_empty() {
+            true => "(no input: pipe a sample into str tui or use --input)".to_string(),
+            false => self.sample.join("\n"),
+        };
+        frame.render_widget(
+            Paragraph::new(sample).block(Block::bordered().title("Input")),
+            input,
+        );
+        let output_pane = match &self.output {
+            Ok(lines) => Paragraph::new(lines.join("\n")).block(Block::bordered().title("Output")),
+            Err(e) => Paragraph::new(e.as_str())
+                .style(Style::new().red())
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title("Output (invalid stage)")),
+        };
+        frame.render_widget(output_pane, output);
+
+        let lines: Vec<Line> = self
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| match i == self.selected {
+                true => Line::from(format!("str {stage}")).bold(),
+                false => Line::from(format!("str {stage}")).dim(),
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Pipeline")),
+            stages,
+        );
+        frame.render_widget(Paragraph::new(HELP).dim(), help);
+
+        let typed = self.stages[self.selected].chars().count() as u16;
+        frame.set_cursor_position(Position::new(
+            stages.x + 5 + typed,
+            stages.y + 1 + self.selected as u16,
+        ));
+    }
+}